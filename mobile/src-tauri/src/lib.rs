@@ -1,53 +1,267 @@
 use get_if_addrs::get_if_addrs;
+use ipnetwork::Ipv4Network;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HostTrust {
+    // identity key is pinned and signed the reply
+    Verified,
+    // never paired (TOFU) or an older server without signatures
+    Unverified,
+    // identity key is pinned but the reply names another key or the signature is wrong:
+    // likely spoofed
+    Mismatch,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DiscoveredHost {
+    // stable across DHCP changes: identity fingerprint, else name, else ip
+    #[serde(default)]
+    pub id: String,
     pub ip: String,
     pub port: u16,
     pub name: Option<String>,
     pub path: Option<String>,
     pub version: Option<String>,
+    pub fingerprint: Option<String>,
+    pub trust: HostTrust,
+    // server identity key fingerprint, pinned on first successful auth (TOFU)
+    #[serde(default)]
+    pub identity: Option<String>,
+}
+
+fn host_id(fingerprint: &Option<String>, name: &Option<String>, ip: &str) -> String {
+    fingerprint.clone().or_else(|| name.clone()).unwrap_or_else(|| ip.to_string())
+}
+
+fn favorites_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join("favorites.json"))
+}
+
+fn load_favorites(path: &Path) -> Vec<DiscoveredHost> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn store_favorites(path: &Path, favorites: &[DiscoveredHost]) -> Result<(), String> {
+    let s = serde_json::to_string_pretty(favorites).map_err(|e| e.to_string())?;
+    std::fs::write(path, s).map_err(|e| e.to_string())
+}
+
+// Upserts by id and moves the host to the front (most recently connected first)
+#[tauri::command]
+fn save_favorite(app: tauri::AppHandle, mut host: DiscoveredHost) -> Result<(), String> {
+    if host.id.is_empty() {
+        host.id = host_id(&host.fingerprint, &host.name, &host.ip);
+    }
+    let path = favorites_path(&app)?;
+    let mut favorites = load_favorites(&path);
+    // a host without a matching favorite at scan time carries no pin; keep the stored one
+    if host.identity.is_none() {
+        host.identity = favorites.iter().find(|f| f.id == host.id).and_then(|f| f.identity.clone());
+    }
+    favorites.retain(|f| f.id != host.id);
+    favorites.insert(0, host);
+    store_favorites(&path, &favorites)
+}
+
+#[tauri::command]
+fn list_favorites(app: tauri::AppHandle) -> Result<Vec<DiscoveredHost>, String> {
+    Ok(load_favorites(&favorites_path(&app)?))
+}
+
+#[tauri::command]
+fn remove_favorite(app: tauri::AppHandle, id: String) -> Result<(), String> {
+    let path = favorites_path(&app)?;
+    let mut favorites = load_favorites(&path);
+    favorites.retain(|f| f.id != id);
+    store_favorites(&path, &favorites)
+}
+
+// The saved favorite a scan reply stands for: same identity fingerprint, else same name, else
+// the most recently used one at that address (favorites are kept newest first)
+fn matching_favorite<'a>(
+    favorites: &'a [DiscoveredHost],
+    fingerprint: &Option<String>,
+    name: &Option<String>,
+    ip: &str,
+) -> Option<&'a DiscoveredHost> {
+    favorites
+        .iter()
+        .find(|f| fingerprint.is_some() && f.fingerprint == *fingerprint)
+        .or_else(|| favorites.iter().find(|f| name.is_some() && f.name == *name))
+        .or_else(|| favorites.iter().find(|f| f.ip == ip))
+}
+
+// pinned: identity fingerprint of the matching favorite, if it ever authenticated
+// pins: identity fingerprint -> identity public key (hex), saved once auth verified the key
+fn check_signature(
+    v: &serde_json::Value,
+    nonce: &str,
+    pinned: Option<&str>,
+    pins: &HashMap<String, String>,
+) -> HostTrust {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let str_field = |k: &str| v.get(k).and_then(|s| s.as_str()).unwrap_or("");
+    let fingerprint = str_field("identity_fingerprint");
+    // a pinned host answering with some other key
+    if pinned.is_some_and(|p| p != fingerprint) {
+        return HostTrust::Mismatch;
+    }
+    let Some(key) = pins.get(fingerprint) else {
+        return HostTrust::Unverified;
+    };
+
+    let key = hex::decode(key)
+        .ok()
+        .and_then(|k| <[u8; 32]>::try_from(k).ok())
+        .and_then(|k| VerifyingKey::from_bytes(&k).ok())
+        .filter(|k| hex::encode(Sha256::digest(k.as_bytes())) == fingerprint);
+    let sig = hex::decode(str_field("sig")).ok().and_then(|s| <[u8; 64]>::try_from(s).ok());
+    let (Some(key), Some(sig)) = (key, sig) else {
+        return HostTrust::Mismatch;
+    };
+    if str_field("nonce") != nonce {
+        return HostTrust::Mismatch;
+    }
+
+    let port = v.get("port").and_then(|p| p.as_u64()).unwrap_or(0);
+    let signed = format!(
+        "FOSSDECK_DISCOVERY_V1:{}|{}|{}|{}|{}|{}",
+        str_field("name"),
+        port,
+        str_field("path"),
+        str_field("version"),
+        nonce,
+        fingerprint
+    );
+    match key.verify(signed.as_bytes(), &Signature::from_bytes(&sig)) {
+        Ok(()) => HostTrust::Verified,
+        Err(_) => HostTrust::Mismatch,
+    }
+}
+
+// Checks the server's signature over our auth challenge and returns its identity fingerprint.
+// With a pinned fingerprint, a different (validly signed) key is "identity_mismatch".
+fn check_identity(key: &str, challenge: &str, signature: &str, pinned: Option<&str>) -> Result<String, String> {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let key: [u8; 32] = hex::decode(key)
+        .ok()
+        .and_then(|k| k.try_into().ok())
+        .ok_or("bad_identity_key")?;
+    let sig: [u8; 64] = hex::decode(signature)
+        .ok()
+        .and_then(|s| s.try_into().ok())
+        .ok_or("bad_signature")?;
+    let key = VerifyingKey::from_bytes(&key).map_err(|_| "bad_identity_key")?;
+    key.verify(format!("FOSSDECK_AUTH_V1:{challenge}").as_bytes(), &Signature::from_bytes(&sig))
+        .map_err(|_| "bad_signature")?;
+
+    let fingerprint = hex::encode(Sha256::digest(key.as_bytes()));
+    match pinned {
+        Some(p) if p != fingerprint => Err("identity_mismatch".into()),
+        _ => Ok(fingerprint),
+    }
+}
+
+#[tauri::command]
+fn verify_server_identity(
+    key: String,
+    challenge: String,
+    signature: String,
+    pinned: Option<String>,
+) -> Result<String, String> {
+    check_identity(&key, &challenge, &signature, pinned.as_deref())
+}
+
+fn new_challenge() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Only RFC1918 and link-local addresses count as "real" LAN segments
+fn is_lan_ipv4(ip: Ipv4Addr) -> bool {
+    ip.is_private() || ip.is_link_local()
 }
 
 #[tauri::command]
-async fn discover_hosts(timeout_ms: Option<u64>) -> Result<Vec<DiscoveredHost>, String> {
+async fn discover_hosts(
+    app: tauri::AppHandle,
+    timeout_ms: Option<u64>,
+    networks: Option<Vec<String>>,
+    discovery_port: Option<u16>,
+    pins: Option<HashMap<String, String>>,
+) -> Result<Vec<DiscoveredHost>, String> {
     use std::time::Instant;
     use tokio::net::UdpSocket;
     use tokio::time::{timeout, Duration};
 
-    const DISCOVERY_PORT: u16 = 45321;
+    const DEFAULT_DISCOVERY_PORT: u16 = 45321;
     const QUERY: &[u8] = b"FOSSDECK_DISCOVERY_V1?";
 
+    let discovery_port = discovery_port.unwrap_or(DEFAULT_DISCOVERY_PORT);
+
     let sock = UdpSocket::bind(("0.0.0.0", 0)).await.map_err(|e| e.to_string())?;
     sock.set_broadcast(true).map_err(|e| e.to_string())?;
 
-    // Build broadcast targets (255.255.255.255 + each interface directed broadcast)
-    let mut targets = vec![format!("255.255.255.255:{DISCOVERY_PORT}")];
-    if let Ok(ifaces) = get_if_addrs() {
+    // Build broadcast targets (255.255.255.255 + each LAN directed broadcast)
+    let mut targets = vec![format!("255.255.255.255:{discovery_port}")];
+    if let Some(networks) = networks {
+        // user-forced subnets replace interface detection
+        for n in networks {
+            let net: Ipv4Network = n.trim().parse().map_err(|e| format!("invalid network {n}: {e}"))?;
+            targets.push(format!("{}:{discovery_port}", net.broadcast()));
+        }
+    } else if let Ok(ifaces) = get_if_addrs() {
         for iface in ifaces {
             if iface.is_loopback() { continue; }
-            if let std::net::IpAddr::V4(_ip) = iface.ip() {
-                if let get_if_addrs::IfAddr::V4(v4_addr) = iface.addr {
-                    let ip = v4_addr.ip;
-                    let mask = v4_addr.netmask;
-                    let ip_u = u32::from(ip);
-                    let mask_u = u32::from(mask);
-                    let bcast = std::net::Ipv4Addr::from(ip_u | !mask_u);
-                    targets.push(format!("{bcast}:{DISCOVERY_PORT}"));
-                }
+            if let get_if_addrs::IfAddr::V4(v4_addr) = iface.addr {
+                let ip = v4_addr.ip;
+                if !is_lan_ipv4(ip) { continue; }
+                // no broadcast flag (or a /32) means point-to-point, e.g. VPN tunnels
+                if v4_addr.broadcast.is_none() || v4_addr.netmask == Ipv4Addr::BROADCAST { continue; }
+                let ip_u = u32::from(ip);
+                let mask_u = u32::from(v4_addr.netmask);
+                let bcast = Ipv4Addr::from(ip_u | !mask_u);
+                targets.push(format!("{bcast}:{discovery_port}"));
             }
         }
     }
     targets.sort();
     targets.dedup();
 
-    for t in &targets {
-        let _ = sock.send_to(QUERY, t).await;
-    }
+    // fresh nonce per scan so captured replies can't be replayed by another host
+    let mut nonce_bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce_bytes);
+    let nonce = hex::encode(nonce_bytes);
+    let query = [QUERY, nonce.as_bytes()].concat();
+    let pins = pins.unwrap_or_default();
+    // scan results carry the pin of the favorite they match, so connecting from the list enforces it
+    let favorites = favorites_path(&app).map(|p| load_favorites(&p)).unwrap_or_default();
 
-    let deadline = Instant::now() + Duration::from_millis(timeout_ms.unwrap_or(1200));
+    // re-send the query across the first part of the window so one lost packet doesn't
+    // empty the scan: 1200ms gives sends at 0/300/600, and even short scans send twice
+    let total_ms = timeout_ms.unwrap_or(1200);
+    let mut sends_left = (total_ms / 400).clamp(2, 4);
+    let spacing = Duration::from_millis((total_ms / (sends_left + 1)).min(500));
+    let start = Instant::now();
+    let mut next_send = start;
+
+    let deadline = start + Duration::from_millis(total_ms);
     let mut seen: HashSet<String> = HashSet::new();
     let mut out = vec![];
     let mut buf = [0u8; 2048];
@@ -55,35 +269,186 @@ async fn discover_hosts(timeout_ms: Option<u64>) -> Result<Vec<DiscoveredHost>,
     loop {
         let now = Instant::now();
         if now >= deadline { break; }
-        match timeout(deadline.saturating_duration_since(now), sock.recv_from(&mut buf)).await {
+        if sends_left > 0 && now >= next_send {
+            for t in &targets {
+                let _ = sock.send_to(&query, t).await;
+            }
+            sends_left -= 1;
+            next_send += spacing;
+        }
+        let wake = if sends_left > 0 { next_send.min(deadline) } else { deadline };
+        match timeout(wake.saturating_duration_since(Instant::now()), sock.recv_from(&mut buf)).await {
             Ok(Ok((n, addr))) => {
                 let ip = addr.ip().to_string();
+                let text = std::str::from_utf8(&buf[..n]).unwrap_or("");
+                // host announcing it is going away mid-scan: drop it even if it already replied
+                let parsed = serde_json::from_str::<serde_json::Value>(text).ok();
+                if parsed.as_ref().and_then(|v| v.get("type")).and_then(|t| t.as_str()) == Some("shutdown") {
+                    seen.insert(ip.clone());
+                    out.retain(|h: &DiscoveredHost| h.ip != ip);
+                    continue;
+                }
                 if !seen.insert(ip.clone()) {
                     continue;
                 }
-                let text = std::str::from_utf8(&buf[..n]).unwrap_or("");
-                if let Ok(v) = serde_json::from_str::<serde_json::Value>(text) {
+                if let Some(v) = parsed {
                     let port = v.get("port").and_then(|p| p.as_u64()).unwrap_or(3030) as u16;
                     let name = v.get("name").and_then(|s| s.as_str()).map(|s| s.to_string());
                     let path = v.get("path").and_then(|s| s.as_str()).map(|s| s.to_string());
                     let version = v.get("version").and_then(|s| s.as_str()).map(|s| s.to_string());
-                    out.push(DiscoveredHost { ip, port, name, path, version });
+                    let fingerprint = v.get("identity_fingerprint").and_then(|s| s.as_str()).map(|s| s.to_string());
+                    let identity = matching_favorite(&favorites, &fingerprint, &name, &ip).and_then(|f| f.identity.clone());
+                    let trust = check_signature(&v, &nonce, identity.as_deref(), &pins);
+                    let id = host_id(&fingerprint, &name, &ip);
+                    out.push(DiscoveredHost { id, ip, port, name, path, version, fingerprint, trust, identity });
                 } else {
-                    out.push(DiscoveredHost { ip, port: 3030, name: None, path: Some("/ws".into()), version: None });
+                    // a reply that isn't even JSON can't prove a pinned identity
+                    let identity = matching_favorite(&favorites, &None, &None, &ip).and_then(|f| f.identity.clone());
+                    let trust = if identity.is_some() { HostTrust::Mismatch } else { HostTrust::Unverified };
+                    out.push(DiscoveredHost {
+                        id: ip.clone(),
+                        ip,
+                        port: 3030,
+                        name: None,
+                        path: Some("/ws".into()),
+                        version: None,
+                        fingerprint: None,
+                        trust,
+                        identity,
+                    });
                 }
             }
-            _ => break, // timeout
+            Ok(Err(_)) => break,
+            Err(_) => {} // next send or the deadline
         }
     }
 
     Ok(out)
 }
 
+// Opens the host's WebSocket and runs Auth (with a token) or Pair (with a code), returning
+// the token to persist. Errors are "<kind>" or "<kind>:<detail>" so the UI can branch on kind.
+#[tauri::command]
+async fn connect_and_auth(
+    host: DiscoveredHost,
+    device_id: String,
+    token: Option<String>,
+    code: Option<String>,
+) -> Result<String, String> {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::time::{timeout, Duration};
+    use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    let challenge = new_challenge();
+    let request = match (&token, code) {
+        (Some(t), _) => serde_json::json!({"cmd":"auth","device_id":device_id,"token":t}),
+        (None, Some(c)) => serde_json::json!({"cmd":"pair","code":c.trim(),"device_id":device_id}),
+        (None, None) => return Err("missing_code".into()),
+    };
+
+    let url = format!("ws://{}:{}{}", host.ip, host.port, host.path.as_deref().unwrap_or("/ws"));
+    let (mut ws, _) = timeout(HANDSHAKE_TIMEOUT, connect_async(url.as_str()))
+        .await
+        .map_err(|_| "timeout".to_string())?
+        .map_err(|e| format!("connect_failed:{e}"))?;
+
+    // the server proves its key first, so a token or code only ever goes to the pinned host
+    let identify = serde_json::json!({"cmd":"identify","challenge":challenge});
+    ws.send(Message::Text(identify.to_string()))
+        .await
+        .map_err(|e| format!("connect_failed:{e}"))?;
+    let identity = loop {
+        let msg = match timeout(HANDSHAKE_TIMEOUT, ws.next()).await {
+            Ok(Some(Ok(msg))) => msg,
+            Ok(_) => break Err("connection_closed".to_string()),
+            Err(_) => break Err("timeout".to_string()),
+        };
+        let Message::Text(text) = msg else { continue };
+        let Ok(v) = serde_json::from_str::<serde_json::Value>(&text) else { continue };
+        match v.get("type").and_then(|t| t.as_str()) {
+            Some("identity") => break Ok(v),
+            // older servers don't know identify and send no identity key
+            Some("error") => break Ok(serde_json::Value::Null),
+            _ => continue,
+        }
+    };
+    let checked = identity.and_then(|v| {
+        let field = |k: &str| v.get(k).and_then(|s| s.as_str());
+        match (field("identity_key"), field("challenge_sig")) {
+            (Some(key), Some(sig)) => check_identity(key, &challenge, sig, host.identity.as_deref()).map(|_| ()),
+            // only a pinned host can be refused for not proving itself
+            _ if host.identity.is_some() => Err("identity_mismatch".into()),
+            _ => Ok(()),
+        }
+    });
+    if let Err(e) = checked {
+        let _ = ws.close(None).await;
+        return Err(e);
+    }
+
+    ws.send(Message::Text(request.to_string()))
+        .await
+        .map_err(|e| format!("connect_failed:{e}"))?;
+
+    // skip hello (and anything else unsolicited) until the handshake reply arrives
+    let mut wait = HANDSHAKE_TIMEOUT;
+    let result = loop {
+        let msg = match timeout(wait, ws.next()).await {
+            Ok(Some(Ok(msg))) => msg,
+            Ok(_) => break Err("connection_closed".to_string()),
+            Err(_) => break Err("timeout".to_string()),
+        };
+        let Message::Text(text) = msg else { continue };
+        let Ok(v) = serde_json::from_str::<serde_json::Value>(&text) else { continue };
+        let code = v.get("code").and_then(|c| c.as_str()).unwrap_or("unknown");
+
+        match v.get("type").and_then(|t| t.as_str()) {
+            Some("auth_ok") => break token.clone().ok_or_else(|| "auth_error:unknown".to_string()),
+            Some("pairing_ok") => {
+                break v
+                    .get("token")
+                    .and_then(|t| t.as_str())
+                    .map(|t| t.to_string())
+                    .ok_or_else(|| "pairing_error:missing_token".to_string());
+            }
+            Some("rate_limited") => {
+                let secs = v.get("retry_after_secs").and_then(|s| s.as_u64()).unwrap_or(0);
+                break Err(format!("rate_limited:{secs}"));
+            }
+            // the PC owner has to approve this device; wait out their prompt
+            Some("pairing_pending") => {
+                let secs = v.get("timeout_secs").and_then(|s| s.as_u64()).unwrap_or(60);
+                wait = Duration::from_secs(secs) + HANDSHAKE_TIMEOUT;
+            }
+            Some("pairing_denied") => {
+                let reason = v.get("reason").and_then(|r| r.as_str()).unwrap_or("denied");
+                break Err(format!("pairing_denied:{reason}"));
+            }
+            Some("pairing_error") => break Err(format!("pairing_error:{code}")),
+            Some("auth_error") => break Err(format!("auth_error:{code}")),
+            Some("error") => break Err(format!("error:{code}")),
+            _ => continue,
+        }
+    };
+
+    let _ = ws.close(None).await;
+    result
+}
+
 #[tauri::mobile_entry_point]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![discover_hosts])
+        .invoke_handler(tauri::generate_handler![
+            discover_hosts,
+            save_favorite,
+            list_favorites,
+            remove_favorite,
+            connect_and_auth,
+            verify_server_identity
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -71,12 +71,25 @@ export const ACTIONS = {
         },
     },
 
+    panic_mute: {
+        id: "panic_mute",
+        title: "Panic mute",
+        icon: () => state.audio.panicMute ? "assets/mic_muted.png" : "assets/mic.png",
+        enabled: () => state.isPaired,
+        run: () => {
+            state.audio.panicMute = !state.audio.panicMute; // optimistic
+            if (state.audio.panicMute) state.audio.micMuted = true;
+            renderTiles();
+            sendCmd({ cmd: "panic_mute", enabled: state.audio.panicMute });
+        },
+    },
+
     take_screenshot: {
         id: "take_screenshot",
         title: "Screenshot",
         icon: "assets/screenshot.png",
         enabled: () => state.isPaired,
-        run: () => sendCmd({ cmd: "take_screenshot" }),
+        run: () => sendCmd({ cmd: "take_screenshot", save_to_disk: true }),
     },
 
     open_calculator: {
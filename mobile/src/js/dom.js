@@ -8,6 +8,7 @@ export const el = {
     // home
     scanBtn: $("scanBtn"),
     directBtn: $("directBtn"),
+    portBtn: $("portBtn"),
     scanStatus: $("scanStatus"),
     recentList: $("recentList"),
     availableList: $("availableList"),
@@ -2,6 +2,8 @@ import { el } from "./dom.js";
 import { escapeHtml } from "./ui.js";
 import { loadRecents, forgetRecent } from "./storage.js";
 import { connect } from "./ws.js";
+import { state } from "./state.js";
+import { invoke } from "./tauri.js";
 
 export function renderRecents() {
     const recents = loadRecents();
@@ -27,7 +29,10 @@ export function renderRecents() {
       </div>
     `;
 
-        row.querySelector('[data-act="connect"]').addEventListener("click", () => connect(pc.url, pc.name || ""));
+        row.querySelector('[data-act="connect"]').addEventListener("click", () => {
+            state.currentHost = null;
+            connect(pc.url, pc.name || "");
+        });
         row.querySelector('[data-act="forget"]').addEventListener("click", () => {
             forgetRecent(pc.url);
             renderRecents();
@@ -37,34 +42,56 @@ export function renderRecents() {
     }
 }
 
-export function renderAvailable(hosts) {
+export function renderAvailable(hosts, favorites = [], { scanning = false } = {}) {
     el.availableList.innerHTML = "";
+    hosts = hosts || [];
 
-    if (!hosts || !hosts.length) {
-        el.scanStatus.textContent = "No PCs found.";
-        return;
+    // favorites not seen in this scan stay listed but greyed out
+    const seen = new Set(hosts.map(h => h.id));
+    const known = favorites.filter(f => !seen.has(f.id));
+
+    if (!scanning) {
+        el.scanStatus.textContent = hosts.length ? `Found ${hosts.length} host(s)` : "No PCs found.";
     }
 
-    el.scanStatus.textContent = `Found ${hosts.length} host(s)`;
+    for (const h of hosts) el.availableList.appendChild(makeHostRow(h, { reachable: true }));
+    for (const h of known) el.availableList.appendChild(makeHostRow(h, { reachable: false }));
+}
 
-    for (const h of hosts) {
-        const url = buildWsUrlFromHost(h);
-        const name = h.name || "PC";
+function makeHostRow(h, { reachable }) {
+    const url = buildWsUrlFromHost(h);
+    const name = h.name || "PC";
+    const sub = reachable ? describeTrust(h) : `Saved · last seen at ${h.ip}`;
 
-        const row = document.createElement("div");
-        row.className = "list-item";
-        row.innerHTML = `
+    const row = document.createElement("div");
+    row.className = "list-item" + (reachable ? "" : " stale");
+    row.innerHTML = `
       <div class="left">
         <div class="name">${escapeHtml(name)}</div>
+        <div class="sub trust-${escapeHtml(reachable ? (h.trust || "unverified") : "unverified")}">${escapeHtml(sub)}</div>
       </div>
       <div class="row-actions">
-        <button class="btn small primary">Connect</button>
+        <button class="btn small primary" data-act="connect">Connect</button>
+        ${reachable ? `` : `<button class="btn small secondary" data-act="forget">Forget</button>`}
       </div>
     `;
 
-        row.querySelector("button").addEventListener("click", () => connect(url, name));
-        el.availableList.appendChild(row);
-    }
+    row.querySelector('[data-act="connect"]').addEventListener("click", () => {
+        state.currentHost = h;
+        connect(url, name);
+    });
+    row.querySelector('[data-act="forget"]')?.addEventListener("click", async () => {
+        try { await invoke("remove_favorite", { id: h.id }); } catch {}
+        row.remove();
+    });
+    return row;
+}
+
+function describeTrust(h) {
+    const fp = h.fingerprint ? h.fingerprint.slice(0, 16) : "";
+    if (h.trust === "verified") return "Verified";
+    if (h.trust === "mismatch") return "Warning: identity mismatch, possible spoof";
+    return fp ? `Unverified · ${fp}` : "Unverified";
 }
 
 export function buildWsUrlFromHost(h) {
@@ -3,13 +3,20 @@ export const state = {
     ws: null,
     currentUrl: "",
     currentPcName: "",
+    currentHost: null, // discovered host being connected to (for favorites)
     isPaired: false,
     heartbeatTimer: null,
     disconnectInProgress: false,
 
+    // device commands the server advertised in hello; null = older server, assume all
+    capabilities: null,
+
     // auth / identity
     deviceId: null,
     authToken: null,
+    challenge: "", // fresh per connection; the server signs it with its identity key
+    identityChecked: false, // the server answered "identify" acceptably; only then auth/pair
+    identityKey: null, // verified identity key, pinned once auth succeeds
 
     // audio state mirrored from PC
     audio: {
@@ -17,6 +24,8 @@ export const state = {
         volume: 1.0,
         playing: true,
         micMuted: false,
+        micVolume: 1.0,
+        panicMute: false, // server keeps the mic muted while set
     },
 
     // ui state
@@ -1,5 +1,7 @@
 const RECENTS_KEY = "fossdeck_recents_v1";
 const LAYOUT_KEY = "fossdeck_layout_v1";
+const DISCOVERY_PORT_KEY = "fossdeck_discovery_port";
+const PINS_KEY = "fossdeck_pins_v2";
 
 export const DEFAULT_LAYOUT = [
     "previous_track",
@@ -9,6 +11,7 @@ export const DEFAULT_LAYOUT = [
     "toggle_mute",
     "volume_up",
     "toggle_mic_mute",
+    "panic_mute",
     "take_screenshot",
     "open_calculator",
 ];
@@ -86,3 +89,32 @@ export function loadLayout() {
 export function saveLayout(arr) {
     localStorage.setItem(LAYOUT_KEY, JSON.stringify(arr));
 }
+
+// optional override; must match the discovery port configured on the PC
+export function loadDiscoveryPort() {
+    const n = Number(localStorage.getItem(DISCOVERY_PORT_KEY));
+    return Number.isInteger(n) && n > 0 && n <= 65535 ? n : null;
+}
+
+export function saveDiscoveryPort(port) {
+    if (port) localStorage.setItem(DISCOVERY_PORT_KEY, String(port));
+    else localStorage.removeItem(DISCOVERY_PORT_KEY);
+}
+
+// identity fingerprint -> identity key, used to verify signed discovery replies
+export function loadPins() {
+    try {
+        const s = localStorage.getItem(PINS_KEY);
+        const obj = s ? JSON.parse(s) : {};
+        return obj && typeof obj === "object" ? obj : {};
+    } catch {
+        return {};
+    }
+}
+
+export function savePin(fingerprint, key) {
+    if (!fingerprint || !key) return;
+    const pins = loadPins();
+    pins[fingerprint] = key;
+    localStorage.setItem(PINS_KEY, JSON.stringify(pins));
+}
@@ -29,6 +29,8 @@ export function renderTiles() {
 
         const action = ACTIONS[actionId];
         if (!action) continue;
+        // e.g. media keys are withheld when the PC has no interactive desktop
+        if (state.capabilities && !state.capabilities.includes(action.id)) continue;
 
         el.tileGrid.appendChild(makeTile(action));
     }
@@ -1,15 +1,86 @@
 import { state } from "./state.js";
 import { el } from "./dom.js";
 import { showHome, showConnected, showHomeError, setConnectedMeta } from "./ui.js";
-import { loadToken, saveToken, clearToken, upsertRecent } from "./storage.js";
+import { loadToken, saveToken, clearToken, upsertRecent, savePin } from "./storage.js";
 import { renderRecents } from "./lists.js";
 import { renderTiles } from "./tiles.js";
+import { hasTauri, invoke } from "./tauri.js";
 
 export function sendCmd(obj) {
     if (!state.ws || state.ws.readyState !== WebSocket.OPEN) return;
     state.ws.send(JSON.stringify(obj));
 }
 
+function saveCurrentFavorite() {
+    if (!hasTauri || !state.currentHost) return;
+    invoke("save_favorite", { host: state.currentHost }).catch(() => {});
+}
+
+function newChallenge() {
+    const bytes = crypto.getRandomValues(new Uint8Array(16));
+    return Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+// Checks the reply to "identify" against the pinned key before any token or code is sent;
+// obj is null for older servers that don't know the command. Resolves false after refusing.
+async function checkIdentity(obj) {
+    if (!hasTauri || !state.currentHost) return true;
+    const host = state.currentHost;
+    if (!obj || !obj.identity_key || !obj.challenge_sig) {
+        if (host.identity) {
+            disconnect();
+            showHomeError("This PC did not prove its identity. Re-pair if the server was reinstalled.");
+            return false;
+        }
+        return true;
+    }
+    try {
+        host.identity = await invoke("verify_server_identity", {
+            key: obj.identity_key,
+            challenge: state.challenge,
+            signature: obj.challenge_sig,
+            pinned: host.identity || null,
+        });
+        state.identityKey = obj.identity_key;
+        return true;
+    } catch (e) {
+        disconnect();
+        showHomeError(String(e) === "identity_mismatch"
+            ? "Warning: this PC's identity changed. It may be a different machine on the same address."
+            : "Could not verify this PC's identity.");
+        return false;
+    }
+}
+
+// Pins the verified key once the PC accepted us (TOFU) and remembers the host
+function saveIdentity() {
+    if (!hasTauri || !state.currentHost) return;
+    savePin(state.currentHost.identity, state.identityKey);
+    saveCurrentFavorite();
+}
+
+// Runs once the server proved who it is: auth with the stored token, or ask for a code
+async function startSession(obj) {
+    if (state.identityChecked) return;
+    if (!(await checkIdentity(obj))) return;
+    state.identityChecked = true;
+
+    state.authToken = loadToken();
+    if (state.authToken) {
+        sendCmd({ cmd: "auth", device_id: state.deviceId, token: state.authToken });
+
+        setTimeout(() => {
+            if (!state.isPaired && state.ws && state.ws.readyState === WebSocket.OPEN) {
+                el.pairHint.classList.remove("hidden");
+                openPairModal();
+            }
+        }, 600);
+    } else {
+        el.pairHint.classList.remove("hidden");
+        openPairModal();
+    }
+}
+
 export function stopHeartbeat() {
     if (state.heartbeatTimer) {
         clearInterval(state.heartbeatTimer);
@@ -65,6 +136,9 @@ export function connect(url, name) {
     state.currentUrl = url;
     state.currentPcName = name || url;
     state.isPaired = false;
+    state.challenge = newChallenge();
+    state.identityChecked = false;
+    state.identityKey = null;
 
     state.ws = new WebSocket(url);
 
@@ -83,40 +157,59 @@ export function connect(url, name) {
         showConnected();
         renderTiles();
 
-        state.authToken = loadToken();
-        if (state.authToken) {
-            sendCmd({ cmd: "auth", device_id: state.deviceId, token: state.authToken });
-
-            setTimeout(() => {
-                if (!state.isPaired && state.ws && state.ws.readyState === WebSocket.OPEN) {
-                    el.pairHint.classList.remove("hidden");
-                    openPairModal();
-                }
-            }, 600);
-        } else {
-            el.pairHint.classList.remove("hidden");
-            openPairModal();
-        }
+        // nothing secret goes out until the reply to this checks out (see startSession)
+        sendCmd({ cmd: "identify", challenge: state.challenge });
     };
 
     state.ws.onmessage = (ev) => {
         let obj;
         try { obj = JSON.parse(ev.data); } catch { return; }
 
-        if (obj.type === "hello") return;
+        if (obj.type === "hello") {
+            state.capabilities = Array.isArray(obj.capabilities) ? obj.capabilities : null;
+            renderTiles();
+            return;
+        }
+
+        if (obj.type === "identity") {
+            startSession(obj);
+            return;
+        }
+
+        // an older server refuses "identify" as an unknown command
+        if (obj.type === "error" && obj.code === "bad_request" && !state.identityChecked) {
+            startSession(null);
+            return;
+        }
 
         if (obj.type === "status") {
             if (typeof obj.muted === "boolean") state.audio.muted = obj.muted;
             if (typeof obj.volume === "number") state.audio.volume = obj.volume;
             if (typeof obj.mic_muted === "boolean") state.audio.micMuted = obj.mic_muted;
+            if (typeof obj.mic_volume === "number") state.audio.micVolume = obj.mic_volume;
+            if (typeof obj.panic_mute === "boolean") state.audio.panicMute = obj.panic_mute;
+            if (obj.playback && typeof obj.playback.status === "string") state.audio.playing = obj.playback.status === "playing";
+            if (typeof obj.output_device === "string") setConnectedMeta(state.currentPcName, `Output: ${obj.output_device}`);
             renderTiles();
             return;
         }
 
+        if (obj.type === "media_changed") {
+            state.audio.playing = obj.status === "playing";
+            renderTiles();
+            return;
+        }
+
+        if (obj.type === "device_changed") {
+            setConnectedMeta(state.currentPcName, obj.name ? `Output: ${obj.name}` : "No output device");
+            return;
+        }
+
         if (obj.type === "auth_ok") {
             state.isPaired = true;
             el.pairHint.classList.add("hidden");
             upsertRecent({ name: state.currentPcName, url: state.currentUrl });
+            saveIdentity();
             renderRecents();
             renderTiles();
             startHeartbeat();
@@ -142,14 +235,30 @@ export function connect(url, name) {
             el.pairHint.classList.add("hidden");
             closePairModal();
             upsertRecent({ name: state.currentPcName, url: state.currentUrl });
+            saveIdentity();
             renderRecents();
             renderTiles();
             startHeartbeat();
             return;
         }
 
+        if (obj.type === "pairing_pending") {
+            el.pairError.textContent = "Waiting for approval on the PC…";
+            return;
+        }
+
+        if (obj.type === "pairing_denied") {
+            el.pairError.textContent = obj.reason === "timeout"
+                ? "Pairing request timed out on the PC."
+                : "Pairing was denied on the PC.";
+            return;
+        }
+
         if (obj.type === "pairing_error") {
-            el.pairError.textContent = `Pairing error: ${obj.reason || "unknown"}`;
+            const left = obj.attempts_remaining;
+            el.pairError.textContent = left != null && left <= 2
+                ? `Pairing error: ${obj.code || "unknown"} (${left} ${left === 1 ? "try" : "tries"} left)`
+                : `Pairing error: ${obj.code || "unknown"}`;
             return;
         }
 
@@ -159,7 +268,7 @@ export function connect(url, name) {
             return;
         }
 
-        if (obj.type === "error" && obj.message === "unauthorized") {
+        if (obj.type === "error" && obj.code === "not_authenticated") {
             state.isPaired = false;
             el.pairHint.classList.remove("hidden");
             renderTiles();
@@ -167,6 +276,19 @@ export function connect(url, name) {
             return;
         }
 
+        if (obj.type === "error" && obj.reason === "input_blocked") {
+            setConnectedMeta(state.currentPcName, obj.code === "blocked_by_uipi"
+                ? "The focused app is running as administrator"
+                : (obj.message || "The PC did not accept the input"));
+            return;
+        }
+
+        if (obj.type === "error" && obj.code === "server_full") {
+            disconnect();
+            showHomeError("PC is busy (too many clients). Try again later.");
+            return;
+        }
+
         if (obj.type === "shutdown") {
             disconnect();
             showHomeError("Server shut down.");
@@ -1,7 +1,7 @@
 import { hasTauri, invoke } from "./js/tauri.js";
 import { el } from "./js/dom.js";
 import { state } from "./js/state.js";
-import { getOrCreateDeviceId } from "./js/storage.js";
+import { getOrCreateDeviceId, loadDiscoveryPort, saveDiscoveryPort, loadPins } from "./js/storage.js";
 import { renderRecents, renderAvailable, normalizeDirectInputToWs } from "./js/lists.js";
 import { log } from "./js/ui.js";
 import { connect, disconnect, sendCmd, closePairModal, openPairModal } from "./js/ws.js";
@@ -20,9 +20,22 @@ el.scanBtn.addEventListener("click", async () => {
     return;
   }
 
+  // show saved hosts right away; the scan confirms which are reachable
+  let favorites = [];
   try {
-    const hosts = await invoke("discover_hosts", { timeoutMs: 1200 });
-    renderAvailable(hosts);
+    favorites = await invoke("list_favorites");
+    renderAvailable([], favorites, { scanning: true });
+  } catch (e) {
+    log(`Favorites error: ${e}`);
+  }
+
+  try {
+    const hosts = await invoke("discover_hosts", {
+      timeoutMs: 1200,
+      discoveryPort: loadDiscoveryPort(),
+      pins: loadPins(),
+    });
+    renderAvailable(hosts, favorites);
   } catch (e) {
     el.scanStatus.textContent = "Scan failed";
     log(`Scan error: ${e}`);
@@ -34,9 +47,17 @@ el.directBtn.addEventListener("click", () => {
   if (v === null) return;
   const url = normalizeDirectInputToWs(v);
   if (!url) return;
+  state.currentHost = null;
   connect(url, url);
 });
 
+el.portBtn.addEventListener("click", () => {
+  const v = prompt("Discovery UDP port (empty = default 45321):", loadDiscoveryPort() ?? "");
+  if (v === null) return;
+  const port = Number(v.trim());
+  saveDiscoveryPort(Number.isInteger(port) && port > 0 && port <= 65535 ? port : null);
+});
+
 // connected screen buttons
 el.backBtn.addEventListener("click", () => disconnect());
 el.editBtn.addEventListener("click", () => setEditMode(!state.editMode));
@@ -49,6 +70,8 @@ el.pairConfirm.addEventListener("click", () => {
     el.pairError.textContent = "Please enter the code.";
     return;
   }
+  // the code only goes to a server that proved its identity
+  if (!state.identityChecked) return;
   sendCmd({ cmd: "pair", code, device_id: state.deviceId, device_name: "Mobile" });
 });
 
@@ -1,90 +1,1503 @@
 use anyhow::Result;
-use windows::core::GUID;
-use windows::Win32::Foundation::BOOL;
-use windows::Win32::Media::Audio::{eCapture, eConsole, eRender, IMMDevice, IMMDeviceEnumerator, MMDeviceEnumerator};
-use windows::Win32::Media::Audio::Endpoints::IAudioEndpointVolume;
-use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_ALL, COINIT_MULTITHREADED};
+use log::error;
+use serde::Serialize;
+use serde::Deserialize;
+use serde_json::json;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread::{self, JoinHandle};
+use windows::core::{implement, Interface, GUID, HSTRING, PCWSTR};
+use windows::Win32::Devices::FunctionDiscovery::PKEY_Device_FriendlyName;
+use windows::Win32::Foundation::{BOOL, ERROR_NOT_FOUND, S_OK};
+use windows::Win32::Media::Audio::{
+    eCapture, eCommunications, eConsole, eMultimedia, eRender, AudioSessionStateActive, AudioSessionStateExpired, ERole, IAudioClient, IAudioSessionControl, IAudioSessionControl2, IAudioSessionManager2, IMMDevice,
+    IMMDeviceEnumerator, ISimpleAudioVolume, MMDeviceEnumerator, AUDCLNT_E_DEVICE_IN_USE, AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_NOPERSIST, DEVICE_STATE_ACTIVE,
+    WAVEFORMATEX, WAVEFORMATEXTENSIBLE,
+};
+use windows::Win32::Media::Audio::Endpoints::{
+    IAudioEndpointVolume, IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallback_Impl, IAudioMeterInformation,
+};
+use windows::Win32::Media::Audio::{
+    EDataFlow, IMMNotificationClient, IMMNotificationClient_Impl, AUDIO_VOLUME_NOTIFICATION_DATA, DEVICE_STATE,
+    DEVICE_STATE_DISABLED, DEVICE_STATE_UNPLUGGED,
+};
+use windows::Win32::UI::Shell::PropertiesSystem::PROPERTYKEY;
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_ALL, COINIT_MULTITHREADED, STGM_READ,
+};
 
-fn ensure_com_initialized() -> windows::core::Result<()> {
-    unsafe { CoInitializeEx(None, COINIT_MULTITHREADED).ok() }
+use crate::server::events::{publish, EventSender};
+use crate::window;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioDevice {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+}
+
+// Render endpoint plus which default roles it holds (Teams etc. follow the communications one)
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputDevice {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+    pub is_default_comms: bool,
+}
+
+// Capture endpoint as offered for switching the default microphone; disabled and unplugged
+// ones are listed so clients can show why they can't be picked
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureDevice {
+    pub id: String,
+    pub name: String,
+    pub is_default: bool,
+    pub state: EndpointState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointState {
+    Active,
+    Disabled,
+    Unplugged,
+}
+
+// Default-device role to switch; All sets console, multimedia and communications together
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputRole {
+    Console,
+    Communications,
+    Multimedia,
+    #[default]
+    All,
+}
+
+// Shared-mode mix format of an endpoint, and whether some app holds it exclusively
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceState {
+    pub id: String,
+    pub name: String,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub channels: u16,
+    // volume changes may not be heard while an exclusive-mode stream owns the device
+    pub exclusive_in_use: bool,
+}
+
+// One per-app session on the default output; `pid` tells apart instances sharing a name
+#[derive(Debug, Clone, Serialize)]
+pub struct AppSession {
+    // session instance identifier, unique per session; what mute_app takes as session_id
+    pub session_id: String,
+    pub pid: u32,
+    pub name: String,
+    pub volume: f32,
+    pub muted: bool,
+}
+
+// Mixer entry for list_audio_sessions; session_id keeps apart several sessions of one process
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub pid: u32,
+    // executable name without ".exe"; null for the system sounds session
+    pub process_name: Option<String>,
+    // what the app set for the volume mixer, often empty
+    pub display_name: String,
+    pub volume: f32,
+    pub muted: bool,
+    pub state: SessionState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    Active,
+    Inactive,
+}
+
+// One line of apply_audio_preset: a session (or every session of a process) and what to set on it
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresetEntry {
+    pub session_id: Option<String>,
+    pub process_name: Option<String>,
+    pub level: Option<f32>,
+    pub mute: Option<bool>,
+}
+
+impl PresetEntry {
+    // process_name matches case-insensitively with or without ".exe", like mute_app
+    pub fn matches(&self, session_id: Option<&str>, process_name: Option<&str>) -> bool {
+        match (&self.session_id, &self.process_name) {
+            (Some(wanted), _) => session_id == Some(wanted.as_str()),
+            (None, Some(wanted)) => {
+                let wanted = wanted.strip_suffix(".exe").unwrap_or(wanted);
+                process_name.is_some_and(|name| name.eq_ignore_ascii_case(wanted))
+            }
+            (None, None) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum PresetOutcome {
+    Applied { sessions: Vec<AppSession> },
+    NotFound,
+    Failed { message: String },
+}
+
+// Balances the one CoInitializeEx a thread makes and owns the interfaces cached on that
+// thread; dropped on thread exit or by release_com
+#[derive(Default)]
+struct ComGuard {
+    enumerator: Option<IMMDeviceEnumerator>,
+    // (endpoint id, ENDPOINT_GENERATION when activated, interface) per default render role's device
+    endpoints: Vec<(String, usize, IAudioEndpointVolume)>,
+}
+
+impl Drop for ComGuard {
+    fn drop(&mut self) {
+        // COM objects have to go before the apartment does
+        self.endpoints.clear();
+        self.enumerator = None;
+        unsafe { CoUninitialize() };
+        COM_THREADS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+thread_local! {
+    static COM: RefCell<Option<ComGuard>> = const { RefCell::new(None) };
+    // device enumerators this thread has created
+    static ENUMERATORS_CREATED: Cell<usize> = const { Cell::new(0) };
+}
+
+// Bumped by the device notifier so every thread drops its cached endpoint, including when the
+// same device id comes back (replug) with a new underlying endpoint
+static ENDPOINT_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+// threads currently holding a ComGuard
+static COM_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+// Joins the MTA once per thread; later calls on the same thread are free
+pub(crate) fn ensure_com_initialized() -> windows::core::Result<()> {
+    COM.with(|com| {
+        let mut com = com.borrow_mut();
+        if com.is_none() {
+            // S_FALSE (already initialized) still takes a reference that needs releasing
+            unsafe { CoInitializeEx(None, COINIT_MULTITHREADED).ok()? };
+            COM_THREADS.fetch_add(1, Ordering::Relaxed);
+            *com = Some(ComGuard::default());
+        }
+        Ok(())
+    })
+}
+
+// For runtime thread-stop hooks: uninitialize while the thread is still fully alive,
+// rather than from TLS teardown where COM's own state may already be gone
+pub(crate) fn release_com() {
+    let _ = COM.try_with(|com| com.borrow_mut().take());
+}
+
+pub(crate) fn com_thread_count() -> usize {
+    COM_THREADS.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+pub(crate) fn enumerators_created_on_this_thread() -> usize {
+    ENUMERATORS_CREATED.with(Cell::get)
+}
+
+type AudioJob = Box<dyn FnOnce() + Send>;
+
+// The one thread SystemAudio runs COM audio calls on, started on first use and again if a
+// request panicked it to death
+static AUDIO_THREAD: Mutex<Option<mpsc::Sender<AudioJob>>> = Mutex::new(None);
+
+thread_local! {
+    static IS_AUDIO_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+// Runs `f` on the audio thread and waits for it, so the MTA, the enumerator and the cached
+// endpoints all live on a single thread no matter which runtime thread asks
+pub(crate) fn on_audio_thread<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    // nested call from a job: waiting on ourselves would deadlock
+    if IS_AUDIO_THREAD.with(Cell::get) {
+        return f();
+    }
+    let (tx, rx) = mpsc::sync_channel(1);
+    submit(Box::new(move || {
+        let result = ensure_com_initialized()
+            .map_err(|e| anyhow::anyhow!("COM initialization failed on the audio thread: {e}"))
+            .and_then(|_| f());
+        let _ = tx.send(result);
+    }))?;
+    rx.recv().map_err(|_| anyhow::anyhow!("audio thread stopped while handling the request"))?
+}
+
+fn submit(job: AudioJob) -> Result<()> {
+    let mut worker = AUDIO_THREAD.lock().unwrap();
+    let job = match worker.as_ref() {
+        Some(tx) => match tx.send(job) {
+            Ok(()) => return Ok(()),
+            Err(mpsc::SendError(job)) => job,
+        },
+        None => job,
+    };
+
+    let (tx, rx) = mpsc::channel::<AudioJob>();
+    thread::Builder::new()
+        .name("audio-com".into())
+        .spawn(move || {
+            IS_AUDIO_THREAD.with(|f| f.set(true));
+            while let Ok(job) = rx.recv() {
+                job();
+            }
+        })
+        .map_err(|e| anyhow::anyhow!("could not start the audio thread: {e}"))?;
+    tx.send(job).map_err(|_| anyhow::anyhow!("audio thread stopped"))?;
+    *worker = Some(tx);
+    Ok(())
+}
+
+// The thread's cached enumerator, created on first use
+fn device_enumerator() -> Result<IMMDeviceEnumerator> {
+    ensure_com_initialized()?;
+    COM.with(|com| {
+        let mut com = com.borrow_mut();
+        let guard = com.as_mut().expect("COM initialized above");
+        if let Some(e) = &guard.enumerator {
+            return Ok(e.clone());
+        }
+        let enumerator: IMMDeviceEnumerator = unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+        ENUMERATORS_CREATED.with(|n| n.set(n.get() + 1));
+        guard.enumerator = Some(enumerator.clone());
+        Ok(enumerator)
+    })
 }
 
 fn default_render_endpoint() -> Result<IMMDevice> {
+    default_render_endpoint_for(eConsole)
+}
+
+fn default_render_endpoint_for(role: ERole) -> Result<IMMDevice> {
     ensure_com_initialized()?;
     unsafe {
-        let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
-        Ok(enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?)
+        let enumerator = device_enumerator()?;
+        Ok(enumerator.GetDefaultAudioEndpoint(eRender, role)?)
+    }
+}
+
+// Callers expand All into the individual roles
+fn render_role(role: OutputRole) -> Result<ERole> {
+    match role {
+        OutputRole::Console => Ok(eConsole),
+        OutputRole::Communications => Ok(eCommunications),
+        OutputRole::Multimedia => Ok(eMultimedia),
+        OutputRole::All => anyhow::bail!("\"all\" is not a single device role"),
     }
 }
 
 fn default_capture_endpoint() -> Result<IMMDevice> {
+    default_capture_endpoint_for(eConsole)
+}
+
+fn default_capture_endpoint_for(role: ERole) -> Result<IMMDevice> {
     ensure_com_initialized()?;
     unsafe {
-        let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
-        Ok(enumerator.GetDefaultAudioEndpoint(eCapture, eConsole)?)
+        let enumerator = device_enumerator()?;
+        Ok(enumerator.GetDefaultAudioEndpoint(eCapture, role)?)
     }
 }
 
-fn mic_endpoint_volume() -> Result<IAudioEndpointVolume> {
+// None = default capture endpoint, otherwise an id from list_input_devices
+fn capture_endpoint(device_id: Option<&str>) -> Result<IMMDevice> {
+    let Some(id) = device_id else {
+        return default_capture_endpoint();
+    };
     ensure_com_initialized()?;
     unsafe {
-        let device = default_capture_endpoint()?;
-        let ep: IAudioEndpointVolume = device.Activate(CLSCTX_ALL, None)?;
-        Ok(ep)
+        let enumerator = device_enumerator()?;
+        Ok(enumerator.GetDevice(&HSTRING::from(id))?)
     }
 }
 
-pub fn get_mic_mute() -> Result<bool> {
+fn endpoint_id(device: &IMMDevice) -> Result<String> {
+    unsafe {
+        let raw = device.GetId()?;
+        let id = raw.to_string();
+        CoTaskMemFree(Some(raw.0 as _));
+        Ok(id?)
+    }
+}
+
+// Active capture endpoints with their friendly names
+pub fn list_input_devices() -> Result<Vec<AudioDevice>> {
     ensure_com_initialized()?;
     unsafe {
-        let ep = mic_endpoint_volume()?;
-        Ok(ep.GetMute()?.as_bool())
+        let enumerator = device_enumerator()?;
+        let default_id = enumerator
+            .GetDefaultAudioEndpoint(eCapture, eConsole)
+            .ok()
+            .and_then(|d| endpoint_id(&d).ok());
+
+        let collection = enumerator.EnumAudioEndpoints(eCapture, DEVICE_STATE_ACTIVE)?;
+        let mut devices = Vec::new();
+        for i in 0..collection.GetCount()? {
+            let device = collection.Item(i)?;
+            let id = endpoint_id(&device)?;
+            let is_default = default_id.as_deref() == Some(id.as_str());
+            devices.push(AudioDevice { name: friendly_name(&device, &id), id, is_default });
+        }
+        Ok(devices)
     }
 }
 
-pub fn set_mic_mute(mute: bool) -> Result<()> {
+// Active, disabled and unplugged capture endpoints, flagged with the console default
+pub fn list_capture_devices() -> Result<Vec<CaptureDevice>> {
     ensure_com_initialized()?;
     unsafe {
-        let ep = mic_endpoint_volume()?;
-        ep.SetMute(BOOL::from(mute), &GUID::zeroed())?;
+        let enumerator = device_enumerator()?;
+        let default_id = enumerator
+            .GetDefaultAudioEndpoint(eCapture, eConsole)
+            .ok()
+            .and_then(|d| endpoint_id(&d).ok());
+
+        let mask = DEVICE_STATE(DEVICE_STATE_ACTIVE.0 | DEVICE_STATE_DISABLED.0 | DEVICE_STATE_UNPLUGGED.0);
+        let collection = enumerator.EnumAudioEndpoints(eCapture, mask)?;
+        let mut devices = Vec::new();
+        for i in 0..collection.GetCount()? {
+            let device = collection.Item(i)?;
+            let id = endpoint_id(&device)?;
+            let state = match device.GetState()? {
+                DEVICE_STATE_ACTIVE => EndpointState::Active,
+                DEVICE_STATE_DISABLED => EndpointState::Disabled,
+                _ => EndpointState::Unplugged,
+            };
+            devices.push(CaptureDevice {
+                name: friendly_name(&device, &id),
+                is_default: default_id.as_deref() == Some(id.as_str()),
+                state,
+                id,
+            });
+        }
+        Ok(devices)
+    }
+}
+
+// Makes the capture endpoint the default for every role, like "Set as Default Device" in Sound
+pub fn set_default_capture(device_id: &str) -> Result<()> {
+    ensure_com_initialized()?;
+    Ok(policy_config::set_default_endpoint(device_id, &[eConsole, eMultimedia, eCommunications])?)
+}
+
+// The endpoint master volume commands act on
+pub fn default_output_device() -> Result<AudioDevice> {
+    let device = default_render_endpoint()?;
+    let id = endpoint_id(&device)?;
+    Ok(AudioDevice { name: friendly_name(&device, &id), id, is_default: true })
+}
+
+// The microphone mic commands act on; None when the machine has no capture endpoint at all
+pub fn default_input_device() -> Result<Option<AudioDevice>> {
+    let device = match default_capture_endpoint() {
+        Ok(device) => device,
+        Err(e) => match e.downcast_ref::<windows::core::Error>() {
+            Some(err) if err.code() == ERROR_NOT_FOUND.to_hresult() => return Ok(None),
+            _ => return Err(e),
+        },
+    };
+    let id = endpoint_id(&device)?;
+    Ok(Some(AudioDevice { name: friendly_name(&device, &id), id, is_default: true }))
+}
+
+// Active render endpoints, flagged with the console and communications defaults
+pub fn list_output_devices() -> Result<Vec<OutputDevice>> {
+    ensure_com_initialized()?;
+    unsafe {
+        let enumerator = device_enumerator()?;
+        let default_of = |role| {
+            enumerator
+                .GetDefaultAudioEndpoint(eRender, role)
+                .ok()
+                .and_then(|d| endpoint_id(&d).ok())
+        };
+        let default_id = default_of(eConsole);
+        let comms_id = default_of(eCommunications);
+
+        let collection = enumerator.EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)?;
+        let mut devices = Vec::new();
+        for i in 0..collection.GetCount()? {
+            let device = collection.Item(i)?;
+            let id = endpoint_id(&device)?;
+            devices.push(OutputDevice {
+                name: friendly_name(&device, &id),
+                is_default: default_id.as_deref() == Some(id.as_str()),
+                is_default_comms: comms_id.as_deref() == Some(id.as_str()),
+                id,
+            });
+        }
+        Ok(devices)
+    }
+}
+
+// Undocumented, but it is what the Sound control panel uses and has been stable since Vista.
+// Only SetDefaultEndpoint is called; the rest are declared to keep the vtable layout.
+#[allow(non_snake_case)]
+mod policy_config {
+    use windows::core::{interface, IUnknown, IUnknown_Vtbl, GUID, HRESULT, HSTRING, PCWSTR};
+    use windows::Win32::Media::Audio::ERole;
+    use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_ALL};
+
+    #[interface("f8679f50-850a-41cf-9c72-430f290290c8")]
+    pub unsafe trait IPolicyConfig: IUnknown {
+        fn GetMixFormat(&self, id: PCWSTR, format: *mut *mut std::ffi::c_void) -> HRESULT;
+        fn GetDeviceFormat(&self, id: PCWSTR, default: i32, format: *mut *mut std::ffi::c_void) -> HRESULT;
+        fn ResetDeviceFormat(&self, id: PCWSTR) -> HRESULT;
+        fn SetDeviceFormat(&self, id: PCWSTR, endpoint: *mut std::ffi::c_void, mix: *mut std::ffi::c_void) -> HRESULT;
+        fn GetProcessingPeriod(&self, id: PCWSTR, default: i32, default_period: *mut i64, min_period: *mut i64) -> HRESULT;
+        fn SetProcessingPeriod(&self, id: PCWSTR, period: *mut i64) -> HRESULT;
+        fn GetShareMode(&self, id: PCWSTR, mode: *mut std::ffi::c_void) -> HRESULT;
+        fn SetShareMode(&self, id: PCWSTR, mode: *mut std::ffi::c_void) -> HRESULT;
+        fn GetPropertyValue(&self, id: PCWSTR, key: *const std::ffi::c_void, value: *mut std::ffi::c_void) -> HRESULT;
+        fn SetPropertyValue(&self, id: PCWSTR, key: *const std::ffi::c_void, value: *mut std::ffi::c_void) -> HRESULT;
+        fn SetDefaultEndpoint(&self, id: PCWSTR, role: ERole) -> HRESULT;
+        fn SetEndpointVisibility(&self, id: PCWSTR, visible: i32) -> HRESULT;
+    }
+
+    const CLSID_POLICY_CONFIG_CLIENT: GUID = GUID::from_u128(0x870af99c_171d_4f9e_af0d_e63df40c2bc9);
+
+    pub fn set_default_endpoint(device_id: &str, roles: &[ERole]) -> windows::core::Result<()> {
+        unsafe {
+            let policy: IPolicyConfig = CoCreateInstance(&CLSID_POLICY_CONFIG_CLIENT, None, CLSCTX_ALL)?;
+            let id = HSTRING::from(device_id);
+            for &role in roles {
+                policy.SetDefaultEndpoint(PCWSTR(id.as_ptr()), role).ok()?;
+            }
+        }
         Ok(())
     }
 }
 
-fn endpoint_volume() -> Result<IAudioEndpointVolume> {
+pub fn set_default_output(device_id: &str, role: OutputRole) -> Result<()> {
+    let roles: &[ERole] = match role {
+        OutputRole::Console => &[eConsole],
+        OutputRole::Communications => &[eCommunications],
+        OutputRole::Multimedia => &[eMultimedia],
+        OutputRole::All => &[eConsole, eMultimedia, eCommunications],
+    };
+
+    ensure_com_initialized()?;
+    Ok(policy_config::set_default_endpoint(device_id, roles)?)
+}
+
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// Render or capture endpoint by id (as returned by the list_* functions)
+pub fn device_state(device_id: &str) -> Result<DeviceState> {
+    ensure_com_initialized()?;
+    unsafe {
+        let enumerator = device_enumerator()?;
+        let device = enumerator.GetDevice(&HSTRING::from(device_id))?;
+        let client: IAudioClient = device.Activate(CLSCTX_ALL, None)?;
+
+        let format = client.GetMixFormat()?;
+        let fmt: WAVEFORMATEX = *format;
+        let bits_per_sample = if fmt.wFormatTag == WAVE_FORMAT_EXTENSIBLE {
+            (*(format as *const WAVEFORMATEXTENSIBLE)).Samples.wValidBitsPerSample
+        } else {
+            fmt.wBitsPerSample
+        };
+
+        // a shared stream can't open while another app holds the device exclusively;
+        // the client is never started, so nothing is heard
+        let probe = client.Initialize(AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_NOPERSIST, 0, 0, format, None);
+        CoTaskMemFree(Some(format as _));
+        let exclusive_in_use = match probe {
+            Ok(()) => false,
+            Err(e) if e.code() == AUDCLNT_E_DEVICE_IN_USE => true,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(DeviceState {
+            name: friendly_name(&device, device_id),
+            id: device_id.to_string(),
+            sample_rate: fmt.nSamplesPerSec,
+            bits_per_sample,
+            channels: fmt.nChannels,
+            exclusive_in_use,
+        })
+    }
+}
+
+// Falls back to the endpoint id when the property store is unavailable
+fn friendly_name(device: &IMMDevice, id: &str) -> String {
+    unsafe {
+        device
+            .OpenPropertyStore(STGM_READ)
+            .and_then(|props| props.GetValue(&PKEY_Device_FriendlyName))
+            .map(|v| v.to_string())
+            .unwrap_or_else(|_| id.to_string())
+    }
+}
+
+// Fails with a readable message instead of a bare HRESULT when there is no mic or it was
+// unplugged/disabled since the client last listed devices
+fn mic_endpoint_volume(device_id: Option<&str>) -> Result<IAudioEndpointVolume> {
+    let device = capture_endpoint(device_id).map_err(|e| match device_id {
+        Some(id) => e.context(format!("microphone {id} is not available")),
+        None => e.context("no default microphone"),
+    })?;
+    activate_mic(&device)
+}
+
+fn activate_mic(device: &IMMDevice) -> Result<IAudioEndpointVolume> {
+    unsafe {
+        if device.GetState()? != DEVICE_STATE_ACTIVE {
+            let id = endpoint_id(device)?;
+            anyhow::bail!("microphone {} is unplugged or disabled", friendly_name(device, &id));
+        }
+        Ok(device.Activate(CLSCTX_ALL, None)?)
+    }
+}
+
+pub fn get_mic_mute(device_id: Option<&str>) -> Result<bool> {
+    let ep = mic_endpoint_volume(device_id)?;
+    unsafe {
+        let muted = ep.GetMute().map_err(|e| anyhow::anyhow!("microphone went away: {e}"))?;
+        Ok(muted.as_bool())
+    }
+}
+
+// With no device_id this also mutes the default communications mic when that is a different
+// device, so calls (which use it) follow the deck's mic button
+pub fn set_mic_mute(device_id: Option<&str>, mute: bool) -> Result<()> {
+    let ep = mic_endpoint_volume(device_id)?;
+    unsafe {
+        ep.SetMute(BOOL::from(mute), &GUID::zeroed())
+            .map_err(|e| anyhow::anyhow!("microphone went away: {e}"))?;
+    }
+    if device_id.is_none()
+        && let Ok(comms) = default_capture_endpoint_for(eCommunications)
+        && endpoint_id(&comms)? != endpoint_id(&default_capture_endpoint()?)?
+    {
+        let ep = activate_mic(&comms)?;
+        unsafe { ep.SetMute(BOOL::from(mute), &GUID::zeroed())? };
+    }
+    Ok(())
+}
+
+// Current output peak of the default render endpoint, 0.0-1.0 (what the Sound panel meter shows)
+pub fn get_peak_level() -> Result<f32> {
     ensure_com_initialized()?;
     unsafe {
         let device = default_render_endpoint()?;
-        // windows 0.58+ generic Activate API (works with your current setup):
-        let ep: IAudioEndpointVolume = device.Activate(CLSCTX_ALL, None)?;
-        Ok(ep)
+        let meter: IAudioMeterInformation = device.Activate(CLSCTX_ALL, None)?;
+        Ok(meter.GetPeakValue()?)
     }
 }
 
-pub fn get_volume_and_mute() -> Result<(f32, bool)> {
+// Capture level of the default microphone, scalar 0.0-1.0 like the master volume
+pub fn get_mic_volume() -> Result<f32> {
     ensure_com_initialized()?;
     unsafe {
-        let ep = endpoint_volume()?;
+        let ep = mic_endpoint_volume(None)?;
+        Ok(ep.GetMasterVolumeLevelScalar()?)
+    }
+}
+
+pub fn set_mic_volume(level: f32) -> Result<()> {
+    ensure_com_initialized()?;
+    unsafe {
+        let ep = mic_endpoint_volume(None)?;
+        ep.SetMasterVolumeLevelScalar(level.clamp(0.0, 1.0), &GUID::zeroed())?;
+        Ok(())
+    }
+}
+
+// Reuses this thread's interface while the role's default render device stays the same
+fn endpoint_volume(role: ERole) -> Result<IAudioEndpointVolume> {
+    let device = default_render_endpoint_for(role)?;
+    let id = endpoint_id(&device)?;
+    let generation = ENDPOINT_GENERATION.load(Ordering::SeqCst);
+    let cached = COM.with(|com| {
+        com.borrow().as_ref().and_then(|g| {
+            g.endpoints
+                .iter()
+                .find(|(cached_id, cached_gen, _)| *cached_id == id && *cached_gen == generation)
+                .map(|(_, _, ep)| ep.clone())
+        })
+    });
+    if let Some(ep) = cached {
+        return Ok(ep);
+    }
+
+    let ep: IAudioEndpointVolume = unsafe { device.Activate(CLSCTX_ALL, None)? };
+    COM.with(|com| {
+        if let Some(g) = com.borrow_mut().as_mut() {
+            // at most one entry per device, none from before the last device change
+            g.endpoints.retain(|(cached_id, cached_gen, _)| *cached_id != id && *cached_gen == generation);
+            g.endpoints.push((id, generation, ep.clone()));
+        }
+    });
+    Ok(ep)
+}
+
+// Runs `f` on the cached interface of the role's device; a failure drops the cache and retries
+// once on a fresh one, since a driver reset invalidates the old interface without a device change
+fn with_endpoint<T>(role: ERole, f: impl Fn(&IAudioEndpointVolume) -> Result<T>) -> Result<T> {
+    match f(&endpoint_volume(role)?) {
+        Ok(v) => Ok(v),
+        Err(_) => {
+            COM.with(|com| {
+                if let Some(g) = com.borrow_mut().as_mut() {
+                    g.endpoints.clear();
+                }
+            });
+            f(&endpoint_volume(role)?)
+        }
+    }
+}
+
+fn with_master<T>(f: impl Fn(&IAudioEndpointVolume) -> Result<T>) -> Result<T> {
+    with_endpoint(eConsole, f)
+}
+
+// Same as get_volume_and_mute/set_volume/set_mute, for the default device of another role
+pub fn get_role_volume_and_mute(role: OutputRole) -> Result<(f32, bool)> {
+    with_endpoint(render_role(role)?, |ep| unsafe {
+        Ok((ep.GetMasterVolumeLevelScalar()?, ep.GetMute()?.as_bool()))
+    })
+}
+
+pub fn set_role_volume(role: OutputRole, level: f32) -> Result<()> {
+    with_endpoint(render_role(role)?, |ep| unsafe {
+        ep.SetMasterVolumeLevelScalar(level.clamp(0.0, 1.0), &GUID::zeroed())?;
+        Ok(())
+    })
+}
+
+pub fn set_role_mute(role: OutputRole, mute: bool) -> Result<()> {
+    with_endpoint(render_role(role)?, |ep| unsafe {
+        ep.SetMute(BOOL::from(mute), &GUID::zeroed())?;
+        Ok(())
+    })
+}
+
+pub fn get_volume_and_mute() -> Result<(f32, bool)> {
+    with_master(|ep| unsafe {
         let level = ep.GetMasterVolumeLevelScalar()?;
         let mute = ep.GetMute()?.as_bool();
         Ok((level, mute))
-    }
+    })
 }
 
 pub fn set_volume(level: f32) -> Result<()> {
+    with_master(|ep| unsafe {
+        ep.SetMasterVolumeLevelScalar(level.clamp(0.0, 1.0), &GUID::zeroed())?;
+        Ok(())
+    })
+}
+
+// None if the endpoint is unplugged, disabled or gone
+fn active_endpoint_volume(device_id: &str) -> Result<Option<IAudioEndpointVolume>> {
     ensure_com_initialized()?;
     unsafe {
-        let ep = endpoint_volume()?;
-        ep.SetMasterVolumeLevelScalar(level.clamp(0.0, 1.0), &GUID::zeroed())?;
+        let enumerator = device_enumerator()?;
+        let Ok(device) = enumerator.GetDevice(&HSTRING::from(device_id)) else {
+            return Ok(None);
+        };
+        if device.GetState()? != DEVICE_STATE_ACTIVE {
+            return Ok(None);
+        }
+        Ok(Some(device.Activate(CLSCTX_ALL, None)?))
+    }
+}
+
+// Master level of a specific output endpoint; Ok(false) if it is unplugged, disabled or gone
+pub fn set_endpoint_volume(device_id: &str, level: f32) -> Result<bool> {
+    let Some(ep) = active_endpoint_volume(device_id)? else {
+        return Ok(false);
+    };
+    unsafe { ep.SetMasterVolumeLevelScalar(level.clamp(0.0, 1.0), &GUID::zeroed())? };
+    Ok(true)
+}
+
+pub fn set_endpoint_mute(device_id: &str, mute: bool) -> Result<bool> {
+    let Some(ep) = active_endpoint_volume(device_id)? else {
+        return Ok(false);
+    };
+    unsafe { ep.SetMute(BOOL::from(mute), &GUID::zeroed())? };
+    Ok(true)
+}
+
+pub fn get_endpoint_volume_and_mute(device_id: &str) -> Result<Option<(f32, bool)>> {
+    let Some(ep) = active_endpoint_volume(device_id)? else {
+        return Ok(None);
+    };
+    unsafe { Ok(Some((ep.GetMasterVolumeLevelScalar()?, ep.GetMute()?.as_bool()))) }
+}
+
+// Publishes master volume/mute changes made outside the server (keyboard keys, the tray
+// flyout, other apps) on the "volume" topic, and default output switches on "devices".
+// Like SessionWatcher, the registrations live on their own MTA thread and are released when
+// this is dropped.
+pub struct VolumeWatcher {
+    wake: mpsc::Sender<WatchMsg>,
+    handle: Option<JoinHandle<()>>,
+}
+
+enum WatchMsg {
+    // the default render device changed or an endpoint came or went
+    Rebind,
+    Stop,
+}
+
+impl VolumeWatcher {
+    pub fn start(events: EventSender) -> Self {
+        let (wake, rx) = mpsc::channel();
+        let notifier_wake = wake.clone();
+        let handle = thread::spawn(move || {
+            let res = watch_volume(events, notifier_wake, rx);
+            release_com();
+            if let Err(e) = res {
+                error!("Volume watcher failed: {e:?}");
+            }
+        });
+        Self { wake, handle: Some(handle) }
+    }
+}
+
+impl Drop for VolumeWatcher {
+    fn drop(&mut self) {
+        // the device notifier holds a sender too, so stop explicitly
+        let _ = self.wake.send(WatchMsg::Stop);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+#[implement(IAudioEndpointVolumeCallback)]
+struct VolumeCallback {
+    events: EventSender,
+    last: Mutex<(f32, bool)>,
+}
+
+impl IAudioEndpointVolumeCallback_Impl for VolumeCallback_Impl {
+    fn OnNotify(&self, data: *mut AUDIO_VOLUME_NOTIFICATION_DATA) -> windows::core::Result<()> {
+        let Some(data) = (unsafe { data.as_ref() }) else {
+            return Ok(());
+        };
+        let current = (data.fMasterVolume, data.bMuted.as_bool());
+        // setting the level it already has (a repeated command, channel balance) notifies too
+        if std::mem::replace(&mut *self.last.lock().unwrap(), current) == current {
+            return Ok(());
+        }
+        publish(&self.events, "volume", json!({"type":"status","volume":current.0,"muted":current.1}));
         Ok(())
     }
 }
 
+// Runs on a system thread where registering callbacks or activating devices can deadlock,
+// so it only invalidates caches and hands the rebinding to the watcher thread
+#[implement(IMMNotificationClient)]
+struct DeviceNotifier {
+    wake: mpsc::Sender<WatchMsg>,
+}
+
+impl DeviceNotifier {
+    fn changed(&self) {
+        ENDPOINT_GENERATION.fetch_add(1, Ordering::SeqCst);
+        let _ = self.wake.send(WatchMsg::Rebind);
+    }
+}
+
+impl IMMNotificationClient_Impl for DeviceNotifier_Impl {
+    fn OnDeviceStateChanged(&self, _id: &PCWSTR, _state: DEVICE_STATE) -> windows::core::Result<()> {
+        self.changed();
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, _id: &PCWSTR) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, _id: &PCWSTR) -> windows::core::Result<()> {
+        self.changed();
+        Ok(())
+    }
+
+    fn OnDefaultDeviceChanged(&self, flow: EDataFlow, role: ERole, _id: &PCWSTR) -> windows::core::Result<()> {
+        if flow == eRender && role == eConsole {
+            self.changed();
+        }
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(&self, _id: &PCWSTR, _key: &PROPERTYKEY) -> windows::core::Result<()> {
+        Ok(())
+    }
+}
+
+// Volume callback registered on one render endpoint
+struct MasterBinding {
+    id: String,
+    name: String,
+    ep: IAudioEndpointVolume,
+    callback: IAudioEndpointVolumeCallback,
+}
+
+impl MasterBinding {
+    fn register(events: &EventSender) -> Result<Self> {
+        let device = default_render_endpoint()?;
+        let id = endpoint_id(&device)?;
+        unsafe {
+            let ep: IAudioEndpointVolume = device.Activate(CLSCTX_ALL, None)?;
+            let last = (ep.GetMasterVolumeLevelScalar()?, ep.GetMute()?.as_bool());
+            let callback: IAudioEndpointVolumeCallback =
+                VolumeCallback { events: events.clone(), last: Mutex::new(last) }.into();
+            ep.RegisterControlChangeNotify(&callback)?;
+            Ok(Self { name: friendly_name(&device, &id), id, ep, callback })
+        }
+    }
+
+    fn unregister(self) {
+        let _ = unsafe { self.ep.UnregisterControlChangeNotify(&self.callback) };
+    }
+}
+
+fn watch_volume(events: EventSender, wake: mpsc::Sender<WatchMsg>, rx: mpsc::Receiver<WatchMsg>) -> Result<()> {
+    let enumerator = device_enumerator()?;
+    let notifier: IMMNotificationClient = DeviceNotifier { wake }.into();
+    unsafe { enumerator.RegisterEndpointNotificationCallback(&notifier)? };
+
+    // no render device at all is fine; the next change binds again
+    let mut bound = MasterBinding::register(&events).ok();
+    while let Ok(WatchMsg::Rebind) = rx.recv() {
+        // one unplug fires several notifications; rebind once for all of them
+        let mut stop = false;
+        while let Ok(msg) = rx.try_recv() {
+            stop |= matches!(msg, WatchMsg::Stop);
+        }
+        if stop {
+            break;
+        }
+
+        let previous = bound.take().map(|b| {
+            let id = b.id.clone();
+            b.unregister();
+            id
+        });
+        bound = MasterBinding::register(&events).ok();
+        let current = bound.as_ref().map(|b| (b.id.as_str(), b.name.as_str()));
+        if previous.as_deref() != current.map(|(id, _)| id) {
+            publish(
+                &events,
+                "devices",
+                json!({"type":"device_changed","id":current.map(|(id, _)| id),"name":current.map(|(_, name)| name)}),
+            );
+        }
+    }
+
+    if let Some(b) = bound {
+        b.unregister();
+    }
+    unsafe { enumerator.UnregisterEndpointNotificationCallback(&notifier)? };
+    Ok(())
+}
+
+// Master level in dB, the space the device driver exposes via GetVolumeRange
+pub fn get_volume_db() -> Result<f32> {
+    with_master(|ep| unsafe { Ok(ep.GetMasterVolumeLevel()?) })
+}
+
+pub fn set_volume_db(db: f32) -> Result<()> {
+    with_master(|ep| unsafe {
+        let (min, max) = range_db(ep)?;
+        ep.SetMasterVolumeLevel(db.clamp(min, max), &GUID::zeroed())?;
+        Ok(())
+    })
+}
+
+// (min_db, max_db) of the default render device
+pub fn get_volume_range_db() -> Result<(f32, f32)> {
+    with_master(range_db)
+}
+
+fn range_db(ep: &IAudioEndpointVolume) -> Result<(f32, f32)> {
+    let (mut min, mut max, mut step) = (0f32, 0f32, 0f32);
+    unsafe { ep.GetVolumeRange(&mut min, &mut max, &mut step)? };
+    Ok((min, max))
+}
+
 pub fn set_mute(mute: bool) -> Result<()> {
-    ensure_com_initialized()?;
-    unsafe {
-        let ep = endpoint_volume()?;
+    with_master(|ep| unsafe {
         ep.SetMute(BOOL::from(mute), &GUID::zeroed())?;
         Ok(())
+    })
+}
+
+// Channel positions in the standard WAVEFORMATEXTENSIBLE order (FL FR FC LFE BL BR SL SR);
+// center and LFE are left alone
+const LEFT_CHANNELS: [u32; 3] = [0, 4, 6];
+const RIGHT_CHANNELS: [u32; 3] = [1, 5, 7];
+
+// -1.0 full left .. 1.0 full right, from the front pair's relative levels; None on mono endpoints
+pub fn get_balance() -> Result<Option<f32>> {
+    with_master(|ep| unsafe {
+        if ep.GetChannelCount()? < 2 {
+            return Ok(None);
+        }
+        let left = ep.GetChannelVolumeLevelScalar(0)?;
+        let right = ep.GetChannelVolumeLevelScalar(1)?;
+        Ok(Some(balance_from_levels(left, right)))
+    })
+}
+
+// Keeps the louder side at the current loudest channel level, so the master volume is
+// unchanged; false on mono endpoints
+pub fn set_balance(value: f32) -> Result<bool> {
+    let value = value.clamp(-1.0, 1.0);
+    with_master(|ep| unsafe {
+        let count = ep.GetChannelCount()?;
+        if count < 2 {
+            return Ok(false);
+        }
+        let mut peak = 0f32;
+        for ch in 0..count {
+            peak = peak.max(ep.GetChannelVolumeLevelScalar(ch)?);
+        }
+        let (left, right) = (peak * (1.0 - value).min(1.0), peak * (1.0 + value).min(1.0));
+        for ch in 0..count {
+            let level = if LEFT_CHANNELS.contains(&ch) {
+                left
+            } else if RIGHT_CHANNELS.contains(&ch) {
+                right
+            } else {
+                continue;
+            };
+            ep.SetChannelVolumeLevelScalar(ch, level, &GUID::zeroed())?;
+        }
+        Ok(true)
+    })
+}
+
+pub(crate) fn balance_from_levels(left: f32, right: f32) -> f32 {
+    if left <= 0.0 && right <= 0.0 {
+        0.0
+    } else if right >= left {
+        1.0 - left / right
+    } else {
+        right / left - 1.0
+    }
+}
+
+// Sets every session owned by `pid` on the default render device; false if it has none
+pub fn set_process_volume(pid: u32, level: f32) -> Result<bool> {
+    // the system sounds session has no single owning process
+    set_matching_sessions_volume(level, |control| unsafe { control.GetProcessId().ok() == Some(pid) })
+}
+
+// Sets every session whose identifier mentions `app_id` (an AppUserModelId or exe name)
+pub fn set_app_volume(app_id: &str, level: f32) -> Result<bool> {
+    // packaged apps: "Publisher.App_hash!Entry" appears as "Publisher.App_<version>_..." in the identifier
+    let package = app_id.split('!').next().unwrap_or(app_id);
+    let needle = package.split('_').next().unwrap_or(package).to_lowercase();
+    set_matching_sessions_volume(level, |control| {
+        session_identifier(control).is_some_and(|id| id.to_lowercase().contains(&needle))
+    })
+}
+
+pub fn list_app_sessions() -> Result<Vec<AppSession>> {
+    ensure_com_initialized()?;
+    unsafe {
+        let device = default_render_endpoint()?;
+        let manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, None)?;
+        let list = manager.GetSessionEnumerator()?;
+
+        let mut sessions = Vec::new();
+        for i in 0..list.GetCount()? {
+            let control: IAudioSessionControl2 = list.GetSession(i)?.cast()?;
+            if control.GetState()? == AudioSessionStateExpired {
+                continue;
+            }
+            sessions.push(app_session(&control)?);
+        }
+        Ok(sessions)
+    }
+}
+
+// Non-expired sessions on the default output, ordered by process so one app's sessions sit together
+pub fn list_audio_sessions() -> Result<Vec<SessionInfo>> {
+    ensure_com_initialized()?;
+    unsafe {
+        let device = default_render_endpoint()?;
+        let manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, None)?;
+        let list = manager.GetSessionEnumerator()?;
+
+        let mut sessions = Vec::new();
+        for i in 0..list.GetCount()? {
+            let control: IAudioSessionControl2 = list.GetSession(i)?.cast()?;
+            let state = control.GetState()?;
+            if state == AudioSessionStateExpired {
+                continue;
+            }
+            let state = if state == AudioSessionStateActive { SessionState::Active } else { SessionState::Inactive };
+            let pid = control.GetProcessId().unwrap_or(0);
+            let process_name = if control.IsSystemSoundsSession() == S_OK {
+                None
+            } else {
+                window::process_name(pid)
+            };
+            let volume: ISimpleAudioVolume = control.cast()?;
+            sessions.push(SessionInfo {
+                session_id: session_instance_id(&control).unwrap_or_default(),
+                pid,
+                process_name,
+                display_name: session_display_name(&control),
+                volume: volume.GetMasterVolume()?,
+                muted: volume.GetMute()?.as_bool(),
+                state,
+            });
+        }
+        sessions.sort_by(|a, b| (&a.process_name, a.pid).cmp(&(&b.process_name, b.pid)));
+        Ok(sessions)
+    }
+}
+
+fn session_display_name(control: &IAudioSessionControl2) -> String {
+    unsafe {
+        let Ok(raw) = control.GetDisplayName() else {
+            return String::new();
+        };
+        let name = raw.to_string().unwrap_or_default();
+        CoTaskMemFree(Some(raw.0 as _));
+        name
+    }
+}
+
+// Mutes or unmutes the session with this instance identifier; the affected session, if any
+pub fn mute_session(session_id: &str, mute: bool) -> Result<Vec<AppSession>> {
+    set_matching_sessions_mute(mute, |control| session_instance_id(control).as_deref() == Some(session_id))
+}
+
+// Mutes or unmutes every session of an executable, matched case-insensitively with or
+// without ".exe"; returns the sessions affected
+pub fn mute_process_sessions(process_name: &str, mute: bool) -> Result<Vec<AppSession>> {
+    let wanted = process_name.strip_suffix(".exe").unwrap_or(process_name);
+    set_matching_sessions_mute(mute, |control| unsafe {
+        let pid = control.GetProcessId().unwrap_or(0);
+        control.IsSystemSoundsSession() != S_OK
+            && window::process_name(pid).is_some_and(|name| name.eq_ignore_ascii_case(wanted))
+    })
+}
+
+fn set_matching_sessions_mute(mute: bool, matches: impl Fn(&IAudioSessionControl2) -> bool) -> Result<Vec<AppSession>> {
+    ensure_com_initialized()?;
+    unsafe {
+        let device = default_render_endpoint()?;
+        let manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, None)?;
+        let list = manager.GetSessionEnumerator()?;
+
+        let mut affected = Vec::new();
+        for i in 0..list.GetCount()? {
+            let control: IAudioSessionControl2 = list.GetSession(i)?.cast()?;
+            if control.GetState()? == AudioSessionStateExpired || !matches(&control) {
+                continue;
+            }
+            let volume: ISimpleAudioVolume = control.cast()?;
+            volume.SetMute(BOOL::from(mute), &GUID::zeroed())?;
+            affected.push(app_session(&control)?);
+        }
+        Ok(affected)
+    }
+}
+
+// Applies every entry against one pass over the default output's sessions; an entry that
+// fails is reported in its outcome and the rest still run
+pub fn apply_session_preset(entries: &[PresetEntry]) -> Result<Vec<PresetOutcome>> {
+    ensure_com_initialized()?;
+    unsafe {
+        let device = default_render_endpoint()?;
+        let manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, None)?;
+        let list = manager.GetSessionEnumerator()?;
+
+        let mut sessions = Vec::new();
+        for i in 0..list.GetCount()? {
+            let control: IAudioSessionControl2 = list.GetSession(i)?.cast()?;
+            if control.GetState()? == AudioSessionStateExpired {
+                continue;
+            }
+            let process_name = if control.IsSystemSoundsSession() == S_OK {
+                None
+            } else {
+                window::process_name(control.GetProcessId().unwrap_or(0))
+            };
+            sessions.push((session_instance_id(&control), process_name, control));
+        }
+
+        Ok(entries
+            .iter()
+            .map(|entry| {
+                let matching: Vec<_> = sessions
+                    .iter()
+                    .filter(|(id, process, _)| entry.matches(id.as_deref(), process.as_deref()))
+                    .collect();
+                if matching.is_empty() {
+                    return PresetOutcome::NotFound;
+                }
+                let applied: Result<Vec<_>> =
+                    matching.into_iter().map(|(_, _, control)| apply_preset_entry(control, entry)).collect();
+                match applied {
+                    Ok(sessions) => PresetOutcome::Applied { sessions },
+                    Err(e) => PresetOutcome::Failed { message: e.to_string() },
+                }
+            })
+            .collect())
+    }
+}
+
+fn apply_preset_entry(control: &IAudioSessionControl2, entry: &PresetEntry) -> Result<AppSession> {
+    let volume: ISimpleAudioVolume = control.cast()?;
+    unsafe {
+        if let Some(level) = entry.level {
+            volume.SetMasterVolume(level.clamp(0.0, 1.0), &GUID::zeroed())?;
+        }
+        if let Some(mute) = entry.mute {
+            volume.SetMute(BOOL::from(mute), &GUID::zeroed())?;
+        }
+    }
+    app_session(control)
+}
+
+// Level/mute asked for before the system sounds session existed; the session watcher applies
+// it when Windows creates the session (on the first ding)
+static PENDING_SYSTEM_SOUNDS: Mutex<(Option<f32>, Option<bool>)> = Mutex::new((None, None));
+
+fn system_sounds_volume() -> Result<Option<ISimpleAudioVolume>> {
+    ensure_com_initialized()?;
+    unsafe {
+        let device = default_render_endpoint()?;
+        let manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, None)?;
+        let list = manager.GetSessionEnumerator()?;
+        for i in 0..list.GetCount()? {
+            let control: IAudioSessionControl2 = list.GetSession(i)?.cast()?;
+            if control.IsSystemSoundsSession() == S_OK && control.GetState()? != AudioSessionStateExpired {
+                return Ok(Some(control.cast()?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// (volume, muted) of the "System Sounds" mixer entry; None until Windows has created it
+pub fn get_system_sounds() -> Result<Option<(f32, bool)>> {
+    let Some(volume) = system_sounds_volume()? else {
+        return Ok(None);
+    };
+    unsafe { Ok(Some((volume.GetMasterVolume()?, volume.GetMute()?.as_bool()))) }
+}
+
+// Applies whichever of level/mute is given; without a session yet they are kept for later
+// and None is returned
+pub fn set_system_sounds(level: Option<f32>, mute: Option<bool>) -> Result<Option<(f32, bool)>> {
+    let Some(volume) = system_sounds_volume()? else {
+        let mut pending = PENDING_SYSTEM_SOUNDS.lock().unwrap();
+        *pending = (level.or(pending.0), mute.or(pending.1));
+        return Ok(None);
+    };
+    *PENDING_SYSTEM_SOUNDS.lock().unwrap() = (None, None);
+    apply_system_sounds(&volume, level, mute)?;
+    unsafe { Ok(Some((volume.GetMasterVolume()?, volume.GetMute()?.as_bool()))) }
+}
+
+fn apply_system_sounds(volume: &ISimpleAudioVolume, level: Option<f32>, mute: Option<bool>) -> Result<()> {
+    unsafe {
+        if let Some(level) = level {
+            volume.SetMasterVolume(level.clamp(0.0, 1.0), &GUID::zeroed())?;
+        }
+        if let Some(mute) = mute {
+            volume.SetMute(BOOL::from(mute), &GUID::zeroed())?;
+        }
+    }
+    Ok(())
+}
+
+// For the session watcher's OnSessionCreated
+pub(crate) fn apply_pending_system_sounds(session: &IAudioSessionControl) -> Result<()> {
+    let control: IAudioSessionControl2 = session.cast()?;
+    if unsafe { control.IsSystemSoundsSession() } != S_OK {
+        return Ok(());
+    }
+    let (level, mute) = std::mem::take(&mut *PENDING_SYSTEM_SOUNDS.lock().unwrap());
+    apply_system_sounds(&control.cast()?, level, mute)
+}
+
+fn app_session(control: &IAudioSessionControl2) -> Result<AppSession> {
+    unsafe {
+        let pid = control.GetProcessId().unwrap_or(0);
+        let name = if control.IsSystemSoundsSession() == S_OK {
+            "System sounds".to_string()
+        } else {
+            window::process_name(pid).unwrap_or_else(|| format!("pid {pid}"))
+        };
+        let volume: ISimpleAudioVolume = control.cast()?;
+        Ok(AppSession {
+            session_id: session_instance_id(control).unwrap_or_default(),
+            pid,
+            name,
+            volume: volume.GetMasterVolume()?,
+            muted: volume.GetMute()?.as_bool(),
+        })
+    }
+}
+
+fn session_identifier(control: &IAudioSessionControl2) -> Option<String> {
+    unsafe {
+        let raw = control.GetSessionIdentifier().ok()?;
+        let id = raw.to_string().ok();
+        CoTaskMemFree(Some(raw.0 as _));
+        id
+    }
+}
+
+fn session_instance_id(control: &IAudioSessionControl2) -> Option<String> {
+    unsafe {
+        let raw = control.GetSessionInstanceIdentifier().ok()?;
+        let id = raw.to_string().ok();
+        CoTaskMemFree(Some(raw.0 as _));
+        id
+    }
+}
+
+fn set_matching_sessions_volume(level: f32, matches: impl Fn(&IAudioSessionControl2) -> bool) -> Result<bool> {
+    ensure_com_initialized()?;
+    unsafe {
+        let device = default_render_endpoint()?;
+        let manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, None)?;
+        let list = manager.GetSessionEnumerator()?;
+
+        let mut found = false;
+        for i in 0..list.GetCount()? {
+            let control: IAudioSessionControl2 = list.GetSession(i)?.cast()?;
+            if !matches(&control) {
+                continue;
+            }
+            let volume: ISimpleAudioVolume = control.cast()?;
+            volume.SetMasterVolume(level.clamp(0.0, 1.0), &GUID::zeroed())?;
+            found = true;
+        }
+        Ok(found)
+    }
+}
+
+// Endpoint operations the WS commands depend on; swapped for a mock in tests
+pub trait AudioBackend: Send + Sync {
+    fn get_volume_and_mute(&self) -> Result<(f32, bool)>;
+    fn set_volume(&self, level: f32) -> Result<()>;
+    fn set_mute(&self, mute: bool) -> Result<()>;
+    // console/communications/multimedia default device; All is expanded by the caller
+    fn get_role_volume_and_mute(&self, role: OutputRole) -> Result<(f32, bool)>;
+    fn set_role_volume(&self, role: OutputRole, level: f32) -> Result<()>;
+    fn set_role_mute(&self, role: OutputRole, mute: bool) -> Result<()>;
+    fn get_mic_mute(&self, device_id: Option<&str>) -> Result<bool>;
+    fn set_mic_mute(&self, device_id: Option<&str>, mute: bool) -> Result<()>;
+    fn get_mic_volume(&self) -> Result<f32>;
+    fn set_mic_volume(&self, level: f32) -> Result<()>;
+    fn get_peak_level(&self) -> Result<f32>;
+    fn list_input_devices(&self) -> Result<Vec<AudioDevice>>;
+    fn default_output_device(&self) -> Result<AudioDevice>;
+    fn default_input_device(&self) -> Result<Option<AudioDevice>>;
+    fn list_output_devices(&self) -> Result<Vec<OutputDevice>>;
+    fn set_default_output(&self, device_id: &str, role: OutputRole) -> Result<()>;
+    fn list_capture_devices(&self) -> Result<Vec<CaptureDevice>>;
+    fn set_default_capture(&self, device_id: &str) -> Result<()>;
+    fn device_state(&self, device_id: &str) -> Result<DeviceState>;
+    fn set_endpoint_volume(&self, device_id: &str, level: f32) -> Result<bool>;
+    // None/false when the endpoint isn't active
+    fn set_endpoint_mute(&self, device_id: &str, mute: bool) -> Result<bool>;
+    fn get_endpoint_volume_and_mute(&self, device_id: &str) -> Result<Option<(f32, bool)>>;
+    fn get_volume_db(&self) -> Result<f32>;
+    fn set_volume_db(&self, db: f32) -> Result<()>;
+    fn get_volume_range_db(&self) -> Result<(f32, f32)>;
+    // None/false on a mono endpoint
+    fn get_balance(&self) -> Result<Option<f32>>;
+    fn set_balance(&self, value: f32) -> Result<bool>;
+    // per-app sessions on the default output
+    fn list_app_sessions(&self) -> Result<Vec<AppSession>>;
+    fn list_audio_sessions(&self) -> Result<Vec<SessionInfo>>;
+    fn set_process_volume(&self, pid: u32, level: f32) -> Result<bool>;
+    fn set_app_volume(&self, app_id: &str, level: f32) -> Result<bool>;
+    fn mute_session(&self, session_id: &str, mute: bool) -> Result<Vec<AppSession>>;
+    fn mute_process_sessions(&self, process_name: &str, mute: bool) -> Result<Vec<AppSession>>;
+    // one outcome per entry, in order
+    fn apply_session_preset(&self, entries: &[PresetEntry]) -> Result<Vec<PresetOutcome>>;
+    // None while Windows has no system sounds session yet
+    fn get_system_sounds(&self) -> Result<Option<(f32, bool)>>;
+    fn set_system_sounds(&self, level: Option<f32>, mute: Option<bool>) -> Result<Option<(f32, bool)>>;
+}
+
+// Default render/capture endpoints via Core Audio; every call runs on the audio thread
+pub struct SystemAudio;
+
+impl AudioBackend for SystemAudio {
+    fn get_volume_and_mute(&self) -> Result<(f32, bool)> {
+        on_audio_thread(get_volume_and_mute)
+    }
+
+    fn set_volume(&self, level: f32) -> Result<()> {
+        on_audio_thread(move || set_volume(level))
+    }
+
+    fn set_mute(&self, mute: bool) -> Result<()> {
+        on_audio_thread(move || set_mute(mute))
+    }
+
+    fn get_role_volume_and_mute(&self, role: OutputRole) -> Result<(f32, bool)> {
+        on_audio_thread(move || get_role_volume_and_mute(role))
+    }
+
+    fn set_role_volume(&self, role: OutputRole, level: f32) -> Result<()> {
+        on_audio_thread(move || set_role_volume(role, level))
+    }
+
+    fn set_role_mute(&self, role: OutputRole, mute: bool) -> Result<()> {
+        on_audio_thread(move || set_role_mute(role, mute))
+    }
+
+    fn get_mic_mute(&self, device_id: Option<&str>) -> Result<bool> {
+        let device_id = device_id.map(str::to_owned);
+        on_audio_thread(move || get_mic_mute(device_id.as_deref()))
+    }
+
+    fn set_mic_mute(&self, device_id: Option<&str>, mute: bool) -> Result<()> {
+        let device_id = device_id.map(str::to_owned);
+        on_audio_thread(move || set_mic_mute(device_id.as_deref(), mute))
+    }
+
+    fn get_mic_volume(&self) -> Result<f32> {
+        on_audio_thread(get_mic_volume)
+    }
+
+    fn set_mic_volume(&self, level: f32) -> Result<()> {
+        on_audio_thread(move || set_mic_volume(level))
+    }
+
+    fn get_peak_level(&self) -> Result<f32> {
+        on_audio_thread(get_peak_level)
+    }
+
+    fn list_input_devices(&self) -> Result<Vec<AudioDevice>> {
+        on_audio_thread(list_input_devices)
+    }
+
+    fn default_output_device(&self) -> Result<AudioDevice> {
+        on_audio_thread(default_output_device)
+    }
+
+    fn default_input_device(&self) -> Result<Option<AudioDevice>> {
+        on_audio_thread(default_input_device)
+    }
+
+    fn list_output_devices(&self) -> Result<Vec<OutputDevice>> {
+        on_audio_thread(list_output_devices)
+    }
+
+    fn set_default_output(&self, device_id: &str, role: OutputRole) -> Result<()> {
+        let device_id = device_id.to_owned();
+        on_audio_thread(move || set_default_output(&device_id, role))
+    }
+
+    fn list_capture_devices(&self) -> Result<Vec<CaptureDevice>> {
+        on_audio_thread(list_capture_devices)
+    }
+
+    fn set_default_capture(&self, device_id: &str) -> Result<()> {
+        let device_id = device_id.to_owned();
+        on_audio_thread(move || set_default_capture(&device_id))
+    }
+
+    fn device_state(&self, device_id: &str) -> Result<DeviceState> {
+        let device_id = device_id.to_owned();
+        on_audio_thread(move || device_state(&device_id))
+    }
+
+    fn set_endpoint_volume(&self, device_id: &str, level: f32) -> Result<bool> {
+        let device_id = device_id.to_owned();
+        on_audio_thread(move || set_endpoint_volume(&device_id, level))
+    }
+
+    fn set_endpoint_mute(&self, device_id: &str, mute: bool) -> Result<bool> {
+        let device_id = device_id.to_owned();
+        on_audio_thread(move || set_endpoint_mute(&device_id, mute))
+    }
+
+    fn get_endpoint_volume_and_mute(&self, device_id: &str) -> Result<Option<(f32, bool)>> {
+        let device_id = device_id.to_owned();
+        on_audio_thread(move || get_endpoint_volume_and_mute(&device_id))
+    }
+
+    fn get_volume_db(&self) -> Result<f32> {
+        on_audio_thread(get_volume_db)
+    }
+
+    fn set_volume_db(&self, db: f32) -> Result<()> {
+        on_audio_thread(move || set_volume_db(db))
+    }
+
+    fn get_volume_range_db(&self) -> Result<(f32, f32)> {
+        on_audio_thread(get_volume_range_db)
+    }
+
+    fn get_balance(&self) -> Result<Option<f32>> {
+        on_audio_thread(get_balance)
+    }
+
+    fn set_balance(&self, value: f32) -> Result<bool> {
+        on_audio_thread(move || set_balance(value))
+    }
+
+    fn list_app_sessions(&self) -> Result<Vec<AppSession>> {
+        on_audio_thread(list_app_sessions)
+    }
+
+    fn list_audio_sessions(&self) -> Result<Vec<SessionInfo>> {
+        on_audio_thread(list_audio_sessions)
+    }
+
+    fn set_process_volume(&self, pid: u32, level: f32) -> Result<bool> {
+        on_audio_thread(move || set_process_volume(pid, level))
+    }
+
+    fn set_app_volume(&self, app_id: &str, level: f32) -> Result<bool> {
+        let app_id = app_id.to_owned();
+        on_audio_thread(move || set_app_volume(&app_id, level))
+    }
+
+    fn mute_session(&self, session_id: &str, mute: bool) -> Result<Vec<AppSession>> {
+        let session_id = session_id.to_owned();
+        on_audio_thread(move || mute_session(&session_id, mute))
+    }
+
+    fn mute_process_sessions(&self, process_name: &str, mute: bool) -> Result<Vec<AppSession>> {
+        let process_name = process_name.to_owned();
+        on_audio_thread(move || mute_process_sessions(&process_name, mute))
+    }
+
+    fn apply_session_preset(&self, entries: &[PresetEntry]) -> Result<Vec<PresetOutcome>> {
+        let entries = entries.to_vec();
+        on_audio_thread(move || apply_session_preset(&entries))
+    }
+
+    fn get_system_sounds(&self) -> Result<Option<(f32, bool)>> {
+        on_audio_thread(get_system_sounds)
+    }
+
+    fn set_system_sounds(&self, level: Option<f32>, mute: Option<bool>) -> Result<Option<(f32, bool)>> {
+        on_audio_thread(move || set_system_sounds(level, mute))
     }
 }
@@ -0,0 +1,144 @@
+// src/config.rs
+#![cfg(windows)]
+
+use anyhow::Result;
+use directories_next::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::media::MediaBackend;
+use crate::server::pairing::{CodeFormat, DEFAULT_RECONNECT_GRACE};
+
+pub const DEFAULT_DISCOVERY_PORT: u16 = 45321;
+
+// The running server reads it per request, so edits apply live except for what it binds
+// to at startup (port, interface, origins, client limit)
+pub type SharedConfig = Arc<RwLock<Config>>;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Config {
+    // Fixed pairing code for kiosk setups; None = rotating code
+    pub static_pairing_code: Option<String>,
+
+    // Length and charset of rotating codes
+    pub pairing_code_format: CodeFormat,
+
+    // UDP port the discovery responder listens on
+    pub discovery_port: u16,
+
+    // Upper bound on concurrent WebSocket clients
+    pub max_clients: usize,
+
+    // Hold first pairings of unknown devices until approved in the GUI
+    pub require_pairing_approval: bool,
+
+    // Seconds a dropped session is kept for the same device to resume it
+    pub reconnect_grace_secs: u64,
+
+    // Seconds without any frame (pongs included) before a connection is dropped
+    pub read_timeout_secs: u64,
+
+    // Folders OpenPath may open or reveal files under; empty = OpenPath disabled
+    pub open_path_roots: Vec<String>,
+
+    // Injected key/mouse events allowed per second across all clients; bursts may reach one
+    // type_text at its length cap
+    pub input_events_per_sec: u32,
+
+    // VolumeUp/VolumeDown step when the client sends no delta
+    pub volume_step: f32,
+
+    // FastForward/Rewind jump when the client sends no ms
+    pub seek_step_ms: u64,
+
+    // Longer side, in pixels, of screenshots sent back to clients; larger captures are scaled down
+    pub screenshot_max_dimension: u32,
+
+    // How media transport commands without a session target are delivered
+    pub media_backend: MediaBackend,
+
+    // Command categories (scope names, see auth_store::ALL_SCOPES) refused for every device
+    pub disabled_categories: Vec<String>,
+
+    // Lets devices with the power scope shut down, restart or suspend the PC
+    pub power_commands_enabled: bool,
+
+    // Network interface (get_if_addrs name) discovery is pinned to; None = all interfaces
+    pub bind_interface: Option<String>,
+    // Also bind the WebSocket server to `bind_interface` instead of 0.0.0.0
+    pub bind_ws_to_interface: bool,
+
+    // Listen on a free port instead of failing when the WS port is taken
+    pub fallback_to_free_port: bool,
+
+    // Extra Origin values accepted on the WS upgrade; absent Origin and localhost are always allowed
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            static_pairing_code: None,
+            pairing_code_format: CodeFormat::default(),
+            discovery_port: DEFAULT_DISCOVERY_PORT,
+            max_clients: 8,
+            require_pairing_approval: false,
+            reconnect_grace_secs: DEFAULT_RECONNECT_GRACE.as_secs(),
+            read_timeout_secs: 30,
+            open_path_roots: Vec::new(),
+            input_events_per_sec: 50,
+            volume_step: 0.05,
+            seek_step_ms: 30_000,
+            screenshot_max_dimension: 1920,
+            media_backend: MediaBackend::default(),
+            disabled_categories: Vec::new(),
+            power_commands_enabled: false,
+            bind_interface: None,
+            bind_ws_to_interface: false,
+            fallback_to_free_port: false,
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // disabled_categories, plus "power" until power commands are switched on
+    pub fn refused_categories(&self) -> Vec<String> {
+        let mut refused = self.disabled_categories.clone();
+        if !self.power_commands_enabled {
+            refused.push("power".to_string());
+        }
+        refused
+    }
+}
+
+// %APPDATA%/FOSS-Deck/<name> (falls back to the working directory)
+pub fn data_file(name: &str) -> PathBuf {
+    if let Some(proj_dirs) = ProjectDirs::from("org", "FOSS-Deck", "FOSS-Deck") {
+        let dir = proj_dirs.data_dir();
+        let _ = fs::create_dir_all(dir);
+        dir.join(name)
+    } else {
+        PathBuf::from(name)
+    }
+}
+
+pub fn config_path() -> PathBuf {
+    data_file("config.json")
+}
+
+pub fn load_config(path: &Path) -> Config {
+    match fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s).unwrap_or_default(),
+        Err(_) => Config::default(),
+    }
+}
+
+pub fn save_config(path: &Path, config: &Config) -> Result<()> {
+    let s = serde_json::to_string_pretty(config)?;
+    fs::write(path, s)?;
+    Ok(())
+}
@@ -1,39 +1,121 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use get_if_addrs::{get_if_addrs, IfAddr};
+use std::net::Ipv4Addr;
 use tokio::sync::oneshot;
 use whoami::fallible;
 
-pub async fn run_discovery_server(ws_port: u16, mut shutdown_rx: oneshot::Receiver<()>) -> Result<()> {
+use crate::server::identity::ServerIdentity;
+
+// Non-loopback IPv4 interfaces, as (name, address), that discovery can be pinned to
+pub fn list_interfaces() -> Vec<(String, Ipv4Addr)> {
+    get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|iface| !iface.is_loopback())
+        .filter_map(|iface| match iface.addr {
+            IfAddr::V4(v4) => Some((iface.name, v4.ip)),
+            IfAddr::V6(_) => None,
+        })
+        .collect()
+}
+
+pub fn interface_ip(name: &str) -> Option<Ipv4Addr> {
+    list_interfaces().into_iter().find(|(n, _)| n == name).map(|(_, ip)| ip)
+}
+
+// `interface` = None answers on every interface; Some pins the socket (and advertised IP) to one
+pub async fn run_discovery_server(
+    ws_port: u16,
+    discovery_port: u16,
+    interface: Option<String>,
+    identity: ServerIdentity,
+    mut shutdown_rx: oneshot::Receiver<()>,
+) -> Result<()> {
     use log::{error, info};
     use serde_json::json;
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+    use tokio::time::timeout;
     use tokio::{net::UdpSocket, select};
 
-    const DISCOVERY_PORT: u16 = 45321;
     const QUERY: &str = "FOSSDECK_DISCOVERY_V1?";
+    const MAX_NONCE_LEN: usize = 64;
+    // scanners that asked this recently get the shutdown notice directly on their scan socket
+    const RECENT_PEER_WINDOW: Duration = Duration::from_secs(30);
+    const MAX_RECENT_PEERS: usize = 32;
+
+    let bind_ip = match &interface {
+        Some(name) => Some(interface_ip(name).ok_or_else(|| anyhow!("network interface {name} not found"))?),
+        None => None,
+    };
 
-    let sock = UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)).await?;
-    info!("Discovery listening on UDP {DISCOVERY_PORT}");
+    let sock = UdpSocket::bind((bind_ip.unwrap_or(Ipv4Addr::UNSPECIFIED), discovery_port)).await?;
+    sock.set_broadcast(true)?;
+    info!("Discovery listening on UDP {}:{discovery_port}", bind_ip.unwrap_or(Ipv4Addr::UNSPECIFIED));
 
     let mut buf = [0u8; 1024];
+    let mut recent_peers: VecDeque<(SocketAddr, Instant)> = VecDeque::new();
 
     loop {
         select! {
-            _ = &mut shutdown_rx => break,
+            _ = &mut shutdown_rx => {
+                let name = fallible::hostname().unwrap_or_else(|_| "unknown".to_string());
+                let notice = json!({
+                    "type": "shutdown",
+                    "name": name,
+                    "port": ws_port,
+                    "identity_fingerprint": identity.identity_fingerprint(),
+                }).to_string();
+
+                // best-effort: a send that fails or stalls must not hold up shutdown
+                let mut targets: Vec<SocketAddr> = recent_peers.iter().map(|(p, _)| *p).collect();
+                targets.push((Ipv4Addr::BROADCAST, discovery_port).into());
+                let _ = timeout(Duration::from_millis(500), async {
+                    for t in targets {
+                        if let Err(e) = sock.send_to(notice.as_bytes(), t).await {
+                            error!("discovery shutdown notice to {t} failed: {e}");
+                        }
+                    }
+                }).await;
+                break;
+            }
             res = sock.recv_from(&mut buf) => {
                 match res {
                     Ok((n, peer)) => {
                         let msg = std::str::from_utf8(&buf[..n]).unwrap_or_default();
-                        if msg == QUERY {
-                            let name = fallible::hostname().unwrap_or_else(|_| "unknown".to_string());
-                            let reply = json!({
-                                "name": name,
-                                "proto": "ws",
-                                "port": ws_port,
-                                "path": "/ws",
-                                "version": env!("CARGO_PKG_VERSION")
-                            }).to_string();
-                            if let Err(e) = sock.send_to(reply.as_bytes(), peer).await {
-                                error!("discovery send_to error: {e}");
-                            }
+                        // query may carry a client nonce after the '?' so signed replies can't be replayed
+                        let nonce = match msg.strip_prefix(QUERY) {
+                            Some(nonce) if nonce.len() <= MAX_NONCE_LEN => nonce,
+                            _ => continue,
+                        };
+
+                        recent_peers.retain(|(p, at)| *p != peer && at.elapsed() < RECENT_PEER_WINDOW);
+                        if recent_peers.len() == MAX_RECENT_PEERS {
+                            recent_peers.pop_front();
+                        }
+                        recent_peers.push_back((peer, Instant::now()));
+
+                        let name = fallible::hostname().unwrap_or_else(|_| "unknown".to_string());
+                        let path = "/ws";
+                        let version = env!("CARGO_PKG_VERSION");
+                        let fingerprint = identity.identity_fingerprint().unwrap_or_default();
+                        // clients that pinned the identity key verify this with it
+                        let signed = format!("{name}|{ws_port}|{path}|{version}|{nonce}|{fingerprint}");
+                        let reply = json!({
+                            "name": name,
+                            "proto": "ws",
+                            "port": ws_port,
+                            "path": path,
+                            "version": version,
+                            "nonce": nonce,
+                            "identity_fingerprint": fingerprint,
+                            "sig": identity.sign_discovery(&signed),
+                            // only set when pinned; otherwise the reply's source address is the host
+                            "ip": bind_ip,
+                        }).to_string();
+                        if let Err(e) = sock.send_to(reply.as_bytes(), peer).await {
+                            error!("discovery send_to error: {e}");
                         }
                     }
                     Err(e) => { error!("discovery recv_from error: {e}"); }
@@ -1,16 +1,35 @@
 // src/gui.rs
 #![cfg(windows)]
 
-use std::sync::{Arc, Mutex};
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use env_logger;
-use log::info;
+use log::{error, info};
 use tokio::{runtime::Runtime, sync::oneshot};
 
-use crate::discovery::run_discovery_server;
-use crate::server::{generate_pairing_code, run_ws_server, PairingState};
+use crate::audio::{AudioBackend, SystemAudio};
+use crate::media::MediaBackend;
+use crate::{media, system, window};
+use crate::config::{config_path, load_config, save_config, Config, SharedConfig};
+use crate::discovery::{list_interfaces, run_discovery_server};
+use crate::server::audit::AuditEntry;
+use crate::server::auth_store::{normalize_device_name, ALL_SCOPES};
+use crate::server::identity::{identity_path, load_or_create_identity, ServerIdentity};
+use crate::server::groups::{groups_path, GroupStore};
+use crate::server::launchers::{launchers_path, LauncherStore};
+use crate::server::power::PowerSchedule;
+use crate::server::presets::{presets_path, PresetStore};
+use crate::server::{
+    generate_pairing_code, is_valid_static_code, run_ws_server, CodeCharset, PairingState, CODE_LENGTHS,
+};
 
 const PORT: u16 = 3030;
+// security log entries shown in the GUI
+const AUDIT_TAIL_LEN: usize = 50;
 
 pub fn run_gui() {
     env_logger::try_init().ok();
@@ -35,19 +54,58 @@ struct App {
     discovery_tx: Option<oneshot::Sender<()>>,
 
     last_status: String,
+    // port the WS server actually listens on (may differ from PORT after fallback)
+    ws_port: u16,
 
     pairing: Arc<Mutex<PairingState>>,
+    identity: ServerIdentity,
+
+    config_path: PathBuf,
+    config: Config,
+    // what the running server reads; updated on every save
+    live_config: SharedConfig,
+    // set by a phone's panic_mute; only cleared by the phone or the button here
+    panic_mute: Arc<AtomicBool>,
+    // a phone's delayed shutdown/restart/sleep, cancelable here
+    power: PowerSchedule,
+
+    static_code_input: String,
+    static_code_error: Option<String>,
+
+    // last security events, reloaded on demand
+    audit_tail: Vec<AuditEntry>,
+
+    // (name, IPv4) choices for interface pinning
+    interfaces: Vec<(String, Ipv4Addr)>,
+
+    // outcome of the last diagnostics button, filled in from the runtime
+    diagnostic: Arc<Mutex<Option<String>>>,
+
+    // (device_id, edited name) while a device row is being renamed
+    renaming: Option<(String, String)>,
 }
 
 impl App {
     fn new() -> Self {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
+            .on_thread_stop(crate::audio::release_com)
             .build()
             .unwrap();
 
-        let initial_code = generate_pairing_code();
-        let pairing = Arc::new(Mutex::new(PairingState::new(initial_code)));
+        let config_path = config_path();
+        let config = load_config(&config_path);
+
+        let initial_code = generate_pairing_code(config.pairing_code_format);
+        let mut pairing_state = PairingState::new(initial_code);
+        pairing_state.set_code_format(config.pairing_code_format);
+        if let Some(code) = config.static_pairing_code.clone().filter(|c| is_valid_static_code(c)) {
+            pairing_state.set_static_code(Some(code));
+        }
+        pairing_state.set_require_approval(config.require_pairing_approval);
+        pairing_state.set_reconnect_grace(Duration::from_secs(config.reconnect_grace_secs));
+        let pairing = Arc::new(Mutex::new(pairing_state));
+        let identity = load_or_create_identity(&identity_path());
 
         Self {
             rt,
@@ -56,10 +114,52 @@ impl App {
             server_tx: None,
             discovery_tx: None,
             last_status: "Idle".into(),
+            ws_port: PORT,
             pairing,
+            identity,
+            static_code_input: config.static_pairing_code.clone().unwrap_or_default(),
+            static_code_error: None,
+            audit_tail: Vec::new(),
+            interfaces: list_interfaces(),
+            diagnostic: Arc::new(Mutex::new(None)),
+            renaming: None,
+            config_path,
+            live_config: Arc::new(RwLock::new(config.clone())),
+            panic_mute: Arc::new(AtomicBool::new(false)),
+            power: PowerSchedule::default(),
+            config,
+        }
+    }
+
+    fn save_config(&mut self) {
+        *self.live_config.write().unwrap() = self.config.clone();
+        if let Err(e) = save_config(&self.config_path, &self.config) {
+            self.last_status = format!("Failed to save config: {e}");
         }
     }
 
+    fn set_static_code(&mut self) {
+        let code = self.static_code_input.trim().to_string();
+        if !is_valid_static_code(&code) {
+            self.static_code_error = Some("Static code must be 6 to 10 digits.".into());
+            return;
+        }
+        self.static_code_error = None;
+        self.pairing.lock().unwrap().set_static_code(Some(code.clone()));
+        self.config.static_pairing_code = Some(code);
+        self.save_config();
+        info!("Static pairing code set");
+    }
+
+    fn clear_static_code(&mut self) {
+        self.static_code_input.clear();
+        self.static_code_error = None;
+        self.pairing.lock().unwrap().set_static_code(None);
+        self.config.static_pairing_code = None;
+        self.save_config();
+        info!("Static pairing code cleared");
+    }
+
     fn start_server(&mut self) {
         if self.server_on {
             return;
@@ -69,13 +169,38 @@ impl App {
         self.server_tx = Some(tx);
 
         let pairing = self.pairing.clone();
-        self.rt.spawn(async move {
-            let _ = run_ws_server(PORT, rx, pairing).await;
-        });
-
-        self.server_on = true;
-        self.last_status = format!("Server running on ws://0.0.0.0:{}/ws", PORT);
-        info!("{}", self.last_status);
+        let identity = self.identity.clone();
+        let config = self.live_config.clone();
+        let presets = PresetStore::load(presets_path());
+        let groups = GroupStore::load(groups_path());
+        let launchers = LauncherStore::load(launchers_path());
+        let bound = self.rt.block_on(run_ws_server(
+            PORT,
+            rx,
+            pairing,
+            identity,
+            config,
+            Arc::new(SystemAudio),
+            presets,
+            groups,
+            launchers,
+            self.panic_mute.clone(),
+            self.power.clone(),
+        ));
+
+        match bound {
+            Ok(addr) => {
+                self.server_on = true;
+                self.ws_port = addr.port();
+                self.last_status = format!("Server running on ws://{addr}/ws");
+                info!("{}", self.last_status);
+            }
+            Err(e) => {
+                self.server_tx = None;
+                self.last_status = format!("Server failed to start: {e}");
+                error!("{}", self.last_status);
+            }
+        }
     }
 
     fn stop_server(&mut self) {
@@ -96,8 +221,14 @@ impl App {
         let (tx, rx) = oneshot::channel::<()>();
         self.discovery_tx = Some(tx);
 
+        let ws_port = self.ws_port;
+        let discovery_port = self.config.discovery_port;
+        let interface = self.config.bind_interface.clone();
+        let identity = self.identity.clone();
         self.rt.spawn(async move {
-            let _ = run_discovery_server(PORT, rx).await;
+            if let Err(e) = run_discovery_server(ws_port, discovery_port, interface, identity, rx).await {
+                error!("Discovery failed on UDP {discovery_port}: {e}");
+            }
         });
 
         self.discovery_on = true;
@@ -111,16 +242,56 @@ impl App {
         self.discovery_on = false;
         info!("Discovery disabled");
     }
+
+    // Runs a local action off the UI thread, bypassing the network, and reports how it went
+    fn run_diagnostic(&self, ctx: &eframe::egui::Context, label: &'static str, action: fn() -> anyhow::Result<()>) {
+        *self.diagnostic.lock().unwrap() = Some(format!("{label}: running..."));
+        let result = self.diagnostic.clone();
+        let ctx = ctx.clone();
+        self.rt.spawn(async move {
+            let outcome = match tokio::task::spawn_blocking(action).await {
+                Ok(Ok(())) => format!("{label}: OK"),
+                Ok(Err(e)) => format!("{label} failed: {e:#}"),
+                Err(e) => format!("{label} failed: {e}"),
+            };
+            *result.lock().unwrap() = Some(outcome);
+            ctx.request_repaint();
+        });
+    }
 }
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
+        // Pairing requests arrive from the server side; poll so the prompt shows up
+        if self.server_on {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+
         eframe::egui::TopBottomPanel::top("top").show(ctx, |ui| {
             ui.heading("FOSS-Deck PC");
             ui.label("Service Control Panel");
         });
 
         eframe::egui::CentralPanel::default().show(ctx, |ui| {
+            if self.panic_mute.load(Ordering::SeqCst) {
+                ui.horizontal(|ui| {
+                    ui.colored_label(eframe::egui::Color32::RED, "Panic mute is on: the microphone is held muted.");
+                    if ui.button("Release").clicked() {
+                        self.panic_mute.store(false, Ordering::SeqCst);
+                    }
+                });
+                ui.separator();
+            }
+            if let Some((action, secs)) = self.power.pending() {
+                ui.horizontal(|ui| {
+                    ui.colored_label(eframe::egui::Color32::RED, format!("Pending {} in {secs} s.", action.as_str()));
+                    if ui.button("Cancel").clicked() {
+                        self.power.cancel();
+                    }
+                });
+                ui.separator();
+            }
+
             // --- Service toggle ---
             let mut srv = self.server_on;
             if ui.checkbox(&mut srv, "Enable WebSocket Server").changed() {
@@ -147,6 +318,165 @@ impl eframe::App for App {
                 }
             }
 
+            // --- Discovery port (applies next time discovery starts) ---
+            ui.horizontal(|ui| {
+                ui.label("Discovery UDP port:");
+                let mut port = self.config.discovery_port;
+                let resp = ui.add_enabled(
+                    !self.discovery_on,
+                    eframe::egui::DragValue::new(&mut port).range(1..=u16::MAX),
+                );
+                if resp.changed() {
+                    self.config.discovery_port = port;
+                    self.save_config();
+                }
+                resp.on_disabled_hover_text("Disable discoverability to change the port.");
+            });
+
+            // --- Interface pinning (applies next time discovery / the server starts) ---
+            ui.horizontal(|ui| {
+                ui.label("Network interface:");
+                let current = self.config.bind_interface.clone();
+                let mut selected = current.clone();
+                ui.add_enabled_ui(!self.discovery_on && !self.server_on, |ui| {
+                    eframe::egui::ComboBox::from_id_salt("bind_interface")
+                        .selected_text(current.as_deref().unwrap_or("All interfaces"))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut selected, None, "All interfaces");
+                            for (name, ip) in &self.interfaces {
+                                ui.selectable_value(&mut selected, Some(name.clone()), format!("{name} ({ip})"));
+                            }
+                        });
+                })
+                .response
+                .on_disabled_hover_text("Stop the server to change the interface.");
+                if selected != current {
+                    self.config.bind_interface = selected;
+                    self.save_config();
+                }
+                if ui.add_enabled(!self.server_on, eframe::egui::Button::new("Rescan")).clicked() {
+                    self.interfaces = list_interfaces();
+                }
+            });
+            let mut bind_ws = self.config.bind_ws_to_interface;
+            let resp = ui.add_enabled(
+                !self.server_on && self.config.bind_interface.is_some(),
+                eframe::egui::Checkbox::new(&mut bind_ws, "Bind the WebSocket server to this interface too"),
+            );
+            if resp.changed() {
+                self.config.bind_ws_to_interface = bind_ws;
+                self.save_config();
+            }
+
+            let mut fallback = self.config.fallback_to_free_port;
+            let resp = ui.add_enabled(
+                !self.server_on,
+                eframe::egui::Checkbox::new(&mut fallback, format!("Use a free port if {PORT} is taken")),
+            );
+            if resp.changed() {
+                self.config.fallback_to_free_port = fallback;
+                self.save_config();
+            }
+
+            // --- Client limit (applies next time the server starts) ---
+            ui.horizontal(|ui| {
+                ui.label("Max clients:");
+                let mut max = self.config.max_clients;
+                let resp = ui.add_enabled(
+                    !self.server_on,
+                    eframe::egui::DragValue::new(&mut max).range(1..=64),
+                );
+                if resp.changed() {
+                    self.config.max_clients = max;
+                    self.save_config();
+                }
+                resp.on_disabled_hover_text("Stop the server to change the limit.");
+            });
+
+            // --- Server-wide command categories, above per-device scopes ---
+            ui.horizontal(|ui| {
+                ui.label("Enabled categories:");
+                // admin gates data in replies rather than whole commands
+                // power has its own switch below
+                for category in ALL_SCOPES.iter().filter(|c| **c != "admin" && **c != "power") {
+                    let mut on = !self.config.disabled_categories.iter().any(|c| c == category);
+                    if ui.checkbox(&mut on, *category).changed() {
+                        if on {
+                            self.config.disabled_categories.retain(|c| c != category);
+                        } else {
+                            self.config.disabled_categories.push(category.to_string());
+                        }
+                        self.save_config();
+                    }
+                }
+            });
+            let mut power = self.config.power_commands_enabled;
+            if ui.checkbox(&mut power, "Allow shutdown, restart and sleep from devices with the power scope").changed() {
+                self.config.power_commands_enabled = power;
+                self.save_config();
+            }
+
+            // --- Live settings (the running server picks these up on the next command) ---
+            ui.horizontal(|ui| {
+                ui.label("Volume step (%):");
+                let mut step = (self.config.volume_step * 100.0).round() as u32;
+                if ui.add(eframe::egui::DragValue::new(&mut step).range(1..=25)).changed() {
+                    self.config.volume_step = step as f32 / 100.0;
+                    self.save_config();
+                }
+                ui.label("Input events/s:");
+                let mut rate = self.config.input_events_per_sec;
+                if ui.add(eframe::egui::DragValue::new(&mut rate).range(1..=500)).changed() {
+                    self.config.input_events_per_sec = rate;
+                    self.save_config();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Media buttons:");
+                let mut backend = self.config.media_backend;
+                ui.radio_value(&mut backend, MediaBackend::Keys, "Media keys");
+                ui.radio_value(&mut backend, MediaBackend::Smtc, "Media session");
+                ui.radio_value(&mut backend, MediaBackend::Auto, "Session, then keys");
+                if backend != self.config.media_backend {
+                    self.config.media_backend = backend;
+                    self.save_config();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Skip forward/back (s):");
+                let mut secs = self.config.seek_step_ms / 1000;
+                if ui.add(eframe::egui::DragValue::new(&mut secs).range(1..=600)).changed() {
+                    self.config.seek_step_ms = secs * 1000;
+                    self.save_config();
+                }
+                ui.label("Screenshot size (px):");
+                let mut max = self.config.screenshot_max_dimension;
+                if ui.add(eframe::egui::DragValue::new(&mut max).range(320..=7680)).changed() {
+                    self.config.screenshot_max_dimension = max;
+                    self.save_config();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Read timeout (s):");
+                let mut timeout = self.config.read_timeout_secs;
+                if ui.add(eframe::egui::DragValue::new(&mut timeout).range(3..=600)).changed() {
+                    self.config.read_timeout_secs = timeout;
+                    self.save_config();
+                }
+                ui.label("(new connections)");
+            });
+
+            // --- How long a dropped device keeps its session ---
+            ui.horizontal(|ui| {
+                ui.label("Reconnect grace (s):");
+                let mut grace = self.config.reconnect_grace_secs;
+                if ui.add(eframe::egui::DragValue::new(&mut grace).range(0..=300)).changed() {
+                    self.config.reconnect_grace_secs = grace;
+                    self.pairing.lock().unwrap().set_reconnect_grace(Duration::from_secs(grace));
+                    self.save_config();
+                }
+            });
+
             ui.separator();
             ui.label(format!("Status: {}", self.last_status));
 
@@ -165,7 +495,31 @@ impl eframe::App for App {
                 )
             };
 
+            // --- Pending first-time pairings ---
+            let pending = self.pairing.lock().unwrap().pending_approvals();
+            for p in pending {
+                ui.horizontal(|ui| {
+                    ui.colored_label(
+                        eframe::egui::Color32::YELLOW,
+                        format!(
+                            "Pairing request: {} ({})",
+                            p.device_name.as_deref().unwrap_or(&p.device_id),
+                            p.ip
+                        ),
+                    );
+                    if ui.button("Approve").clicked() {
+                        self.pairing.lock().unwrap().resolve_approval(p.id, true);
+                    }
+                    if ui.button("Deny").clicked() {
+                        self.pairing.lock().unwrap().resolve_approval(p.id, false);
+                    }
+                });
+            }
+
             ui.label(format!("Pairing code: {}", code));
+            if let Some(fp) = self.identity.identity_fingerprint() {
+                ui.label(format!("Identity key fingerprint: {}", &fp[..16]));
+            }
             ui.label(format!("Authorized devices stored: {}", authorized_list_len));
 
             if let Some(id) = &active_id {
@@ -177,6 +531,114 @@ impl eframe::App for App {
                 ui.label(format!("Active client IP: {}", ip));
             }
 
+            // --- Rotating code format ---
+            ui.horizontal(|ui| {
+                let mut format = self.config.pairing_code_format;
+                ui.label("Code length:");
+                let mut changed = ui.add(eframe::egui::DragValue::new(&mut format.length).range(CODE_LENGTHS)).changed();
+                let mut letters = format.charset == CodeCharset::Alphanumeric;
+                if ui.checkbox(&mut letters, "Letters and digits").changed() {
+                    format.charset = if letters { CodeCharset::Alphanumeric } else { CodeCharset::Numeric };
+                    changed = true;
+                }
+                if changed {
+                    self.config.pairing_code_format = format;
+                    self.pairing.lock().unwrap().set_code_format(format);
+                    self.save_config();
+                }
+            });
+
+            // --- Static pairing code ---
+            ui.horizontal(|ui| {
+                ui.label("Static code:");
+                ui.text_edit_singleline(&mut self.static_code_input);
+                if ui.button("Set").clicked() {
+                    self.set_static_code();
+                }
+                if ui.button("Clear").clicked() {
+                    self.clear_static_code();
+                }
+            });
+            if let Some(err) = &self.static_code_error {
+                ui.colored_label(eframe::egui::Color32::RED, err);
+            }
+            if self.config.static_pairing_code.is_some() {
+                ui.colored_label(
+                    eframe::egui::Color32::YELLOW,
+                    "Warning: a static code never rotates and is less secure than the default.",
+                );
+            }
+
+            let mut approval = self.config.require_pairing_approval;
+            if ui.checkbox(&mut approval, "Require approval for new devices").changed() {
+                self.config.require_pairing_approval = approval;
+                self.pairing.lock().unwrap().set_require_approval(approval);
+                self.save_config();
+            }
+
+            ui.separator();
+            let security_log = ui.collapsing("Security log", |ui| {
+                if ui.button("Refresh").clicked() {
+                    self.audit_tail = self.pairing.lock().unwrap().audit().tail(AUDIT_TAIL_LEN);
+                }
+                if self.audit_tail.is_empty() {
+                    ui.label("(no security events)");
+                }
+                eframe::egui::ScrollArea::vertical()
+                    .id_salt("security_log")
+                    .max_height(140.0)
+                    .show(ui, |ui| {
+                        for e in self.audit_tail.iter().rev() {
+                            ui.label(format!(
+                                "{}  {}  {}  {}",
+                                e.ts,
+                                e.event,
+                                e.ip.as_deref().unwrap_or("-"),
+                                e.device_id.as_deref().unwrap_or("-"),
+                            ));
+                        }
+                    });
+            });
+            if security_log.header_response.clicked() {
+                self.audit_tail = self.pairing.lock().unwrap().audit().tail(AUDIT_TAIL_LEN);
+            }
+
+            ui.separator();
+            ui.collapsing("Diagnostics", |ui| {
+                ui.label("Runs directly on this PC, without the phone or network.");
+                ui.horizontal(|ui| {
+                    if ui.button("Volume 50%").clicked() {
+                        self.run_diagnostic(ctx, "Set volume", || SystemAudio.set_volume(0.5));
+                    }
+                    if ui.button("Toggle mute").clicked() {
+                        self.run_diagnostic(ctx, "Toggle mute", || {
+                            let (_, muted) = SystemAudio.get_volume_and_mute()?;
+                            SystemAudio.set_mute(!muted)
+                        });
+                    }
+                    if ui.button("Play/Pause").clicked() {
+                        self.run_diagnostic(ctx, "Play/Pause", media::toggle_play_pause);
+                    }
+                    if ui.button("Screenshot").clicked() {
+                        self.run_diagnostic(ctx, "Screenshot", system::take_screenshot);
+                    }
+                });
+                let check = ui.button("Check input access in 3s");
+                if check.clicked() {
+                    self.run_diagnostic(ctx, "Input access", || {
+                        std::thread::sleep(Duration::from_secs(3));
+                        if window::foreground_blocks_input() {
+                            anyhow::bail!("the focused app runs elevated, so key presses are dropped (UIPI); run FOSS-Deck as administrator");
+                        }
+                        Ok(())
+                    });
+                }
+                check.on_hover_text("Switch to the app you want to control before the check runs.");
+                if let Some(outcome) = self.diagnostic.lock().unwrap().as_ref() {
+                    ui.label(outcome);
+                }
+            });
+
             ui.separator();
             ui.heading("Authorized devices");
 
@@ -191,7 +653,30 @@ impl eframe::App for App {
                     for (device_id, dev) in authorized_list {
                         ui.group(|ui| {
                             let name = dev.name.clone().unwrap_or_else(|| "Unnamed device".into());
-                            ui.label(format!("Name: {}", name));
+                            let mut rename_done = false;
+                            match &mut self.renaming {
+                                Some((id, edited)) if *id == device_id => {
+                                    ui.horizontal(|ui| {
+                                        ui.label("Name:");
+                                        ui.text_edit_singleline(edited);
+                                        let valid = normalize_device_name(edited);
+                                        if ui.add_enabled(valid.is_some(), eframe::egui::Button::new("Save")).clicked() {
+                                            if let Some(new_name) = valid {
+                                                self.pairing.lock().unwrap().rename_device(&device_id, new_name);
+                                            }
+                                            rename_done = true;
+                                        } else if ui.button("Cancel").clicked() {
+                                            rename_done = true;
+                                        }
+                                    });
+                                }
+                                _ => {
+                                    ui.label(format!("Name: {}", name));
+                                }
+                            }
+                            if rename_done {
+                                self.renaming = None;
+                            }
                             ui.label(format!("Device ID: {}", device_id));
                             ui.label(format!("Last seen (unix): {}", dev.last_seen));
 
@@ -201,6 +686,30 @@ impl eframe::App for App {
                             }
 
                             ui.horizontal(|ui| {
+                                ui.label("Scopes:");
+                                let mut scopes = dev.scopes.clone();
+                                let mut changed = false;
+                                for scope in ALL_SCOPES {
+                                    let mut on = scopes.iter().any(|s| s == scope);
+                                    if ui.checkbox(&mut on, *scope).changed() {
+                                        changed = true;
+                                        if on {
+                                            scopes.push(scope.to_string());
+                                        } else {
+                                            scopes.retain(|s| s != scope);
+                                        }
+                                    }
+                                }
+                                if changed {
+                                    let mut st = self.pairing.lock().unwrap();
+                                    st.set_scopes(&device_id, scopes);
+                                }
+                            });
+
+                            ui.horizontal(|ui| {
+                                if ui.button("Rename").clicked() {
+                                    self.renaming = Some((device_id.clone(), dev.name.clone().unwrap_or_default()));
+                                }
                                 let revoke = ui.button("Revoke");
                                 if revoke.clicked() {
                                     let mut st = self.pairing.lock().unwrap();
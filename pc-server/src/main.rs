@@ -3,9 +3,13 @@
 mod gui;
 mod server;
 mod audio;
+mod config;
 mod discovery;
 mod media;
+mod screen;
+mod sessions;
 mod system;
+mod window;
 
 fn main() {
     gui::run_gui();
@@ -1,58 +1,599 @@
 // src/media.rs
 use anyhow::Result;
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use windows::Foundation::{EventRegistrationToken, TypedEventHandler};
+use windows::Media::MediaPlaybackAutoRepeatMode;
+use windows::Media::Control::{
+    GlobalSystemMediaTransportControlsSession, GlobalSystemMediaTransportControlsSessionManager,
+    GlobalSystemMediaTransportControlsSessionPlaybackStatus,
+};
+
+use crate::server::events::{publish, EventSender};
 use windows::Win32::UI::Input::KeyboardAndMouse::{
-    SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP,
-    VK_MEDIA_NEXT_TRACK, VK_MEDIA_PLAY_PAUSE, VK_MEDIA_PREV_TRACK, VIRTUAL_KEY,
+    INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYBD_EVENT_FLAGS, KEYEVENTF_KEYUP,
+    VK_MEDIA_NEXT_TRACK, VK_MEDIA_PLAY_PAUSE, VK_MEDIA_PREV_TRACK, VK_MEDIA_STOP, VIRTUAL_KEY,
 };
 
 fn send_media_key(vk: VIRTUAL_KEY) -> Result<()> {
-    unsafe {
-        let down = INPUT {
-            r#type: INPUT_KEYBOARD,
-            Anonymous: INPUT_0 {
-                ki: KEYBDINPUT {
-                    wVk: vk,
-                    wScan: 0,
-                    dwFlags: windows::Win32::UI::Input::KeyboardAndMouse::KEYBD_EVENT_FLAGS(0),
-                    time: 0,
-                    dwExtraInfo: 0,
-                },
+    let key = |flags| INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk,
+                wScan: 0,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
             },
-        };
+        },
+    };
+    crate::system::send_inputs(&[key(KEYBD_EVENT_FLAGS(0)), key(KEYEVENTF_KEYUP)])
+}
 
-        let up = INPUT {
-            r#type: INPUT_KEYBOARD,
-            Anonymous: INPUT_0 {
-                ki: KEYBDINPUT {
-                    wVk: vk,
-                    wScan: 0,
-                    dwFlags: KEYEVENTF_KEYUP,
-                    time: 0,
-                    dwExtraInfo: 0,
-                },
-            },
-        };
+pub fn next_track() -> Result<()> {
+    send_media_key(VK_MEDIA_NEXT_TRACK)
+}
+
+pub fn previous_track() -> Result<()> {
+    send_media_key(VK_MEDIA_PREV_TRACK)
+}
+
+pub fn toggle_play_pause() -> Result<()> {
+    send_media_key(VK_MEDIA_PLAY_PAUSE)
+}
+
+pub fn stop() -> Result<()> {
+    send_media_key(VK_MEDIA_STOP)
+}
+
+// How untargeted NextTrack/PreviousTrack/TogglePlayPause/Stop reach the player
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaBackend {
+    // global media key; GSMTC only when UIPI blocks the key
+    #[default]
+    Keys,
+    // the current GSMTC session only
+    Smtc,
+    // GSMTC first, the media key if no session takes it
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportAction {
+    Next,
+    Previous,
+    TogglePlayPause,
+    Stop,
+}
+
+// Which session a transport command goes to: a session_id from list_media_sessions, the
+// first session of an app (AppUserModelId, case-insensitive), or whichever Windows considers current
+pub enum SessionTarget<'a> {
+    SessionId(&'a str),
+    AppId(&'a str),
+    Current,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaSession {
+    // the app id, plus "#n" for the n-th further session of the same app
+    pub session_id: String,
+    pub app_id: String,
+    // best-effort display name derived from app_id
+    pub app_name: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub status: PlaybackStatus,
+    // the session untargeted media commands reach
+    pub is_current: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackStatus {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+// Every GSMTC session, in the order Windows lists them
+fn sessions_with_ids() -> Result<Vec<(String, GlobalSystemMediaTransportControlsSession)>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let mut seen: Vec<String> = Vec::new();
+    let mut sessions = Vec::new();
+    for session in manager.GetSessions()? {
+        let app_id = session.SourceAppUserModelId()?.to_string();
+        let n = seen.iter().filter(|a| **a == app_id).count();
+        let session_id = if n == 0 { app_id.clone() } else { format!("{app_id}#{n}") };
+        seen.push(app_id);
+        sessions.push((session_id, session));
+    }
+    Ok(sessions)
+}
 
-        let inputs = [down, up];
+pub fn list_media_sessions() -> Result<Vec<MediaSession>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let current = manager.GetCurrentSession().ok();
+    let non_empty = |s: windows::core::HSTRING| Some(s.to_string()).filter(|s| !s.is_empty());
 
-        // SendInput returns number of events successfully inserted
-        let sent = SendInput(&inputs, std::mem::size_of::<INPUT>() as i32);
-        if sent != inputs.len() as u32 {
-            anyhow::bail!("SendInput failed (sent {sent}/{})", inputs.len());
+    let mut list = Vec::new();
+    for (session_id, session) in sessions_with_ids()? {
+        let props = session.TryGetMediaPropertiesAsync()?.get().ok();
+        list.push(MediaSession {
+            session_id,
+            app_name: friendly_app_name(&session.SourceAppUserModelId()?.to_string()),
+            app_id: session.SourceAppUserModelId()?.to_string(),
+            title: props.as_ref().and_then(|p| p.Title().ok()).and_then(non_empty),
+            artist: props.as_ref().and_then(|p| p.Artist().ok()).and_then(non_empty),
+            status: playback_status(&session),
+            is_current: current.as_ref() == Some(&session),
+        });
+    }
+    Ok(list)
+}
+
+// "Spotify.exe" -> "Spotify", "C:\...\vlc.exe" -> "vlc",
+// "Microsoft.ZuneMusic_8wekyb3d8bbwe!Microsoft.ZuneMusic" -> "ZuneMusic"; None if nothing is left
+pub(crate) fn friendly_app_name(app_id: &str) -> Option<String> {
+    let app = app_id.split('!').next().unwrap_or(app_id);
+    let app = app.rsplit(['\\', '/']).next().unwrap_or(app);
+    let exe = app.len() > 4 && app.get(app.len() - 4..).is_some_and(|ext| ext.eq_ignore_ascii_case(".exe"));
+    let name = if exe {
+        &app[..app.len() - 4]
+    } else if let Some((family, _publisher)) = app.split_once('_') {
+        // packaged app: Publisher.Name_publisherhash
+        family.rsplit('.').next().unwrap_or(family)
+    } else {
+        app
+    };
+    Some(name.trim().to_string()).filter(|n| !n.is_empty())
+}
+
+fn playback_status(session: &GlobalSystemMediaTransportControlsSession) -> PlaybackStatus {
+    match session.GetPlaybackInfo().and_then(|info| info.PlaybackStatus()) {
+        Ok(GlobalSystemMediaTransportControlsSessionPlaybackStatus::Playing) => PlaybackStatus::Playing,
+        Ok(GlobalSystemMediaTransportControlsSessionPlaybackStatus::Paused) => PlaybackStatus::Paused,
+        // opened, changing and closed sessions aren't playing anything either
+        _ => PlaybackStatus::Stopped,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Playback {
+    pub status: PlaybackStatus,
+    pub app: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+// Status of the current session for GetStatus; None if there is none
+pub fn current_playback() -> Result<Option<Playback>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let Ok(session) = manager.GetCurrentSession() else {
+        return Ok(None);
+    };
+    session_playback(&session).map(Some)
+}
+
+fn session_playback(session: &GlobalSystemMediaTransportControlsSession) -> Result<Playback> {
+    let props = session.TryGetMediaPropertiesAsync()?.get().ok();
+    let non_empty = |s: windows::core::HSTRING| Some(s.to_string()).filter(|s| !s.is_empty());
+    Ok(Playback {
+        status: playback_status(session),
+        app: session.SourceAppUserModelId()?.to_string(),
+        title: props.as_ref().and_then(|p| p.Title().ok()).and_then(non_empty),
+        artist: props.as_ref().and_then(|p| p.Artist().ok()).and_then(non_empty),
+    })
+}
+
+// Collects notification bursts for this long, so a track change reaches clients once
+const MEDIA_DEBOUNCE: Duration = Duration::from_millis(250);
+
+enum MediaMsg {
+    // Windows picked another current session
+    Rebind,
+    // the current session's track or playback state changed
+    Changed,
+    Stop,
+}
+
+// Pushes {"type":"media_changed"} on the "media" topic when the current GSMTC session's track
+// or playback state changes. Handlers are registered on a dedicated thread and removed when
+// this is dropped.
+pub struct MediaWatcher {
+    wake: mpsc::Sender<MediaMsg>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MediaWatcher {
+    pub fn start(events: EventSender) -> Self {
+        let (wake, rx) = mpsc::channel();
+        let handler_wake = wake.clone();
+        let handle = thread::spawn(move || {
+            let res = watch_media(events, handler_wake, rx);
+            crate::audio::release_com();
+            if let Err(e) = res {
+                error!("Media watcher failed: {e:?}");
+            }
+        });
+        Self { wake, handle: Some(handle) }
+    }
+}
+
+impl Drop for MediaWatcher {
+    fn drop(&mut self) {
+        // the registered handlers hold senders too, so stop explicitly
+        let _ = self.wake.send(MediaMsg::Stop);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
         }
     }
+}
+
+// Track and playback handlers registered on one session
+struct SessionBinding {
+    session: GlobalSystemMediaTransportControlsSession,
+    properties: EventRegistrationToken,
+    playback: EventRegistrationToken,
+}
+
+impl SessionBinding {
+    fn register(session: GlobalSystemMediaTransportControlsSession, wake: &mpsc::Sender<MediaMsg>) -> Result<Self> {
+        let properties = session.MediaPropertiesChanged(&TypedEventHandler::new({
+            let wake = wake.clone();
+            move |_, _| {
+                let _ = wake.send(MediaMsg::Changed);
+                Ok(())
+            }
+        }))?;
+        let playback = session.PlaybackInfoChanged(&TypedEventHandler::new({
+            let wake = wake.clone();
+            move |_, _| {
+                let _ = wake.send(MediaMsg::Changed);
+                Ok(())
+            }
+        }))?;
+        Ok(Self { session, properties, playback })
+    }
+
+    fn unregister(self) {
+        let _ = self.session.RemoveMediaPropertiesChanged(self.properties);
+        let _ = self.session.RemovePlaybackInfoChanged(self.playback);
+    }
+}
+
+fn watch_media(events: EventSender, wake: mpsc::Sender<MediaMsg>, rx: mpsc::Receiver<MediaMsg>) -> Result<()> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let current_changed = manager.CurrentSessionChanged(&TypedEventHandler::new({
+        let wake = wake.clone();
+        move |_, _| {
+            let _ = wake.send(MediaMsg::Rebind);
+            Ok(())
+        }
+    }))?;
 
+    let bind = |manager: &GlobalSystemMediaTransportControlsSessionManager| {
+        manager.GetCurrentSession().ok().and_then(|s| SessionBinding::register(s, &wake).ok())
+    };
+    let snapshot = |binding: &Option<SessionBinding>| binding.as_ref().and_then(|b| session_playback(&b.session).ok());
+
+    let mut bound = bind(&manager);
+    let mut last = snapshot(&bound);
+    'watch: while let Ok(msg) = rx.recv() {
+        let mut rebind = matches!(msg, MediaMsg::Rebind);
+        if matches!(msg, MediaMsg::Stop) {
+            break;
+        }
+        // some players fire several notifications per change; wait for the burst to end
+        let deadline = Instant::now() + MEDIA_DEBOUNCE;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match rx.recv_timeout(left) {
+                Ok(MediaMsg::Stop) => break 'watch,
+                Ok(MediaMsg::Rebind) => rebind = true,
+                Ok(MediaMsg::Changed) => {}
+                Err(_) => break,
+            }
+        }
+
+        if rebind {
+            if let Some(b) = bound.take() {
+                b.unregister();
+            }
+            bound = bind(&manager);
+        }
+        let current = snapshot(&bound);
+        if current == last {
+            continue;
+        }
+        let payload = match &current {
+            Some(p) => json!({"type":"media_changed","status":p.status,"app":p.app,"title":p.title,"artist":p.artist}),
+            None => json!({"type":"media_changed","status":"none","app":null,"title":null,"artist":null}),
+        };
+        publish(&events, "media", payload);
+        last = current;
+    }
+
+    if let Some(b) = bound {
+        b.unregister();
+    }
+    manager.RemoveCurrentSessionChanged(current_changed)?;
     Ok(())
 }
 
-pub fn next_track() -> Result<()> {
-    send_media_key(VK_MEDIA_NEXT_TRACK)
+// Sends the action straight to one session instead of as a global media key. None if no
+// session matches; Some(false) if the app turned the request down
+pub fn session_transport(target: SessionTarget, action: TransportAction) -> Result<Option<bool>> {
+    let session = if let SessionTarget::Current = target {
+        crate::audio::ensure_com_initialized()?;
+        GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?.GetCurrentSession().ok()
+    } else {
+        sessions_with_ids()?
+            .into_iter()
+            .find(|(session_id, session)| match target {
+                SessionTarget::SessionId(id) => session_id == id,
+                SessionTarget::AppId(app) => {
+                    session.SourceAppUserModelId().is_ok_and(|a| a.to_string().eq_ignore_ascii_case(app))
+                }
+                SessionTarget::Current => false,
+            })
+            .map(|(_, session)| session)
+    };
+    let Some(session) = session else {
+        return Ok(None);
+    };
+    let op = match action {
+        TransportAction::Next => session.TrySkipNextAsync()?,
+        TransportAction::Previous => session.TrySkipPreviousAsync()?,
+        TransportAction::TogglePlayPause => session.TryTogglePlayPauseAsync()?,
+        TransportAction::Stop => session.TryStopAsync()?,
+    };
+    Ok(Some(op.get()?))
 }
 
-pub fn previous_track() -> Result<()> {
-    send_media_key(VK_MEDIA_PREV_TRACK)
+// AppUserModelId of the app owning the current media session (e.g. "Spotify.exe"); None if nothing is playing
+pub fn current_media_app() -> Result<Option<String>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    match manager.GetCurrentSession() {
+        Ok(session) => Ok(Some(session.SourceAppUserModelId()?.to_string())),
+        Err(_) => Ok(None),
+    }
 }
 
-pub fn toggle_play_pause() -> Result<()> {
-    send_media_key(VK_MEDIA_PLAY_PAUSE)
+#[derive(Debug, Clone, Serialize)]
+pub struct NowPlaying {
+    pub app: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub playing: bool,
+}
+
+// Current media session's track; None if nothing is playing
+pub fn now_playing() -> Result<Option<NowPlaying>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let Ok(session) = manager.GetCurrentSession() else {
+        return Ok(None);
+    };
+
+    let props = session.TryGetMediaPropertiesAsync()?.get().ok();
+    let non_empty = |s: windows::core::HSTRING| Some(s.to_string()).filter(|s| !s.is_empty());
+    let playing = session
+        .GetPlaybackInfo()
+        .and_then(|info| info.PlaybackStatus())
+        .is_ok_and(|st| st == GlobalSystemMediaTransportControlsSessionPlaybackStatus::Playing);
+
+    Ok(Some(NowPlaying {
+        app: session.SourceAppUserModelId()?.to_string(),
+        title: props.as_ref().and_then(|p| p.Title().ok()).and_then(non_empty),
+        artist: props.as_ref().and_then(|p| p.Artist().ok()).and_then(non_empty),
+        playing,
+    }))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaybackPosition {
+    pub position_ms: u64,
+    pub duration_ms: u64,
+    // whether seek will be accepted by the app
+    pub can_seek: bool,
+    pub playing: bool,
+}
+
+// Result of asking the current session to change something
+pub enum ControlOutcome<T> {
+    // the new value (for seek, the clamped position asked for)
+    Changed(T),
+    NoSession,
+    NotSupported,
+}
+
+// TimeSpan/DateTime ticks are 100 ns
+const TICKS_PER_MS: i64 = 10_000;
+// 1601-01-01 (DateTime's epoch) to 1970-01-01, in ticks
+const UNIX_EPOCH_TICKS: i64 = 116_444_736_000_000_000;
+
+// Position within the current media session's track; None if nothing is playing
+pub fn playback_position() -> Result<Option<PlaybackPosition>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let Ok(session) = manager.GetCurrentSession() else {
+        return Ok(None);
+    };
+    let info = session.GetPlaybackInfo()?;
+    let playing = info
+        .PlaybackStatus()
+        .is_ok_and(|st| st == GlobalSystemMediaTransportControlsSessionPlaybackStatus::Playing);
+    let timeline = Timeline::read(&session, playing)?;
+    Ok(Some(PlaybackPosition {
+        position_ms: clamp_position_ms(timeline.position_ms, timeline.duration_ms),
+        duration_ms: timeline.duration_ms as u64,
+        can_seek: info.Controls()?.IsPlaybackPositionEnabled()?,
+        playing,
+    }))
+}
+
+// A session's timeline relative to the start of the track
+struct Timeline {
+    // ticks; what TryChangePlaybackPosition positions are offset by
+    start: i64,
+    position_ms: i64,
+    duration_ms: i64,
+}
+
+impl Timeline {
+    fn read(session: &GlobalSystemMediaTransportControlsSession, playing: bool) -> Result<Self> {
+        let timeline = session.GetTimelineProperties()?;
+        let start = timeline.StartTime()?.Duration;
+        let duration = (timeline.EndTime()?.Duration - start).max(0);
+        let mut position = timeline.Position()?.Duration - start;
+        // apps only push the position now and then; account for the time played since
+        if playing {
+            let now =
+                SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as i64 / 100) + UNIX_EPOCH_TICKS;
+            position += (now - timeline.LastUpdatedTime()?.UniversalTime).max(0);
+        }
+        Ok(Self { start, position_ms: position / TICKS_PER_MS, duration_ms: duration / TICKS_PER_MS })
+    }
+
+    // Ok(false) if the app turned the request down
+    fn seek_to(&self, session: &GlobalSystemMediaTransportControlsSession, position_ms: u64) -> Result<bool> {
+        Ok(session.TryChangePlaybackPositionAsync(self.start + position_ms as i64 * TICKS_PER_MS)?.get()?)
+    }
+}
+
+// Moves the current session to `position_ms` from the start of the track, clamped to its length
+pub fn seek(position_ms: u64) -> Result<ControlOutcome<u64>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let Ok(session) = manager.GetCurrentSession() else {
+        return Ok(ControlOutcome::NoSession);
+    };
+    if !session.GetPlaybackInfo()?.Controls()?.IsPlaybackPositionEnabled()? {
+        return Ok(ControlOutcome::NotSupported);
+    }
+    let timeline = Timeline::read(&session, false)?;
+    let target = clamp_position_ms(position_ms.min(i64::MAX as u64 / TICKS_PER_MS as u64) as i64, timeline.duration_ms);
+    // some apps advertise seeking and still turn individual requests down
+    if !timeline.seek_to(&session, target)? {
+        return Ok(ControlOutcome::NotSupported);
+    }
+    Ok(ControlOutcome::Changed(target))
+}
+
+// Moves the current session `delta_ms` from where it is now, clamped to the track. Sessions
+// without a seekable timeline get the app's own fast-forward/rewind instead, and the new
+// position is then unknown (None)
+pub fn jump(delta_ms: i64) -> Result<ControlOutcome<Option<u64>>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let Ok(session) = manager.GetCurrentSession() else {
+        return Ok(ControlOutcome::NoSession);
+    };
+    let info = session.GetPlaybackInfo()?;
+    let controls = info.Controls()?;
+    let playing = info
+        .PlaybackStatus()
+        .is_ok_and(|st| st == GlobalSystemMediaTransportControlsSessionPlaybackStatus::Playing);
+    let timeline = Timeline::read(&session, playing)?;
+
+    if controls.IsPlaybackPositionEnabled()? && timeline.duration_ms > 0 {
+        let target = clamp_position_ms(timeline.position_ms.saturating_add(delta_ms), timeline.duration_ms);
+        if !timeline.seek_to(&session, target)? {
+            return Ok(ControlOutcome::NotSupported);
+        }
+        return Ok(ControlOutcome::Changed(Some(target)));
+    }
+
+    let accepted = if delta_ms >= 0 {
+        controls.IsFastForwardEnabled()? && session.TryFastForwardAsync()?.get()?
+    } else {
+        controls.IsRewindEnabled()? && session.TryRewindAsync()?.get()?
+    };
+    if !accepted {
+        return Ok(ControlOutcome::NotSupported);
+    }
+    Ok(ControlOutcome::Changed(None))
+}
+
+// 0..=duration; a zero duration (live streams, some players) leaves the position unclamped above
+pub(crate) fn clamp_position_ms(position_ms: i64, duration_ms: i64) -> u64 {
+    let position = position_ms.max(0);
+    if duration_ms > 0 { position.min(duration_ms) as u64 } else { position as u64 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepeatMode {
+    Off,
+    List,
+    Track,
+}
+
+impl RepeatMode {
+    // off -> list -> track -> off, the order Spotify's button goes through
+    pub fn next(self) -> Self {
+        match self {
+            RepeatMode::Off => RepeatMode::List,
+            RepeatMode::List => RepeatMode::Track,
+            RepeatMode::Track => RepeatMode::Off,
+        }
+    }
+}
+
+// Flips shuffle on the current session; the new state
+pub fn toggle_shuffle() -> Result<ControlOutcome<bool>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let Ok(session) = manager.GetCurrentSession() else {
+        return Ok(ControlOutcome::NoSession);
+    };
+    let info = session.GetPlaybackInfo()?;
+    if !info.Controls()?.IsShuffleEnabled()? {
+        return Ok(ControlOutcome::NotSupported);
+    }
+    // apps that never reported a state are taken to be off
+    let active = info.IsShuffleActive().and_then(|v| v.Value()).unwrap_or(false);
+    if !session.TryChangeShuffleActiveAsync(!active)?.get()? {
+        return Ok(ControlOutcome::NotSupported);
+    }
+    Ok(ControlOutcome::Changed(!active))
+}
+
+// Moves the current session to the next repeat mode; the new mode
+pub fn cycle_repeat() -> Result<ControlOutcome<RepeatMode>> {
+    crate::audio::ensure_com_initialized()?;
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let Ok(session) = manager.GetCurrentSession() else {
+        return Ok(ControlOutcome::NoSession);
+    };
+    let info = session.GetPlaybackInfo()?;
+    if !info.Controls()?.IsRepeatEnabled()? {
+        return Ok(ControlOutcome::NotSupported);
+    }
+    let current = match info.AutoRepeatMode().and_then(|v| v.Value()) {
+        Ok(MediaPlaybackAutoRepeatMode::List) => RepeatMode::List,
+        Ok(MediaPlaybackAutoRepeatMode::Track) => RepeatMode::Track,
+        _ => RepeatMode::Off,
+    };
+    let next = current.next();
+    let requested = match next {
+        RepeatMode::Off => MediaPlaybackAutoRepeatMode::None,
+        RepeatMode::List => MediaPlaybackAutoRepeatMode::List,
+        RepeatMode::Track => MediaPlaybackAutoRepeatMode::Track,
+    };
+    if !session.TryChangeAutoRepeatModeAsync(requested)?.get()? {
+        return Ok(ControlOutcome::NotSupported);
+    }
+    Ok(ControlOutcome::Changed(next))
 }
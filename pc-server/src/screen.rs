@@ -0,0 +1,238 @@
+// src/screen.rs
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+// Top-down 32-bit BGRA pixels, as GetDIBits hands them out
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub bgra: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+// What take_screenshot captures: "primary", "monitor:<index>" (see list_monitors) or "active_window"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ScreenshotTarget {
+    #[default]
+    Primary,
+    Monitor(usize),
+    ActiveWindow,
+}
+
+impl TryFrom<String> for ScreenshotTarget {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "primary" => Ok(ScreenshotTarget::Primary),
+            "active_window" => Ok(ScreenshotTarget::ActiveWindow),
+            _ => s
+                .strip_prefix("monitor:")
+                .and_then(|i| i.parse().ok())
+                .map(ScreenshotTarget::Monitor)
+                .ok_or_else(|| format!("unknown screenshot target {s:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Monitor {
+    pub index: usize,
+    // \\.\DISPLAYn
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub primary: bool,
+}
+
+impl Monitor {
+    pub fn area(&self) -> Area {
+        Area { x: self.x, y: self.y, width: self.width, height: self.height }
+    }
+}
+
+// In EnumDisplayMonitors order, which is what "monitor:<index>" refers to
+pub fn list_monitors() -> Result<Vec<Monitor>> {
+    use windows::Win32::Foundation::{BOOL, LPARAM, RECT};
+    use windows::Win32::Graphics::Gdi::{EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFO, MONITORINFOEXW};
+    use windows::Win32::UI::WindowsAndMessaging::MONITORINFOF_PRIMARY;
+
+    unsafe extern "system" fn collect(monitor: HMONITOR, _: HDC, _: *mut RECT, data: LPARAM) -> BOOL {
+        let monitors = unsafe { &mut *(data.0 as *mut Vec<Monitor>) };
+        let mut info = MONITORINFOEXW::default();
+        info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+        // a monitor unplugged mid-enumeration is skipped
+        if unsafe { GetMonitorInfoW(monitor, &mut info as *mut _ as *mut MONITORINFO) }.as_bool() {
+            let r = info.monitorInfo.rcMonitor;
+            let len = info.szDevice.iter().position(|&c| c == 0).unwrap_or(info.szDevice.len());
+            monitors.push(Monitor {
+                index: monitors.len(),
+                name: String::from_utf16_lossy(&info.szDevice[..len]),
+                x: r.left,
+                y: r.top,
+                width: r.right - r.left,
+                height: r.bottom - r.top,
+                primary: info.monitorInfo.dwFlags & MONITORINFOF_PRIMARY != 0,
+            });
+        }
+        true.into()
+    }
+
+    let mut monitors: Vec<Monitor> = Vec::new();
+    let ok = unsafe { EnumDisplayMonitors(HDC::default(), None, Some(collect), LPARAM(&mut monitors as *mut _ as isize)) };
+    if !ok.as_bool() {
+        bail!("EnumDisplayMonitors failed");
+    }
+    Ok(monitors)
+}
+
+pub fn primary_area() -> Result<Area> {
+    use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_CXSCREEN, SM_CYSCREEN};
+
+    // the primary monitor's top-left corner is the desktop origin
+    let (width, height) = unsafe { (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) };
+    if width <= 0 || height <= 0 {
+        bail!("no primary monitor");
+    }
+    Ok(Area { x: 0, y: 0, width, height })
+}
+
+// Bounds of the foreground window; None without one, or while it is minimized
+pub fn active_window_area() -> Result<Option<Area>> {
+    use windows::Win32::Foundation::RECT;
+    use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowRect, IsIconic};
+
+    unsafe {
+        let hwnd = GetForegroundWindow();
+        if hwnd.0.is_null() || IsIconic(hwnd).as_bool() {
+            return Ok(None);
+        }
+        let mut r = RECT::default();
+        GetWindowRect(hwnd, &mut r)?;
+        let (width, height) = (r.right - r.left, r.bottom - r.top);
+        Ok((width > 0 && height > 0).then_some(Area { x: r.left, y: r.top, width, height }))
+    }
+}
+
+// Copies `area` (desktop coordinates) off the screen; CAPTUREBLT includes layered windows
+// such as tooltips
+pub fn capture(area: Area) -> Result<Frame> {
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::Graphics::Gdi::{
+        BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject, GetDC, GetDIBits, ReleaseDC,
+        SelectObject, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, CAPTUREBLT, DIB_RGB_COLORS, SRCCOPY,
+    };
+
+    let Area { x, y, width, height } = area;
+    unsafe {
+        let screen = GetDC(HWND::default());
+        if screen.is_invalid() {
+            bail!("GetDC failed");
+        }
+        let memory = CreateCompatibleDC(screen);
+        let bitmap = CreateCompatibleBitmap(screen, width, height);
+        let previous = SelectObject(memory, bitmap);
+
+        let mut info = BITMAPINFO {
+            bmiHeader: BITMAPINFOHEADER {
+                biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+                biWidth: width,
+                // negative: rows top to bottom
+                biHeight: -height,
+                biPlanes: 1,
+                biBitCount: 32,
+                biCompression: BI_RGB.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut bgra = vec![0u8; width as usize * height as usize * 4];
+        let copied = BitBlt(memory, 0, 0, width, height, screen, x, y, SRCCOPY | CAPTUREBLT).map(|()| {
+            GetDIBits(
+                memory,
+                bitmap,
+                0,
+                height as u32,
+                Some(bgra.as_mut_ptr().cast()),
+                &mut info,
+                DIB_RGB_COLORS,
+            )
+        });
+
+        SelectObject(memory, previous);
+        let _ = DeleteObject(bitmap);
+        let _ = DeleteDC(memory);
+        ReleaseDC(HWND::default(), screen);
+
+        match copied {
+            Ok(rows) if rows == height => Ok(Frame { width: width as u32, height: height as u32, bgra }),
+            Ok(_) => bail!("GetDIBits failed"),
+            Err(e) => bail!("BitBlt failed: {e}"),
+        }
+    }
+}
+
+// Box-filters the frame down so its longer side is at most `max_dimension`, returning RGB rows
+pub fn downscale_to_rgb(frame: &Frame, max_dimension: u32) -> (u32, u32, Vec<u8>) {
+    let (w, h) = (frame.width, frame.height);
+    let longest = w.max(h).max(1);
+    let max_dimension = max_dimension.max(1);
+    let (dw, dh) = if longest <= max_dimension {
+        (w, h)
+    } else {
+        let scale = |side: u32| ((side as u64 * max_dimension as u64 / longest as u64) as u32).max(1);
+        (scale(w), scale(h))
+    };
+
+    let mut rgb = Vec::with_capacity(dw as usize * dh as usize * 3);
+    for y in 0..dh {
+        let (y0, y1) = source_span(y, dh, h);
+        for x in 0..dw {
+            let (x0, x1) = source_span(x, dw, w);
+            let mut sum = [0u64; 3];
+            for sy in y0..y1 {
+                let row = sy as usize * w as usize * 4;
+                for sx in x0..x1 {
+                    let p = row + sx as usize * 4;
+                    // BGRA -> RGB
+                    sum[0] += frame.bgra[p + 2] as u64;
+                    sum[1] += frame.bgra[p + 1] as u64;
+                    sum[2] += frame.bgra[p] as u64;
+                }
+            }
+            let count = ((y1 - y0) * (x1 - x0)) as u64;
+            rgb.extend(sum.iter().map(|s| (s / count) as u8));
+        }
+    }
+    (dw, dh, rgb)
+}
+
+// Source pixels [start, end) covered by destination pixel `i` of `dest` along a side of `src`
+fn source_span(i: u32, dest: u32, src: u32) -> (u32, u32) {
+    let start = (i as u64 * src as u64 / dest as u64) as u32;
+    let end = ((i as u64 + 1) * src as u64 / dest as u64) as u32;
+    (start, end.max(start + 1).min(src))
+}
+
+pub fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // screenshots are mostly flat UI; fast compression already shrinks them well
+    encoder.set_compression(png::Compression::Fast);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgb)?;
+    writer.finish()?;
+    Ok(out)
+}
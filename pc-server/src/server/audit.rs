@@ -0,0 +1,66 @@
+// src/server/audit.rs
+#![cfg(windows)]
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use crate::server::auth_store::now_unix;
+
+// audit.jsonl is moved to audit.jsonl.1 once it grows past this
+const MAX_AUDIT_BYTES: u64 = 1024 * 1024;
+
+// One security event. Never carries tokens or pairing codes, only outcomes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub ts: i64,
+    pub event: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+}
+
+// Append-only JSONL security log, kept apart from the regular log output
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn record(&self, event: &str, ip: Option<IpAddr>, device_id: Option<&str>) {
+        let entry = AuditEntry {
+            ts: now_unix(),
+            event: event.to_string(),
+            ip: ip.map(|ip| ip.to_string()),
+            device_id: device_id.map(|d| d.to_string()),
+        };
+        if let Err(e) = self.append(&entry) {
+            error!("Failed to write audit log: {e:?}");
+        }
+    }
+
+    // Newest last
+    pub fn tail(&self, n: usize) -> Vec<AuditEntry> {
+        let Ok(s) = fs::read_to_string(&self.path) else {
+            return Vec::new();
+        };
+        let entries: Vec<AuditEntry> = s.lines().filter_map(|l| serde_json::from_str(l).ok()).collect();
+        entries[entries.len().saturating_sub(n)..].to_vec()
+    }
+
+    fn append(&self, entry: &AuditEntry) -> anyhow::Result<()> {
+        if fs::metadata(&self.path).is_ok_and(|m| m.len() > MAX_AUDIT_BYTES) {
+            fs::rename(&self.path, self.path.with_extension("jsonl.1"))?;
+        }
+        let mut f = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(f, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+}
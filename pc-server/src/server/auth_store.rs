@@ -2,7 +2,6 @@
 #![cfg(windows)]
 
 use anyhow::Result;
-use directories_next::ProjectDirs;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -11,6 +10,8 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::config::data_file;
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub(crate) struct AuthorizedStore {
     pub(crate) devices: HashMap<String, AuthorizedDevice>,
@@ -22,6 +23,27 @@ pub struct AuthorizedDevice {
     pub token_hash: String,
     pub added_at: i64,
     pub last_seen: i64,
+    // entries written before scopes existed get the safe subset
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+}
+
+// "admin" covers device-management reads such as the live pairing code
+pub const ALL_SCOPES: &[&str] = &["volume", "media", "system", "power", "input", "admin"];
+
+// granted at pairing time; power/input/admin must be enabled from the GUI
+pub const DEFAULT_SCOPES: &[&str] = &["volume", "media", "system"];
+
+pub fn default_scopes() -> Vec<String> {
+    DEFAULT_SCOPES.iter().map(|s| s.to_string()).collect()
+}
+
+pub const MAX_DEVICE_NAME_CHARS: usize = 64;
+
+// Trimmed, whitespace-collapsed device name; None if empty or too long
+pub fn normalize_device_name(name: &str) -> Option<String> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!name.is_empty() && name.chars().count() <= MAX_DEVICE_NAME_CHARS).then_some(name)
 }
 
 pub(crate) fn now_unix() -> i64 {
@@ -46,13 +68,7 @@ pub(crate) fn generate_token() -> String {
 
 pub(crate) fn auth_store_path() -> PathBuf {
     // %APPDATA%/FOSS-Deck/authorized.json (or similar)
-    if let Some(proj_dirs) = ProjectDirs::from("org", "FOSS-Deck", "FOSS-Deck") {
-        let dir = proj_dirs.data_dir();
-        let _ = fs::create_dir_all(dir);
-        dir.join("authorized.json")
-    } else {
-        PathBuf::from("authorized.json")
-    }
+    data_file("authorized.json")
 }
 
 pub(crate) fn load_store(path: &Path) -> AuthorizedStore {
@@ -1,121 +1,1685 @@
 // src/server/commands.rs
 #![cfg(windows)]
 
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use log::warn;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::{audio, media, system};
+use crate::config::SharedConfig;
+use crate::server::auth_store::now_unix;
+use crate::server::error::{error, input_error_reply, ErrorCode};
+use crate::server::metrics::Metrics;
+use crate::server::groups::GroupStore;
+use crate::server::launchers::{Launcher, LauncherStore};
+use crate::server::power::{PowerSchedule, MAX_POWER_DELAY_SECS};
+use crate::server::presets::PresetStore;
+use crate::server::rate_limit::TokenBucket;
+use crate::audio::{AudioBackend, EndpointState, OutputRole, PresetEntry, PresetOutcome};
+use crate::media::{ControlOutcome, MediaBackend, SessionTarget, TransportAction};
+use crate::screen::ScreenshotTarget;
+use crate::system::{InputError, PowerAction, MAX_TYPE_TEXT_CHARS};
+use crate::{media, screen, system, window};
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "cmd", rename_all = "snake_case")]
-pub enum WsCommand {
-    GetStatus,
-    SetVolume { level: f32 },
-    VolumeUp { delta: Option<f32> },
-    VolumeDown { delta: Option<f32> },
-    ToggleMute,
-    NextTrack,
-    PreviousTrack,
-    TogglePlayPause,
-    ToggleMicMute,
-    TakeScreenshot,
+pub enum ControlCommand {
+    // role "all" adds a per-role breakdown; device_id (list_audio_devices) reports that endpoint's
+    // volume/muted instead of the default's
+    GetStatus { role: Option<OutputRole>, device_id: Option<String> },
+    GetFullState,
+    // role: default render device to act on; omitted = console, "all" = console and communications.
+    // device_id: a specific endpoint instead, leaving the defaults alone.
+    // fade_ms ramps to the level in the background instead of jumping there
+    SetVolume { level: f32, role: Option<OutputRole>, device_id: Option<String>, fade_ms: Option<u64> },
+    // 0-100 mapped linearly through the device's dB range
+    SetVolumePercent { percent: f32 },
+    // whole percentage points (+5, -10) on the SetVolumePercent scale
+    AdjustVolumePercent { delta: i32 },
+    // absolute level in dB, clamped to the device's range (get_status reports it)
+    SetVolumeDb { db: f32 },
+    // delta defaults to the configured volume_step; steps repeats it (encoder clicks)
+    VolumeUp { delta: Option<f32>, steps: Option<u32> },
+    VolumeDown { delta: Option<f32>, steps: Option<u32> },
+    ToggleMute { role: Option<OutputRole>, device_id: Option<String> },
+    // without session_id/app_id these send the global media key to whatever Windows
+    // considers current; with one they go to that session (list_media_sessions)
+    NextTrack { session_id: Option<String>, app_id: Option<String> },
+    PreviousTrack { session_id: Option<String>, app_id: Option<String> },
+    TogglePlayPause { session_id: Option<String>, app_id: Option<String> },
+    Stop { session_id: Option<String>, app_id: Option<String> },
+    ListMediaSessions,
+    // position within the current media session's track, for a scrub bar
+    GetPlaybackPosition,
+    // clamped to the track length; not_supported if the app can't seek
+    Seek { position_ms: u64 },
+    // current session; reply with the new shuffle state / repeat mode (off, list, track)
+    ToggleShuffle,
+    CycleRepeat,
+    // ms defaults to the configured seek_step_ms
+    FastForward { ms: Option<u64> },
+    Rewind { ms: Option<u64> },
+    // None = default capture endpoint
+    ToggleMicMute { device_id: Option<String> },
+    // capture level of the default microphone
+    GetMicVolume,
+    SetMicVolume { level: f32 },
+    // mutes the default mic and keeps it muted until turned off again (here or in the GUI)
+    PanicMute { enabled: bool },
+    // -1.0 full left .. 1.0 full right on the default output
+    GetBalance,
+    SetBalance { value: f32 },
+    ListInputDevices,
+    ListOutputDevices,
+    // render and capture endpoints, with the ids device_id fields take
+    ListAudioDevices,
+    SetOutputDevice {
+        id: String,
+        // omitted = every role
+        #[serde(default)]
+        role: OutputRole,
+    },
+    // every capture endpoint, including disabled/unplugged ones, with its state
+    ListCaptureDevices,
+    SetDefaultCaptureDevice { device_id: String },
+    // mix format and exclusive-mode use of any listed input or output device
+    GetDeviceState { id: String },
+    // PNG of `target` in the reply; save_to_disk presses Win+PrtScn instead
+    TakeScreenshot {
+        #[serde(default)]
+        save_to_disk: bool,
+        #[serde(default)]
+        target: ScreenshotTarget,
+    },
+    ListMonitors,
+    // Raw virtual-key event with no auto-release: the client must send the matching up itself
+    SendKeyCode { vk: u16, up: bool },
+    // Unicode text into the focused window; line breaks press Enter
+    TypeText { text: String },
     OpenCalculator,
-    Mute,
-    Unmute,
+    // entries from launchers.json, for the phone to render as buttons
+    ListLaunchers,
+    Launch { id: String },
+    // folder to open, or file to reveal; must be under a configured root
+    OpenPath { path: String },
+    // http(s) or mailto only
+    OpenUrl { url: String },
+    // delay_secs 0 acts immediately; a delayed action replaces any pending one
+    Power {
+        action: PowerAction,
+        #[serde(default)]
+        delay_secs: u64,
+        #[serde(default)]
+        force: bool,
+    },
+    CancelPower,
+    Mute { role: Option<OutputRole>, device_id: Option<String> },
+    Unmute { role: Option<OutputRole>, device_id: Option<String> },
+    Ping { nonce: Option<String> },
+
+    SetFocusedAppVolume { level: f32 },
+    GetAppVolumes,
+    // mixer snapshot: every live session with its process, display name and state
+    ListAudioSessions,
+    // exact session, for when several instances share an executable name
+    SetSessionVolume { pid: u32, level: f32 },
+    // the app behind the current media (SMTC) session, not the master endpoint
+    SetMediaSessionVolume { level: f32 },
+    // exactly one of session_id (from get_app_volumes) or process_name ("discord", "Discord.exe")
+    MuteApp { session_id: Option<String>, process_name: Option<String>, mute: bool },
+    // several app sessions at once, plus optionally the master level; replies per entry
+    ApplyAudioPreset { entries: Vec<PresetEntry>, master: Option<f32> },
+    // the volume mixer's "System Sounds" entry; either field may be omitted
+    GetSystemSounds,
+    SetSystemSounds { level: Option<f32>, mute: Option<bool> },
+
+    UndoAudio,
+
+    SavePreset { name: String, level: f32 },
+    ListPresets,
+    ApplyPreset { name: String },
+    DeletePreset { name: String },
+
+    // output endpoints whose volumes move together (SetGroupVolume)
+    CreateGroup { name: String, members: Vec<String> },
+    ListGroups,
+    DeleteGroup { name: String },
+    SetGroupVolume { group: String, level: f32 },
+}
+
+// Commands about the connection itself; handled in ws.rs, which has the per-connection state
+// they need, and never reach handle_command
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ConnectionCommand {
+    #[serde(rename = "whoami")]
+    WhoAmI,
+    // renames the caller's own entry; the GUI can rename any device
+    RenameDevice { name: String },
+    // pairing window state; the code itself only with the "admin" scope
+    GetPairingStatus,
+    // None stops the per-connection status push
+    SetStatusInterval { ms: Option<u64> },
+    // per-connection {"type":"peak"} push of the output meter; clamped to 50-1000 ms
+    SubscribePeaks { interval_ms: u64 },
+    UnsubscribePeaks,
+    // per-connection {"type":"timeline"} push while media is playing; at least 500 ms
+    SubscribeTimeline { interval_ms: u64 },
+    UnsubscribeTimeline,
+    // false = mutating commands reply {"type":"ok","action":...} without re-reading status
+    SetVerbosity { full_status: bool },
+
+    Subscribe { topics: Vec<String> },
+    Unsubscribe { topics: Vec<String> },
+}
+
+// Tags that parse as ConnectionCommand; keep in sync with it
+const CONNECTION_COMMANDS: &[&str] = &[
+    "whoami",
+    "rename_device",
+    "get_pairing_status",
+    "set_status_interval",
+    "subscribe_peaks",
+    "unsubscribe_peaks",
+    "subscribe_timeline",
+    "unsubscribe_timeline",
+    "set_verbosity",
+    "subscribe",
+    "unsubscribe",
+];
 
+// Handshake commands; handled entirely in ws.rs and never reach handle_command
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum AuthCommand {
+    // proves the server's identity before the client sends a token or code; clients that pinned
+    // the identity key check `challenge_sig` against it first
+    Identify { challenge: String },
     Pair {
         code: String,
         device_id: String,
         device_name: Option<String>,
+        // opaque to the server; echoed in status replies (see MAX_CLIENT_META_BYTES)
+        #[serde(default)]
+        client_meta: Option<serde_json::Value>,
+        // random string the server signs with its identity key (see ServerIdentity::sign_challenge)
+        #[serde(default)]
+        challenge: Option<String>,
     },
     Auth {
         device_id: String,
         token: String,
+        #[serde(default)]
+        client_meta: Option<serde_json::Value>,
+        #[serde(default)]
+        challenge: Option<String>,
     },
 }
 
-// NOTE: Pair/Auth are handled in ws.rs. This function is for "device control" commands.
-pub fn handle_command(cmd: WsCommand) -> anyhow::Result<serde_json::Value> {
+// Cap on the serialized client_meta a Pair/Auth may carry
+pub const MAX_CLIENT_META_BYTES: usize = 1024;
+
+impl AuthCommand {
+    pub fn client_meta_len(&self) -> usize {
+        match self {
+            AuthCommand::Pair { client_meta, .. } | AuthCommand::Auth { client_meta, .. } => {
+                client_meta.as_ref().map_or(0, |m| m.to_string().len())
+            }
+            AuthCommand::Identify { .. } => 0,
+        }
+    }
+}
+
+// One client message, routed by its "cmd" tag
+#[derive(Debug)]
+pub enum WsCommand {
+    Auth(AuthCommand),
+    Connection(ConnectionCommand),
+    Control(ControlCommand),
+}
+
+impl WsCommand {
+    pub fn parse(text: &str) -> serde_json::Result<Self> {
+        // pick the enum by tag first so errors describe the command that was meant
+        let v: serde_json::Value = serde_json::from_str(text)?;
+        let tag = v["cmd"].as_str().unwrap_or_default();
+        if matches!(tag, "identify" | "pair" | "auth") {
+            serde_json::from_value(v).map(WsCommand::Auth)
+        } else if CONNECTION_COMMANDS.contains(&tag) {
+            serde_json::from_value(v).map(WsCommand::Connection)
+        } else {
+            serde_json::from_value(v).map(WsCommand::Control)
+        }
+    }
+}
+
+impl ControlCommand {
+    // Scope the caller must hold (see auth_store::ALL_SCOPES); None = always allowed
+    pub fn required_scope(&self) -> Option<&'static str> {
+        match self {
+            ControlCommand::SetVolume { .. }
+            | ControlCommand::SetVolumePercent { .. }
+            | ControlCommand::AdjustVolumePercent { .. }
+            | ControlCommand::SetVolumeDb { .. }
+            | ControlCommand::VolumeUp { .. }
+            | ControlCommand::VolumeDown { .. }
+            | ControlCommand::ToggleMute { .. }
+            | ControlCommand::Mute { .. }
+            | ControlCommand::Unmute { .. }
+            | ControlCommand::ToggleMicMute { .. }
+            | ControlCommand::GetMicVolume
+            | ControlCommand::SetMicVolume { .. }
+            | ControlCommand::PanicMute { .. }
+            | ControlCommand::GetBalance
+            | ControlCommand::SetBalance { .. }
+            | ControlCommand::ListInputDevices
+            | ControlCommand::ListOutputDevices
+            | ControlCommand::ListAudioDevices
+            | ControlCommand::SetOutputDevice { .. }
+            | ControlCommand::ListCaptureDevices
+            | ControlCommand::SetDefaultCaptureDevice { .. }
+            | ControlCommand::GetDeviceState { .. }
+            | ControlCommand::SetFocusedAppVolume { .. }
+            | ControlCommand::GetAppVolumes
+            | ControlCommand::ListAudioSessions
+            | ControlCommand::SetSessionVolume { .. }
+            | ControlCommand::SetMediaSessionVolume { .. }
+            | ControlCommand::MuteApp { .. }
+            | ControlCommand::ApplyAudioPreset { .. }
+            | ControlCommand::GetSystemSounds
+            | ControlCommand::SetSystemSounds { .. }
+            | ControlCommand::UndoAudio
+            | ControlCommand::SavePreset { .. }
+            | ControlCommand::ListPresets
+            | ControlCommand::ApplyPreset { .. }
+            | ControlCommand::DeletePreset { .. }
+            | ControlCommand::CreateGroup { .. }
+            | ControlCommand::ListGroups
+            | ControlCommand::DeleteGroup { .. }
+            | ControlCommand::SetGroupVolume { .. } => Some("volume"),
+            ControlCommand::NextTrack { .. }
+            | ControlCommand::PreviousTrack { .. }
+            | ControlCommand::TogglePlayPause { .. }
+            | ControlCommand::Stop { .. }
+            | ControlCommand::ListMediaSessions
+            | ControlCommand::GetPlaybackPosition
+            | ControlCommand::Seek { .. }
+            | ControlCommand::ToggleShuffle
+            | ControlCommand::CycleRepeat
+            | ControlCommand::FastForward { .. }
+            | ControlCommand::Rewind { .. } => Some("media"),
+            ControlCommand::TakeScreenshot { .. }
+            | ControlCommand::ListMonitors
+            | ControlCommand::OpenCalculator
+            | ControlCommand::ListLaunchers
+            | ControlCommand::Launch { .. }
+            | ControlCommand::OpenPath { .. }
+            | ControlCommand::OpenUrl { .. } => Some("system"),
+            ControlCommand::Power { .. } | ControlCommand::CancelPower => Some("power"),
+            ControlCommand::SendKeyCode { .. } | ControlCommand::TypeText { .. } => Some("input"),
+            ControlCommand::GetStatus { .. }
+            | ControlCommand::GetFullState
+            | ControlCommand::Ping { .. } => None,
+        }
+    }
+
+    // Wire name of the command (the "cmd" tag), used as the metrics key
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControlCommand::GetStatus { .. } => "get_status",
+            ControlCommand::GetFullState => "get_full_state",
+            ControlCommand::SetVolume { .. } => "set_volume",
+            ControlCommand::SetVolumePercent { .. } => "set_volume_percent",
+            ControlCommand::AdjustVolumePercent { .. } => "adjust_volume_percent",
+            ControlCommand::SetVolumeDb { .. } => "set_volume_db",
+            ControlCommand::VolumeUp { .. } => "volume_up",
+            ControlCommand::VolumeDown { .. } => "volume_down",
+            ControlCommand::ToggleMute { .. } => "toggle_mute",
+            ControlCommand::NextTrack { .. } => "next_track",
+            ControlCommand::PreviousTrack { .. } => "previous_track",
+            ControlCommand::TogglePlayPause { .. } => "toggle_play_pause",
+            ControlCommand::Stop { .. } => "stop",
+            ControlCommand::ListMediaSessions => "list_media_sessions",
+            ControlCommand::GetPlaybackPosition => "get_playback_position",
+            ControlCommand::Seek { .. } => "seek",
+            ControlCommand::ToggleShuffle => "toggle_shuffle",
+            ControlCommand::CycleRepeat => "cycle_repeat",
+            ControlCommand::FastForward { .. } => "fast_forward",
+            ControlCommand::Rewind { .. } => "rewind",
+            ControlCommand::ToggleMicMute { .. } => "toggle_mic_mute",
+            ControlCommand::GetMicVolume => "get_mic_volume",
+            ControlCommand::PanicMute { .. } => "panic_mute",
+            ControlCommand::GetBalance => "get_balance",
+            ControlCommand::SetBalance { .. } => "set_balance",
+            ControlCommand::SetMicVolume { .. } => "set_mic_volume",
+            ControlCommand::ListInputDevices => "list_input_devices",
+            ControlCommand::ListOutputDevices => "list_output_devices",
+            ControlCommand::ListAudioDevices => "list_audio_devices",
+            ControlCommand::SetOutputDevice { .. } => "set_output_device",
+            ControlCommand::ListCaptureDevices => "list_capture_devices",
+            ControlCommand::SetDefaultCaptureDevice { .. } => "set_default_capture_device",
+            ControlCommand::GetDeviceState { .. } => "get_device_state",
+            ControlCommand::TakeScreenshot { .. } => "take_screenshot",
+            ControlCommand::ListMonitors => "list_monitors",
+            ControlCommand::SendKeyCode { .. } => "send_key_code",
+            ControlCommand::TypeText { .. } => "type_text",
+            ControlCommand::OpenCalculator => "open_calculator",
+            ControlCommand::ListLaunchers => "list_launchers",
+            ControlCommand::Launch { .. } => "launch",
+            ControlCommand::OpenPath { .. } => "open_path",
+            ControlCommand::OpenUrl { .. } => "open_url",
+            ControlCommand::Power { .. } => "power",
+            ControlCommand::CancelPower => "cancel_power",
+            ControlCommand::Mute { .. } => "mute",
+            ControlCommand::Unmute { .. } => "unmute",
+            ControlCommand::Ping { .. } => "ping",
+            ControlCommand::SetFocusedAppVolume { .. } => "set_focused_app_volume",
+            ControlCommand::GetAppVolumes => "get_app_volumes",
+            ControlCommand::ListAudioSessions => "list_audio_sessions",
+            ControlCommand::SetSessionVolume { .. } => "set_session_volume",
+            ControlCommand::SetMediaSessionVolume { .. } => "set_media_session_volume",
+            ControlCommand::MuteApp { .. } => "mute_app",
+            ControlCommand::ApplyAudioPreset { .. } => "apply_audio_preset",
+            ControlCommand::GetSystemSounds => "get_system_sounds",
+            ControlCommand::SetSystemSounds { .. } => "set_system_sounds",
+            ControlCommand::UndoAudio => "undo_audio",
+            ControlCommand::SavePreset { .. } => "save_preset",
+            ControlCommand::ListPresets => "list_presets",
+            ControlCommand::ApplyPreset { .. } => "apply_preset",
+            ControlCommand::DeletePreset { .. } => "delete_preset",
+            ControlCommand::CreateGroup { .. } => "create_group",
+            ControlCommand::ListGroups => "list_groups",
+            ControlCommand::DeleteGroup { .. } => "delete_group",
+            ControlCommand::SetGroupVolume { .. } => "set_group_volume",
+        }
+    }
+
+    // Commands that send input or launch apps on the user's desktop
+    fn needs_interactive_session(&self) -> bool {
+        self.sends_media_key()
+            || matches!(
+                self,
+                ControlCommand::TakeScreenshot { .. }
+                | ControlCommand::OpenCalculator
+                    | ControlCommand::Launch { .. }
+                    | ControlCommand::OpenPath { .. }
+                    | ControlCommand::OpenUrl { .. }
+                    | ControlCommand::SendKeyCode { .. }
+                    | ControlCommand::TypeText { .. }
+            )
+    }
+
+    // Whether the server config turns this command off for everyone, whatever the device's scopes
+    pub fn disabled_by(&self, categories: &[String]) -> bool {
+        self.required_scope().is_some_and(|s| categories.iter().any(|c| c == s))
+    }
+
+    // Commands that change something on the PC (what `request_ack` applies to)
+    pub fn mutates(&self) -> bool {
+        self.required_scope().is_some()
+            && !matches!(
+                self,
+                ControlCommand::ListInputDevices
+                    | ControlCommand::GetMicVolume
+                    | ControlCommand::GetBalance
+                    | ControlCommand::ListCaptureDevices
+                    | ControlCommand::ListOutputDevices
+                    | ControlCommand::ListAudioDevices
+                    | ControlCommand::GetDeviceState { .. }
+                    | ControlCommand::ListPresets
+                    | ControlCommand::ListLaunchers
+                    | ControlCommand::ListMonitors
+                    | ControlCommand::ListGroups
+                    | ControlCommand::GetAppVolumes
+                    | ControlCommand::ListAudioSessions
+                    | ControlCommand::GetSystemSounds
+                    | ControlCommand::GetPlaybackPosition
+                    | ControlCommand::ListMediaSessions
+            )
+    }
+
+    // Commands that go through SendInput, and so through the input rate limiter
+    fn injects_input(&self) -> bool {
+        matches!(
+            self,
+            ControlCommand::SendKeyCode { .. }
+                | ControlCommand::TypeText { .. }
+                | ControlCommand::TakeScreenshot { save_to_disk: true, .. }
+        )
+    }
+
+    // Limiter tokens this command costs: one per typed character, else one
+    pub(crate) fn input_events(&self) -> usize {
+        match self {
+            ControlCommand::TypeText { text } => system::typed_chars(text).len().max(1),
+            _ => 1,
+        }
+    }
+
+    // Transport commands without a session target; targeted ones talk to GSMTC instead
+    fn sends_media_key(&self) -> bool {
+        matches!(
+            self,
+            ControlCommand::NextTrack { session_id: None, app_id: None }
+                | ControlCommand::PreviousTrack { session_id: None, app_id: None }
+                | ControlCommand::TogglePlayPause { session_id: None, app_id: None }
+                | ControlCommand::Stop { session_id: None, app_id: None }
+        )
+    }
+
+    // Commands whose prior master volume/mute state UndoAudio can restore
+    fn changes_master(&self) -> bool {
+        match self {
+            ControlCommand::SetVolume { role, device_id, .. }
+            | ControlCommand::ToggleMute { role, device_id }
+            | ControlCommand::Mute { role, device_id }
+            | ControlCommand::Unmute { role, device_id } => device_id.is_none() && targets_console(*role),
+            ControlCommand::SetVolumePercent { .. }
+            | ControlCommand::AdjustVolumePercent { .. }
+            | ControlCommand::SetVolumeDb { .. }
+            | ControlCommand::VolumeUp { .. }
+            | ControlCommand::VolumeDown { .. }
+            | ControlCommand::ApplyPreset { .. } => true,
+            ControlCommand::ApplyAudioPreset { master, .. } => master.is_some(),
+            _ => false,
+        }
+    }
+}
+
+// Device commands advertised in hello; keep in sync with ControlCommand
+const DEVICE_COMMANDS: &[&str] = &[
+    "get_status",
+    "get_full_state",
+    "set_volume",
+    "set_volume_percent",
+    "adjust_volume_percent",
+    "set_volume_db",
+    "volume_up",
+    "volume_down",
+    "toggle_mute",
+    "mute",
+    "unmute",
+    "toggle_mic_mute",
+    "get_mic_volume",
+    "set_mic_volume",
+    "panic_mute",
+    "get_balance",
+    "set_balance",
+    "list_input_devices",
+    "list_output_devices",
+    "list_audio_devices",
+    "set_output_device",
+    "list_capture_devices",
+    "set_default_capture_device",
+    "get_device_state",
+    "set_focused_app_volume",
+    "get_app_volumes",
+    "list_audio_sessions",
+    "set_session_volume",
+    "set_media_session_volume",
+    "mute_app",
+    "apply_audio_preset",
+    "get_system_sounds",
+    "set_system_sounds",
+    "undo_audio",
+    "save_preset",
+    "list_presets",
+    "apply_preset",
+    "delete_preset",
+    "create_group",
+    "list_groups",
+    "delete_group",
+    "set_group_volume",
+    "next_track",
+    "previous_track",
+    "toggle_play_pause",
+    "stop",
+    "list_media_sessions",
+    "get_playback_position",
+    "seek",
+    "toggle_shuffle",
+    "cycle_repeat",
+    "fast_forward",
+    "rewind",
+    "take_screenshot",
+    "list_monitors",
+    "open_calculator",
+    "list_launchers",
+    "launch",
+    "open_path",
+    "open_url",
+    "power",
+    "cancel_power",
+    "send_key_code",
+    "type_text",
+];
+
+// Same set as ControlCommand::needs_interactive_session
+const INTERACTIVE_COMMANDS: &[&str] = &[
+    "next_track",
+    "previous_track",
+    "toggle_play_pause",
+    "stop",
+    "take_screenshot",
+    "open_calculator",
+    "launch",
+    "open_path",
+    "open_url",
+    "send_key_code",
+    "type_text",
+];
+
+// Same mapping as ControlCommand::required_scope, by wire name
+fn category_of(command: &str) -> Option<&'static str> {
+    match command {
+        "get_status" | "get_full_state" => None,
+        "next_track" | "previous_track" | "toggle_play_pause" | "stop" | "list_media_sessions"
+        | "get_playback_position" | "seek" | "toggle_shuffle" | "cycle_repeat"
+        | "fast_forward" | "rewind" => Some("media"),
+        "take_screenshot" | "list_monitors" | "open_calculator" | "list_launchers" | "launch" | "open_path" | "open_url" => Some("system"),
+        "power" | "cancel_power" => Some("power"),
+        "send_key_code" | "type_text" => Some("input"),
+        _ => Some("volume"),
+    }
+}
+
+// Commands usable right now; input-dependent ones are dropped without an interactive desktop,
+// and disabled categories are never advertised
+pub fn capabilities(interactive: bool, disabled: &[String]) -> Vec<&'static str> {
+    DEVICE_COMMANDS
+        .iter()
+        .copied()
+        .filter(|c| interactive || !INTERACTIVE_COMMANDS.contains(c))
+        .filter(|c| !category_of(c).is_some_and(|cat| disabled.iter().any(|d| d == cat)))
+        .collect()
+}
+
+const UNDO_DEPTH: usize = 5;
+const FADE_STEPS: u32 = 10;
+const MAX_FADE_MS: u64 = 10_000;
+
+// Per-connection (volume, muted) snapshots, newest last
+#[derive(Default)]
+pub struct AudioHistory(VecDeque<(f32, bool)>);
+
+impl AudioHistory {
+    fn push(&mut self, state: (f32, bool)) {
+        if self.0.len() == UNDO_DEPTH {
+            self.0.pop_front();
+        }
+        self.0.push_back(state);
+    }
+
+    fn pop(&mut self) -> Option<(f32, bool)> {
+        self.0.pop_back()
+    }
+}
+
+// What device commands run against; cloned into the blocking task for each command
+#[derive(Clone)]
+pub struct CommandCtx {
+    pub audio: Arc<dyn AudioBackend>,
+    pub presets: Arc<Mutex<PresetStore>>,
+    pub groups: Arc<Mutex<GroupStore>>,
+    pub launchers: Arc<Mutex<LauncherStore>>,
+    pub history: Arc<Mutex<AudioHistory>>,
+    pub metrics: Arc<Metrics>,
+    // shared by every connection: the desktop is one resource
+    pub input_limiter: Arc<Mutex<TokenBucket>>,
+    // bumped by every master volume change; a running fade stops once it no longer matches
+    pub fade_generation: Arc<AtomicUsize>,
+    // per connection, see SetVerbosity
+    pub full_status: bool,
+    // from the last Pair/Auth; echoed back, never interpreted
+    pub client_meta: Option<serde_json::Value>,
+    pub config: SharedConfig,
+    // connected clients, authenticated or not (shared with the client slots in ws.rs)
+    pub active_clients: Arc<AtomicUsize>,
+    // server-wide; while set, ws.rs keeps the default mic muted
+    pub panic_mute: Arc<AtomicBool>,
+    // server-wide, see PowerSchedule
+    pub power: PowerSchedule,
+}
+
+pub fn handle_command(cmd: ControlCommand, ctx: &CommandCtx) -> anyhow::Result<serde_json::Value> {
+    let name = cmd.name();
+    let result = dispatch(cmd, ctx);
+    let failed = match &result {
+        Ok(v) => v["type"] == "error",
+        Err(_) => true,
+    };
+    ctx.metrics.record_command(name, failed);
+    result
+}
+
+fn dispatch(cmd: ControlCommand, ctx: &CommandCtx) -> anyhow::Result<serde_json::Value> {
+    // an unknown id is not_found whatever session the server runs in; the launcher found
+    // here is the one execute starts, so launchers.json is only read once
+    let launcher = match &cmd {
+        ControlCommand::Launch { id } => match find_launcher(ctx, id) {
+            Some(launcher) => Some(launcher),
+            None => return Ok(launcher_not_found(id)),
+        },
+        _ => None,
+    };
+
+    if cmd.needs_interactive_session() && !system::has_interactive_session() {
+        return Ok(error(ErrorCode::NoInteractiveSession, None));
+    }
+
+    // SendInput reports success even when UIPI discards the events, so check up front
+    if cmd.injects_input() && window::foreground_blocks_input() {
+        return Ok(input_error_reply(InputError::BlockedByUipi));
+    }
+
+    if let ControlCommand::TypeText { text } = &cmd
+        && text.chars().count() > MAX_TYPE_TEXT_CHARS
+    {
+        return Ok(error(ErrorCode::BadRequest, Some(format!("text is over {MAX_TYPE_TEXT_CHARS} characters"))));
+    }
+
+    if cmd.injects_input() {
+        let per_sec = ctx.config.read().unwrap().input_events_per_sec;
+        let mut limiter = ctx.input_limiter.lock().unwrap();
+        limiter.set_rate(per_sec);
+        let cost = cmd.input_events();
+        if !limiter.try_take(cost) {
+            // a long text may have to wait for the bucket to refill
+            let message = (cost > 1).then(|| format!("{} needs {cost} input events; the limit is {per_sec}/s", cmd.name()));
+            return Ok(error(ErrorCode::InputRateLimited, message));
+        }
+    }
+
+    // undo_audio only gets the snapshot once the change went through
+    let before = cmd.changes_master().then(|| ctx.audio.get_volume_and_mute()).transpose()?;
+    // any SetVolume (whatever its role or device) and an undo stop a running fade
+    if cmd.changes_master() || matches!(cmd, ControlCommand::SetVolume { .. } | ControlCommand::UndoAudio) {
+        ctx.fade_generation.fetch_add(1, Ordering::SeqCst);
+    }
+    let reply = execute(cmd, ctx, launcher)?;
+    if let Some(before) = before
+        && reply["type"] != "error"
+    {
+        ctx.history.lock().unwrap().push(before);
+    }
+    Ok(reply)
+}
+
+fn execute(cmd: ControlCommand, ctx: &CommandCtx, launcher: Option<Launcher>) -> anyhow::Result<serde_json::Value> {
+    let audio = ctx.audio.as_ref();
+    let presets = &ctx.presets;
+
     match cmd {
-        WsCommand::GetStatus => {
-            let (vol, muted) = audio::get_volume_and_mute()?;
-            let mic_muted = audio::get_mic_mute()?;
-            Ok(json!({"type":"status","volume":vol,"muted":muted,"mic_muted":mic_muted}))
+        ControlCommand::GetStatus { role, device_id } => {
+            if role.is_some() && device_id.is_some() {
+                return Ok(error(ErrorCode::BadRequest, Some("give role or device_id, not both".into())));
+            }
+            let (vol, muted) = audio.get_volume_and_mute()?;
+            let (min_db, max_db) = audio.get_volume_range_db()?;
+            // mic fields are null on a machine without a microphone, or one that vanished mid-read
+            let input = audio.default_input_device().ok().flatten();
+            let (mic_muted, mic_volume) = match input {
+                Some(_) => (audio.get_mic_mute(None).ok(), audio.get_mic_volume().ok()),
+                None => (None, None),
+            };
+            // lets clients highlight the current entry of list_output_devices; null with no device
+            let output = audio.default_output_device().ok();
+            let mut v = json!({
+                "type": "status",
+                "volume": vol,
+                "muted": muted,
+                "volume_db": audio.get_volume_db()?,
+                "volume_range_db": [min_db, max_db],
+                "mic_muted": mic_muted,
+                "mic_volume": mic_volume,
+                "has_microphone": input.is_some(),
+                "panic_mute": ctx.panic_mute.load(Ordering::SeqCst),
+                "pending_power": ctx.power.to_json(),
+                "default_output_id": output.as_ref().map(|d| &d.id),
+                "output_device": output.map(|d| d.name),
+                "input_device": input.map(|d| d.name),
+                // WinRT trouble reads as no session rather than failing the volume status
+                "playback": media::current_playback()
+                    .ok()
+                    .flatten()
+                    .map_or_else(|| json!({"status":"none","app":null,"title":null}), |p| json!(p)),
+            });
+            if role.is_some() {
+                merge(&mut v, role_state(audio, role)?);
+            }
+            // the dB fields stay those of the default output
+            if let Some(id) = device_id {
+                let Some((vol, muted)) = audio.get_endpoint_volume_and_mute(&id)? else {
+                    return Ok(device_not_found(&id));
+                };
+                merge(&mut v, json!({"device_id": id, "volume": vol, "muted": muted}));
+            }
+            if let Some(meta) = &ctx.client_meta {
+                v["client_meta"] = meta.clone();
+            }
+            Ok(v)
+        }
+        ControlCommand::GetFullState => {
+            // each piece degrades to null on its own
+            let (vol, muted) = audio.get_volume_and_mute().ok().unzip();
+            Ok(json!({
+                "type": "full_state",
+                "volume": vol,
+                "muted": muted,
+                "mic_muted": audio.get_mic_mute(None).ok(),
+                "mic_volume": audio.get_mic_volume().ok(),
+                "has_microphone": audio.default_input_device().is_ok_and(|d| d.is_some()),
+                "panic_mute": ctx.panic_mute.load(Ordering::SeqCst),
+                "now_playing": media::now_playing().ok().flatten(),
+                "output_device": audio.default_output_device().ok(),
+                "capabilities": capabilities(system::has_interactive_session(), &ctx.config.read().unwrap().refused_categories()),
+                "client_meta": ctx.client_meta,
+            }))
+        }
+        ControlCommand::SetVolume { level, role, device_id: Some(id), fade_ms } => {
+            if role.is_some() || fade_ms.is_some() {
+                return Ok(error(ErrorCode::BadRequest, Some("device_id can't be combined with role or fade_ms".into())));
+            }
+            if !audio.set_endpoint_volume(&id, level.clamp(0.0, 1.0))? {
+                return Ok(device_not_found(&id));
+            }
+            endpoint_reply(ctx, "set_volume", &id)
         }
-        WsCommand::SetVolume { level } => {
+        ControlCommand::SetVolume { level, role, fade_ms, .. } => {
             let level = level.clamp(0.0, 1.0);
-            audio::set_volume(level)?;
-            let (vol, muted) = audio::get_volume_and_mute()?;
-            Ok(json!({"type":"ok","action":"set_volume","volume":vol,"muted":muted}))
+            let fade_ms = fade_ms.unwrap_or(0).min(MAX_FADE_MS);
+            if fade_ms == 0 {
+                for r in render_roles(role) {
+                    set_role_volume(audio, r, level)?;
+                }
+            } else {
+                let from = render_roles(role)
+                    .into_iter()
+                    .map(|r| Ok((r, role_volume_and_mute(audio, r)?.0)))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                start_fade(ctx, from, level, Duration::from_millis(fade_ms));
+            }
+            if !ctx.full_status {
+                return Ok(minimal_ok("set_volume"));
+            }
+            let mut v = json!({"type":"ok","action":"set_volume"});
+            merge(&mut v, role_state(audio, role)?);
+            if fade_ms > 0 {
+                // report where the fade ends up, not wherever it is right now
+                v["volume"] = json!(level);
+                if let Some(roles) = v["roles"].as_object_mut() {
+                    for state in roles.values_mut() {
+                        state["volume"] = json!(level);
+                    }
+                }
+                v["fade_ms"] = json!(fade_ms);
+            }
+            Ok(v)
         }
-        WsCommand::VolumeUp { delta } => {
-            let delta = delta.unwrap_or(0.05).clamp(0.0, 1.0);
-            let (mut vol, _) = audio::get_volume_and_mute()?;
+        ControlCommand::SetVolumePercent { percent } => {
+            let percent = percent.clamp(0.0, 100.0);
+            let (min_db, max_db) = audio.get_volume_range_db()?;
+            audio.set_volume_db(min_db + (max_db - min_db) * percent / 100.0)?;
+            if !ctx.full_status {
+                return Ok(minimal_ok("set_volume_percent"));
+            }
+            let db = audio.get_volume_db()?;
+            let (vol, muted) = audio.get_volume_and_mute()?;
+            Ok(json!({
+                "type": "ok",
+                "action": "set_volume_percent",
+                "percent": db_to_percent(db, min_db, max_db),
+                "volume_db": db,
+                "volume": vol,
+                "muted": muted
+            }))
+        }
+        ControlCommand::AdjustVolumePercent { delta } => {
+            let (min_db, max_db) = audio.get_volume_range_db()?;
+            let current = db_to_percent(audio.get_volume_db()?, min_db, max_db).round() as i32;
+            let percent = current.saturating_add(delta).clamp(0, 100);
+            audio.set_volume_db(min_db + (max_db - min_db) * percent as f32 / 100.0)?;
+            // the new percent is the point of this command, so it's sent even in minimal mode
+            if !ctx.full_status {
+                return Ok(json!({"type":"ok","action":"adjust_volume_percent","percent":percent}));
+            }
+            let (vol, muted) = audio.get_volume_and_mute()?;
+            Ok(json!({
+                "type": "ok",
+                "action": "adjust_volume_percent",
+                "percent": percent,
+                "volume_db": audio.get_volume_db()?,
+                "volume": vol,
+                "muted": muted
+            }))
+        }
+        ControlCommand::SetVolumeDb { db } => {
+            let (min_db, max_db) = audio.get_volume_range_db()?;
+            audio.set_volume_db(db.clamp(min_db, max_db))?;
+            // both scales go out even in minimal mode, so a client can render either
+            let (vol, muted) = audio.get_volume_and_mute()?;
+            let mut v = json!({
+                "type": "ok",
+                "action": "set_volume_db",
+                "volume": vol,
+                "volume_db": audio.get_volume_db()?,
+            });
+            if ctx.full_status {
+                v["muted"] = json!(muted);
+                v["volume_range_db"] = json!([min_db, max_db]);
+            }
+            Ok(v)
+        }
+        ControlCommand::VolumeUp { delta, steps } => {
+            let delta = volume_step(ctx, delta, steps);
+            let (mut vol, _) = audio.get_volume_and_mute()?;
             vol = (vol + delta).clamp(0.0, 1.0);
-            audio::set_volume(vol)?;
-            let (vol, muted) = audio::get_volume_and_mute()?;
+            audio.set_volume(vol)?;
+            if !ctx.full_status {
+                return Ok(minimal_ok("volume_up"));
+            }
+            let (vol, muted) = audio.get_volume_and_mute()?;
             Ok(json!({"type":"ok","action":"volume_up","volume":vol,"muted":muted}))
         }
-        WsCommand::VolumeDown { delta } => {
-            let delta = delta.unwrap_or(0.05).clamp(0.0, 1.0);
-            let (mut vol, _) = audio::get_volume_and_mute()?;
+        ControlCommand::VolumeDown { delta, steps } => {
+            let delta = volume_step(ctx, delta, steps);
+            let (mut vol, _) = audio.get_volume_and_mute()?;
             vol = (vol - delta).clamp(0.0, 1.0);
-            audio::set_volume(vol)?;
-            let (vol, muted) = audio::get_volume_and_mute()?;
+            audio.set_volume(vol)?;
+            if !ctx.full_status {
+                return Ok(minimal_ok("volume_down"));
+            }
+            let (vol, muted) = audio.get_volume_and_mute()?;
             Ok(json!({"type":"ok","action":"volume_down","volume":vol,"muted":muted}))
         }
-        WsCommand::ToggleMute => {
-            let (_, muted) = audio::get_volume_and_mute()?;
-            audio::set_mute(!muted)?;
-            let (vol, muted) = audio::get_volume_and_mute()?;
+        ControlCommand::ToggleMute { role, device_id: Some(id) } => {
+            let Some((_, muted)) = audio.get_endpoint_volume_and_mute(&id)? else {
+                return Ok(device_not_found(&id));
+            };
+            endpoint_mute(ctx, "toggle_mute", &id, role, !muted)
+        }
+        ControlCommand::Mute { role, device_id: Some(id) } => endpoint_mute(ctx, "mute", &id, role, true),
+        ControlCommand::Unmute { role, device_id: Some(id) } => endpoint_mute(ctx, "unmute", &id, role, false),
+        ControlCommand::ToggleMute { role, .. } => {
+            // with several roles the first one decides, so they end up in the same state
+            let roles = render_roles(role);
+            let (_, muted) = role_volume_and_mute(audio, roles[0])?;
+            for r in roles {
+                set_role_mute(audio, r, !muted)?;
+            }
+            if !ctx.full_status {
+                return Ok(minimal_ok("toggle_mute"));
+            }
+            let mut v = json!({"type":"ok","action":"toggle_mute"});
+            merge(&mut v, role_state(audio, role)?);
+            Ok(v)
+        }
+        ControlCommand::Mute { role, .. } => {
+            for r in render_roles(role) {
+                set_role_mute(audio, r, true)?;
+            }
+            if !ctx.full_status {
+                return Ok(minimal_ok("mute"));
+            }
+            let mut v = json!({"type":"ok","action":"mute"});
+            merge(&mut v, role_state(audio, role)?);
+            Ok(v)
+        }
+        ControlCommand::Unmute { role, .. } => {
+            for r in render_roles(role) {
+                set_role_mute(audio, r, false)?;
+            }
+            if !ctx.full_status {
+                return Ok(minimal_ok("unmute"));
+            }
+            let mut v = json!({"type":"ok","action":"unmute"});
+            merge(&mut v, role_state(audio, role)?);
+            Ok(v)
+        }
+        ControlCommand::NextTrack { session_id, app_id } => {
+            media_transport(ctx, session_id, app_id, TransportAction::Next, "next_track", media::next_track)
+        }
+        ControlCommand::PreviousTrack { session_id, app_id } => {
+            media_transport(ctx, session_id, app_id, TransportAction::Previous, "previous_track", media::previous_track)
+        }
+        ControlCommand::TogglePlayPause { session_id, app_id } => media_transport(
+            ctx,
+            session_id,
+            app_id,
+            TransportAction::TogglePlayPause,
+            "toggle_play_pause",
+            media::toggle_play_pause,
+        ),
+        ControlCommand::Stop { session_id, app_id } => {
+            media_transport(ctx, session_id, app_id, TransportAction::Stop, "stop", media::stop)
+        }
+        ControlCommand::ListMediaSessions => {
+            Ok(json!({"type":"media_sessions","sessions":media::list_media_sessions()?}))
+        }
+        ControlCommand::GetPlaybackPosition => match media::playback_position()? {
+            Some(p) => Ok(json!({
+                "type": "playback_position",
+                "active": true,
+                "position_ms": p.position_ms,
+                "duration_ms": p.duration_ms,
+                "can_seek": p.can_seek,
+                "playing": p.playing,
+            })),
+            None => Ok(json!({"type":"playback_position","active":false})),
+        },
+        ControlCommand::Seek { position_ms } => match media::seek(position_ms)? {
+            ControlOutcome::Changed(position_ms) => Ok(json!({"type":"ok","action":"seek","position_ms":position_ms})),
+            outcome => Ok(control_refused(outcome, "seeking")),
+        },
+        ControlCommand::FastForward { ms } => jump(ctx, "fast_forward", ms, 1),
+        ControlCommand::Rewind { ms } => jump(ctx, "rewind", ms, -1),
+        ControlCommand::ToggleShuffle => match media::toggle_shuffle()? {
+            ControlOutcome::Changed(shuffle) => Ok(json!({"type":"ok","action":"toggle_shuffle","shuffle":shuffle})),
+            outcome => Ok(control_refused(outcome, "shuffle")),
+        },
+        ControlCommand::CycleRepeat => match media::cycle_repeat()? {
+            ControlOutcome::Changed(repeat) => Ok(json!({"type":"ok","action":"cycle_repeat","repeat":repeat})),
+            outcome => Ok(control_refused(outcome, "repeat")),
+        },
+        ControlCommand::ToggleMicMute { device_id } => {
+            if ctx.panic_mute.load(Ordering::SeqCst) {
+                return Ok(error(ErrorCode::PanicMuteActive, Some("release panic mute first".into())));
+            }
+            let mic_muted = toggle_mic_mute(audio, device_id.as_deref())?;
+            if !ctx.full_status {
+                return Ok(minimal_ok("toggle_mic_mute"));
+            }
+            let (vol, muted) = audio.get_volume_and_mute()?;
             Ok(json!({
                 "type": "ok",
-                "action": "toggle_mute",
+                "action": "toggle_mic_mute",
+                "device_id": device_id,
                 "volume": vol,
-                "muted": muted
+                "muted": muted,
+                "mic_muted": mic_muted
             }))
         }
-        WsCommand::Mute => {
-            audio::set_mute(true)?;
-            let (vol, muted) = audio::get_volume_and_mute()?;
-            Ok(json!({"type":"ok","action":"mute","volume":vol,"muted":muted}))
+        ControlCommand::PanicMute { enabled } => {
+            // turning it off leaves the mic muted; unmuting is a separate, deliberate step
+            if enabled {
+                audio.set_mic_mute(None, true)?;
+            }
+            ctx.panic_mute.store(enabled, Ordering::SeqCst);
+            Ok(json!({
+                "type": "ok",
+                "action": "panic_mute",
+                "enabled": enabled,
+                "mic_muted": audio.get_mic_mute(None).ok(),
+            }))
+        }
+        ControlCommand::GetBalance => match audio.get_balance()? {
+            Some(value) => Ok(json!({"type":"balance","supported":true,"value":value})),
+            None => Ok(json!({"type":"balance","supported":false,"value":null})),
+        },
+        ControlCommand::SetBalance { value } => {
+            let value = value.clamp(-1.0, 1.0);
+            if !audio.set_balance(value)? {
+                return Ok(error(ErrorCode::NotSupported, Some("the output device is mono".into())));
+            }
+            Ok(json!({"type":"ok","action":"set_balance","value":value}))
+        }
+        ControlCommand::GetMicVolume => {
+            let level = audio.get_mic_volume()?;
+            let muted = audio.get_mic_mute(None)?;
+            Ok(json!({"type":"mic_volume","level":level,"muted":muted}))
+        }
+        ControlCommand::SetMicVolume { level } => {
+            let level = level.clamp(0.0, 1.0);
+            audio.set_mic_volume(level)?;
+            if !ctx.full_status {
+                return Ok(minimal_ok("set_mic_volume"));
+            }
+            let level = audio.get_mic_volume()?;
+            let muted = audio.get_mic_mute(None)?;
+            Ok(json!({"type":"ok","action":"set_mic_volume","mic_volume":level,"mic_muted":muted}))
+        }
+        ControlCommand::ListInputDevices => {
+            let devices = audio.list_input_devices()?;
+            Ok(json!({"type":"input_devices","devices":devices}))
+        }
+        ControlCommand::ListOutputDevices => {
+            let devices = audio.list_output_devices()?;
+            Ok(json!({"type":"output_devices","devices":devices}))
         }
-        WsCommand::Unmute => {
-            audio::set_mute(false)?;
-            let (vol, muted) = audio::get_volume_and_mute()?;
-            Ok(json!({"type":"ok","action":"unmute","volume":vol,"muted":muted}))
+        ControlCommand::ListAudioDevices => {
+            let outputs = audio.list_output_devices()?;
+            let inputs = audio.list_input_devices()?;
+            let devices: Vec<_> = outputs
+                .into_iter()
+                .map(|d| json!({"id": d.id, "name": d.name, "flow": "render", "is_default": d.is_default}))
+                .chain(
+                    inputs
+                        .into_iter()
+                        .map(|d| json!({"id": d.id, "name": d.name, "flow": "capture", "is_default": d.is_default})),
+                )
+                .collect();
+            Ok(json!({"type":"audio_devices","devices":devices}))
         }
-        WsCommand::NextTrack => {
-            media::next_track()?;
-            Ok(json!({"type":"ok","action":"next_track"}))
+        ControlCommand::SetOutputDevice { id, role } => {
+            if !audio.list_output_devices()?.iter().any(|d| d.id == id) {
+                return Ok(error(ErrorCode::NotFound, Some(format!("no active output device {id}"))));
+            }
+            audio.set_default_output(&id, role)?;
+            Ok(json!({"type":"ok","action":"set_output_device","id":id,"role":role}))
         }
-        WsCommand::PreviousTrack => {
-            media::previous_track()?;
-            Ok(json!({"type":"ok","action":"previous_track"}))
+        ControlCommand::ListCaptureDevices => {
+            let devices = audio.list_capture_devices()?;
+            Ok(json!({"type":"capture_devices","devices":devices}))
         }
-        WsCommand::TogglePlayPause => {
-            media::toggle_play_pause()?;
-            Ok(json!({"type":"ok","action":"toggle_play_pause"}))
+        ControlCommand::SetDefaultCaptureDevice { device_id } => {
+            let devices = audio.list_capture_devices()?;
+            let Some(device) = devices.iter().find(|d| d.id == device_id) else {
+                return Ok(error(ErrorCode::NotFound, Some(format!("no capture device {device_id}"))));
+            };
+            // Windows accepts an inactive endpoint as default without complaint, leaving no working mic
+            if device.state != EndpointState::Active {
+                let mut v = error(ErrorCode::DeviceUnavailable, Some(format!("{} is not active", device.name)));
+                v["state"] = json!(device.state);
+                return Ok(v);
+            }
+            audio.set_default_capture(&device_id)?;
+            Ok(json!({"type":"ok","action":"set_default_capture_device","device_id":device_id}))
         }
-        WsCommand::ToggleMicMute => {
-            let mic_muted = audio::get_mic_mute()?;
-            audio::set_mic_mute(!mic_muted)?;
-            let (vol, muted) = audio::get_volume_and_mute()?;
-            let mic_muted = audio::get_mic_mute()?;
-            Ok(json!({"type":"ok","action":"toggle_mic_mute","volume":vol,"muted":muted,"mic_muted":mic_muted}))
+        ControlCommand::GetDeviceState { id } => {
+            let known = audio.list_output_devices()?.iter().any(|d| d.id == id)
+                || audio.list_input_devices()?.iter().any(|d| d.id == id);
+            if !known {
+                return Ok(error(ErrorCode::NotFound, Some(format!("no active audio device {id}"))));
+            }
+            let state = audio.device_state(&id)?;
+            Ok(json!({"type":"device_state","device":state}))
         }
-        WsCommand::TakeScreenshot => {
+        ControlCommand::TakeScreenshot { save_to_disk: true, target } => {
+            if target != ScreenshotTarget::Primary {
+                return Ok(error(ErrorCode::BadRequest, Some("save_to_disk always captures the whole desktop".into())));
+            }
             system::take_screenshot()?;
-            Ok(json!({"type":"ok","action":"take_screenshot"}))
+            Ok(json!({"type":"ok","action":"take_screenshot","save_to_disk":true}))
         }
-        WsCommand::OpenCalculator => {
+        ControlCommand::TakeScreenshot { save_to_disk: false, target } => {
+            let area = match target {
+                ScreenshotTarget::Primary => screen::primary_area()?,
+                ScreenshotTarget::Monitor(index) => match screen::list_monitors()?.get(index) {
+                    Some(monitor) => monitor.area(),
+                    None => {
+                        return Ok(error(ErrorCode::MonitorNotFound, Some(format!("no monitor {index}, see list_monitors"))));
+                    }
+                },
+                ScreenshotTarget::ActiveWindow => match screen::active_window_area()? {
+                    Some(area) => area,
+                    None => return Ok(error(ErrorCode::NotFound, Some("no active window to capture".into()))),
+                },
+            };
+            let frame = match screen::capture(area) {
+                Ok(frame) => frame,
+                // the lock screen or a UAC prompt took over between the session check and the capture
+                Err(_) if !system::has_interactive_session() => {
+                    return Ok(error(ErrorCode::NoInteractiveSession, Some("the secure desktop is active".into())));
+                }
+                Err(e) => return Ok(error(ErrorCode::CaptureFailed, Some(format!("{e:#}")))),
+            };
+            let max_dimension = ctx.config.read().unwrap().screenshot_max_dimension;
+            let (width, height, rgb) = screen::downscale_to_rgb(&frame, max_dimension);
+            let png = screen::encode_png(width, height, &rgb)?;
+            Ok(json!({
+                "type": "screenshot",
+                "format": "png",
+                "width": width,
+                "height": height,
+                "source_width": frame.width,
+                "source_height": frame.height,
+                "x": area.x,
+                "y": area.y,
+                "data": BASE64_STANDARD.encode(png),
+            }))
+        }
+        ControlCommand::ListMonitors => Ok(json!({"type":"monitors","monitors":screen::list_monitors()?})),
+        ControlCommand::TypeText { text } => {
+            let length = system::typed_chars(&text).len();
+            let (typed, result) = system::type_text(&text);
+            // a partial failure still says how far it got
+            let mut v = match result {
+                Ok(()) => json!({"type":"ok","action":"type_text"}),
+                Err(e) => match e.downcast_ref::<InputError>() {
+                    Some(input) => input_error_reply(*input),
+                    None => error(ErrorCode::CommandFailed, Some(e.to_string())),
+                },
+            };
+            merge(&mut v, json!({"typed": typed, "length": length}));
+            Ok(v)
+        }
+        ControlCommand::SendKeyCode { vk, up } => {
+            // VK codes are 0x01..=0xFE; 0 and 0xFF are reserved
+            if !(0x01..=0xFE).contains(&vk) {
+                return Ok(error(ErrorCode::BadRequest, Some(format!("vk out of range: {vk}"))));
+            }
+            system::send_key_code(vk, up)?;
+            Ok(json!({"type":"ok","action":"send_key_code","vk":vk,"up":up}))
+        }
+        ControlCommand::OpenCalculator => {
             system::open_calculator()?;
             Ok(json!({"type":"ok","action":"open_calculator"}))
         }
+        ControlCommand::ListLaunchers => {
+            let mut store = ctx.launchers.lock().unwrap();
+            store.refresh();
+            let launchers = store.to_json();
+            Ok(json!({"type":"launchers","launchers":launchers}))
+        }
+        ControlCommand::Launch { id } => {
+            let Some(launcher) = launcher else {
+                return Ok(launcher_not_found(&id));
+            };
+            system::launch_program(&launcher.resolved_program(), &launcher.args, launcher.working_dir.as_deref())?;
+            Ok(json!({"type":"ok","action":"launch","id":id}))
+        }
+        ControlCommand::OpenPath { path } => {
+            // read per request so edited roots apply to open connections too
+            let roots: Vec<PathBuf> = ctx.config.read().unwrap().open_path_roots.iter().map(PathBuf::from).collect();
+            let Some(target) = system::resolve_allowed_path(&path, &roots) else {
+                return Ok(error(ErrorCode::Forbidden, Some("path is outside the allowed folders".into())));
+            };
+            system::open_in_explorer(&target)?;
+            Ok(json!({"type":"ok","action":"open_path"}))
+        }
+        ControlCommand::OpenUrl { url } => {
+            let target = match system::parse_web_url(&url) {
+                Ok(target) => target,
+                Err(reason) => return Ok(error(ErrorCode::BadRequest, Some(reason.into()))),
+            };
+            system::open_url(&url)?;
+            Ok(json!({"type":"ok","action":"open_url","scheme":target.scheme,"host":target.host}))
+        }
+        ControlCommand::Power { action, delay_secs, force } => {
+            if delay_secs > MAX_POWER_DELAY_SECS {
+                return Ok(error(ErrorCode::BadRequest, Some(format!("delay_secs is over {MAX_POWER_DELAY_SECS}"))));
+            }
+            if delay_secs == 0 {
+                system::power_action(action, force)?;
+            } else {
+                ctx.power.schedule(action, force, Duration::from_secs(delay_secs));
+            }
+            Ok(json!({
+                "type": "ok",
+                "action": "power",
+                "power_action": action.as_str(),
+                "force": force,
+                "pending_power": ctx.power.to_json(),
+            }))
+        }
+        ControlCommand::CancelPower => {
+            let cancelled = ctx.power.cancel();
+            Ok(json!({"type":"ok","action":"cancel_power","cancelled":cancelled.map(PowerAction::as_str)}))
+        }
+        ControlCommand::SetFocusedAppVolume { level } => {
+            let level = level.clamp(0.0, 1.0);
+            let app = window::foreground_app()?;
+            if !audio.set_process_volume(app.pid, level)? {
+                let mut v = error(ErrorCode::NotFound, Some(format!("{} has no audio session", app.name)));
+                v["app"] = json!(app.name);
+                return Ok(v);
+            }
+            Ok(json!({"type":"ok","action":"set_focused_app_volume","app":app.name,"level":level}))
+        }
+        ControlCommand::GetAppVolumes => {
+            let sessions = audio.list_app_sessions()?;
+            Ok(json!({"type":"app_volumes","sessions":sessions}))
+        }
+        ControlCommand::ListAudioSessions => {
+            let sessions = audio.list_audio_sessions()?;
+            Ok(json!({"type":"audio_sessions","sessions":sessions}))
+        }
+        ControlCommand::SetSessionVolume { pid, level } => {
+            let level = level.clamp(0.0, 1.0);
+            if !audio.set_process_volume(pid, level)? {
+                let mut v = error(ErrorCode::NotFound, Some(format!("pid {pid} has no audio session")));
+                v["pid"] = json!(pid);
+                return Ok(v);
+            }
+            Ok(json!({"type":"ok","action":"set_session_volume","pid":pid,"level":level}))
+        }
+        ControlCommand::SetMediaSessionVolume { level } => {
+            let level = level.clamp(0.0, 1.0);
+            let Some(app) = media::current_media_app()? else {
+                return Ok(error(ErrorCode::NotFound, Some("no active media session".into())));
+            };
+            if !audio.set_app_volume(&app, level)? {
+                let mut v = error(ErrorCode::NotFound, Some(format!("{app} has no audio session")));
+                v["app"] = json!(app);
+                return Ok(v);
+            }
+            Ok(json!({"type":"ok","action":"set_media_session_volume","app":app,"level":level}))
+        }
+        ControlCommand::MuteApp { session_id, process_name, mute } => {
+            let affected = match (session_id.as_deref(), process_name.as_deref()) {
+                (Some(id), None) => audio.mute_session(id, mute)?,
+                (None, Some(name)) => audio.mute_process_sessions(name, mute)?,
+                _ => {
+                    return Ok(error(
+                        ErrorCode::BadRequest,
+                        Some("give exactly one of session_id or process_name".into()),
+                    ))
+                }
+            };
+            if affected.is_empty() {
+                let target = session_id.or(process_name).unwrap_or_default();
+                return Ok(error(ErrorCode::NoMatchingSession, Some(format!("no audio session matches {target}"))));
+            }
+            Ok(json!({"type":"ok","action":"mute_app","mute":mute,"sessions":affected}))
+        }
+        ControlCommand::ApplyAudioPreset { entries, master } => {
+            let targeted = |e: &PresetEntry| e.session_id.is_some() != e.process_name.is_some();
+            // a master that can't be set doesn't stop the session entries
+            let master_error = match master {
+                Some(level) => audio.set_volume(level.clamp(0.0, 1.0)).err().map(|e| e.to_string()),
+                None => None,
+            };
+            let valid: Vec<PresetEntry> = entries.iter().filter(|e| targeted(e)).cloned().collect();
+            // master may already be set, so an enumerator failure fails the entries, not the reply
+            let mut outcomes = match audio.apply_session_preset(&valid) {
+                Ok(outcomes) => outcomes,
+                Err(e) => vec![PresetOutcome::Failed { message: e.to_string() }; valid.len()],
+            }
+            .into_iter();
+            let results: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    let mut v = match targeted(entry).then(|| outcomes.next()).flatten() {
+                        Some(outcome) => json!(outcome),
+                        None => json!({
+                            "result": "failed",
+                            "code": ErrorCode::BadRequest,
+                            "message": "each entry needs exactly one of session_id or process_name",
+                        }),
+                    };
+                    v["session_id"] = json!(entry.session_id);
+                    v["process_name"] = json!(entry.process_name);
+                    v
+                })
+                .collect();
+            let (vol, muted) = audio.get_volume_and_mute()?;
+            Ok(json!({
+                "type": "ok",
+                "action": "apply_audio_preset",
+                "results": results,
+                "master_error": master_error,
+                "volume": vol,
+                "muted": muted,
+            }))
+        }
+        ControlCommand::GetSystemSounds => {
+            let state = audio.get_system_sounds()?;
+            Ok(json!({
+                "type": "system_sounds",
+                "available": state.is_some(),
+                "volume": state.map(|s| s.0),
+                "muted": state.map(|s| s.1),
+            }))
+        }
+        ControlCommand::SetSystemSounds { level, mute } => {
+            if level.is_none() && mute.is_none() {
+                return Ok(error(ErrorCode::BadRequest, Some("give level, mute or both".into())));
+            }
+            let level = level.map(|l| l.clamp(0.0, 1.0));
+            match audio.set_system_sounds(level, mute)? {
+                Some((volume, muted)) => Ok(json!({
+                    "type": "ok",
+                    "action": "set_system_sounds",
+                    "pending": false,
+                    "volume": volume,
+                    "muted": muted,
+                })),
+                // nothing has played a system sound yet; applied once the session shows up
+                None => Ok(json!({
+                    "type": "ok",
+                    "action": "set_system_sounds",
+                    "pending": true,
+                    "volume": level,
+                    "muted": mute,
+                })),
+            }
+        }
+        ControlCommand::UndoAudio => {
+            let Some((vol, muted)) = ctx.history.lock().unwrap().pop() else {
+                return Ok(json!({"type":"ok","action":"undo","changed":false}));
+            };
+            audio.set_volume(vol)?;
+            audio.set_mute(muted)?;
+            if !ctx.full_status {
+                return Ok(minimal_ok("undo"));
+            }
+            let (vol, muted) = audio.get_volume_and_mute()?;
+            Ok(json!({"type":"ok","action":"undo","changed":true,"volume":vol,"muted":muted}))
+        }
+        ControlCommand::SavePreset { name, level } => {
+            let mut store = presets.lock().unwrap();
+            if let Err(e) = store.upsert(name, level) {
+                return Ok(error(ErrorCode::BadRequest, Some(e.to_string())));
+            }
+            Ok(json!({"type":"ok","action":"save_preset","presets":store.to_json()}))
+        }
+        ControlCommand::ListPresets => {
+            let store = presets.lock().unwrap();
+            Ok(json!({"type":"presets","presets":store.to_json()}))
+        }
+        ControlCommand::ApplyPreset { name } => {
+            let Some(level) = presets.lock().unwrap().get(&name) else {
+                return Ok(error(ErrorCode::NotFound, Some(format!("no preset named {name}"))));
+            };
+            audio.set_volume(level)?;
+            if !ctx.full_status {
+                return Ok(minimal_ok("apply_preset"));
+            }
+            let (vol, muted) = audio.get_volume_and_mute()?;
+            let mic_muted = audio.get_mic_mute(None)?;
+            Ok(json!({"type":"status","preset":name,"volume":vol,"muted":muted,"mic_muted":mic_muted}))
+        }
+        ControlCommand::DeletePreset { name } => {
+            let mut store = presets.lock().unwrap();
+            if !store.remove(&name)? {
+                return Ok(error(ErrorCode::NotFound, Some(format!("no preset named {name}"))));
+            }
+            Ok(json!({"type":"ok","action":"delete_preset","presets":store.to_json()}))
+        }
+        ControlCommand::CreateGroup { name, members } => {
+            let mut store = ctx.groups.lock().unwrap();
+            if let Err(e) = store.upsert(name, members) {
+                return Ok(error(ErrorCode::BadRequest, Some(e.to_string())));
+            }
+            Ok(json!({"type":"ok","action":"create_group","groups":store.to_json()}))
+        }
+        ControlCommand::ListGroups => {
+            let store = ctx.groups.lock().unwrap();
+            Ok(json!({"type":"groups","groups":store.to_json()}))
+        }
+        ControlCommand::DeleteGroup { name } => {
+            let mut store = ctx.groups.lock().unwrap();
+            if !store.remove(&name)? {
+                return Ok(error(ErrorCode::NotFound, Some(format!("no group named {name}"))));
+            }
+            Ok(json!({"type":"ok","action":"delete_group","groups":store.to_json()}))
+        }
+        ControlCommand::SetGroupVolume { group, level } => {
+            let Some(members) = ctx.groups.lock().unwrap().get(&group).map(<[String]>::to_vec) else {
+                return Ok(error(ErrorCode::NotFound, Some(format!("no group named {group}"))));
+            };
+            let level = level.clamp(0.0, 1.0);
+            // unplugged members are skipped; only a real failure on a present device errors
+            let (mut adjusted, mut skipped) = (Vec::new(), Vec::new());
+            for id in members {
+                if audio.set_endpoint_volume(&id, level)? {
+                    adjusted.push(id);
+                } else {
+                    skipped.push(id);
+                }
+            }
+            Ok(json!({
+                "type": "ok",
+                "action": "set_group_volume",
+                "group": group,
+                "level": level,
+                "adjusted": adjusted,
+                "skipped": skipped,
+            }))
+        }
+        ControlCommand::Ping { nonce } => {
+            // no side effects; ws.rs already marked the session as seen
+            Ok(json!({
+                "type": "pong",
+                "nonce": nonce,
+                "server_time": now_unix(),
+                "server_uptime_secs": ctx.metrics.uptime_secs(),
+                "active_clients": ctx.active_clients.load(Ordering::SeqCst),
+            }))
+        }
+    }
+}
+
+// Looks `id` up in launchers.json as it is on disk now
+fn find_launcher(ctx: &CommandCtx, id: &str) -> Option<Launcher> {
+    let mut store = ctx.launchers.lock().unwrap();
+    store.refresh();
+    store.get(id)
+}
+
+fn launcher_not_found(id: &str) -> serde_json::Value {
+    error(ErrorCode::NotFound, Some(format!("no launcher with id {id}")))
+}
+
+// Combined VolumeUp/VolumeDown delta, clamped to a full sweep
+fn volume_step(ctx: &CommandCtx, delta: Option<f32>, steps: Option<u32>) -> f32 {
+    let delta = delta.unwrap_or_else(|| ctx.config.read().unwrap().volume_step);
+    (delta * steps.unwrap_or(1) as f32).clamp(0.0, 1.0)
+}
+
+// Reply for connections that turned full status off (SetVerbosity): no re-read of the device
+fn minimal_ok(action: &str) -> serde_json::Value {
+    json!({"type":"ok","action":action})
+}
+
+fn db_to_percent(db: f32, min_db: f32, max_db: f32) -> f32 {
+    if max_db <= min_db {
+        return 100.0;
+    }
+    ((db - min_db) / (max_db - min_db) * 100.0).clamp(0.0, 100.0)
+}
+
+// Inverts the mic's mute state and returns what the device reports afterwards, which is what
+// the client should show even if the write didn't take
+pub fn toggle_mic_mute(audio: &dyn AudioBackend, device_id: Option<&str>) -> anyhow::Result<bool> {
+    let muted = audio.get_mic_mute(device_id)?;
+    audio.set_mic_mute(device_id, !muted)?;
+    audio.get_mic_mute(device_id)
+}
+
+// position_ms is null when the app skipped by its own amount (no seekable timeline)
+fn jump(ctx: &CommandCtx, action: &str, ms: Option<u64>, sign: i64) -> anyhow::Result<serde_json::Value> {
+    let ms = ms.unwrap_or_else(|| ctx.config.read().unwrap().seek_step_ms);
+    let delta = i64::try_from(ms).unwrap_or(i64::MAX) * sign;
+    match media::jump(delta)? {
+        ControlOutcome::Changed(position_ms) => Ok(json!({"type":"ok","action":action,"position_ms":position_ms})),
+        outcome => Ok(control_refused(outcome, action)),
+    }
+}
+
+// Error reply for a media control the current session didn't apply
+fn control_refused<T>(outcome: ControlOutcome<T>, what: &str) -> serde_json::Value {
+    match outcome {
+        ControlOutcome::NoSession => error(ErrorCode::NotFound, Some("nothing is playing".into())),
+        _ => error(ErrorCode::NotSupported, Some(format!("the current media app doesn't support {what}"))),
+    }
+}
+
+// Untargeted transport, delivered per the media_backend setting; the reply's "via" says which
+// path took it
+fn current_session_transport(
+    ctx: &CommandCtx,
+    action: TransportAction,
+    name: &str,
+    media_key: fn() -> anyhow::Result<()>,
+) -> serde_json::Value {
+    let backend = ctx.config.read().unwrap().media_backend;
+    let ok = |via: &str| json!({"type":"ok","action":name,"backend":backend,"via":via});
+    let smtc = || {
+        media::session_transport(SessionTarget::Current, action)
+            .inspect_err(|e| warn!("GSMTC {name} failed: {e:?}"))
+            .is_ok_and(|taken| taken == Some(true))
+    };
+    // SendInput reports success even when UIPI discards the events, so check up front
+    let key = || {
+        if window::foreground_blocks_input() {
+            return Err(InputError::BlockedByUipi);
+        }
+        media_key().map_err(|e| {
+            warn!("Media key for {name} failed: {e:?}");
+            e.downcast_ref::<InputError>().copied().unwrap_or(InputError::Unknown)
+        })
+    };
+
+    match backend {
+        MediaBackend::Smtc if smtc() => ok("smtc"),
+        MediaBackend::Smtc => error(ErrorCode::NotSupported, Some(format!("no media session took {name}"))),
+        MediaBackend::Auto if smtc() => ok("smtc"),
+        MediaBackend::Auto => match key() {
+            Ok(()) => ok("media_key"),
+            Err(e) => input_error_reply(e),
+        },
+        MediaBackend::Keys => match key() {
+            Ok(()) => ok("media_key"),
+            // GSMTC reaches the app directly, past UIPI
+            Err(_) if smtc() => ok("smtc"),
+            Err(e) => input_error_reply(e),
+        },
+    }
+}
 
-        // These should never hit handle_command (handled in ws.rs)
-        WsCommand::Pair { .. } | WsCommand::Auth { .. } => {
-            Ok(json!({"type":"error","reason":"invalid_command_context"}))
+// Global media key without a target, otherwise straight to that GSMTC session
+fn media_transport(
+    ctx: &CommandCtx,
+    session_id: Option<String>,
+    app_id: Option<String>,
+    action: TransportAction,
+    name: &str,
+    media_key: fn() -> anyhow::Result<()>,
+) -> anyhow::Result<serde_json::Value> {
+    let target = match (session_id.as_deref(), app_id.as_deref()) {
+        (None, None) => return Ok(current_session_transport(ctx, action, name, media_key)),
+        (Some(id), None) => SessionTarget::SessionId(id),
+        (None, Some(app)) => SessionTarget::AppId(app),
+        (Some(_), Some(_)) => {
+            return Ok(error(ErrorCode::BadRequest, Some("give session_id or app_id, not both".into())));
         }
+    };
+    match media::session_transport(target, action)? {
+        Some(true) => Ok(json!({"type":"ok","action":name,"session_id":session_id,"app_id":app_id})),
+        Some(false) => Ok(error(ErrorCode::NotSupported, Some(format!("the media app turned down {name}")))),
+        None => {
+            let wanted = session_id.or(app_id).unwrap_or_default();
+            Ok(error(ErrorCode::SessionNotFound, Some(format!("no media session {wanted}"))))
+        }
+    }
+}
+
+fn device_not_found(id: &str) -> serde_json::Value {
+    let mut v = error(ErrorCode::DeviceNotFound, Some(format!("no active audio device {id}")));
+    v["device_id"] = json!(id);
+    v
+}
+
+fn endpoint_mute(
+    ctx: &CommandCtx,
+    action: &str,
+    id: &str,
+    role: Option<OutputRole>,
+    mute: bool,
+) -> anyhow::Result<serde_json::Value> {
+    if role.is_some() {
+        return Ok(error(ErrorCode::BadRequest, Some("give role or device_id, not both".into())));
+    }
+    if !ctx.audio.set_endpoint_mute(id, mute)? {
+        return Ok(device_not_found(id));
+    }
+    endpoint_reply(ctx, action, id)
+}
+
+// Reply for a command aimed at one endpoint by id
+fn endpoint_reply(ctx: &CommandCtx, action: &str, id: &str) -> anyhow::Result<serde_json::Value> {
+    if !ctx.full_status {
+        return Ok(minimal_ok(action));
     }
+    let Some((vol, muted)) = ctx.audio.get_endpoint_volume_and_mute(id)? else {
+        return Ok(device_not_found(id));
+    };
+    Ok(json!({"type":"ok","action":action,"device_id":id,"volume":vol,"muted":muted}))
+}
+
+// Whether a role-taking command touches the console device the undo history tracks
+fn targets_console(role: Option<OutputRole>) -> bool {
+    matches!(role, None | Some(OutputRole::Console | OutputRole::All))
+}
+
+// Render roles a command acts on: omitted = console, "all" = console and communications
+fn render_roles(role: Option<OutputRole>) -> Vec<OutputRole> {
+    match role.unwrap_or(OutputRole::Console) {
+        OutputRole::All => vec![OutputRole::Console, OutputRole::Communications],
+        r => vec![r],
+    }
+}
+
+fn role_volume_and_mute(audio: &dyn AudioBackend, role: OutputRole) -> anyhow::Result<(f32, bool)> {
+    match role {
+        OutputRole::Console => audio.get_volume_and_mute(),
+        r => audio.get_role_volume_and_mute(r),
+    }
+}
+
+fn set_role_volume(audio: &dyn AudioBackend, role: OutputRole, level: f32) -> anyhow::Result<()> {
+    match role {
+        OutputRole::Console => audio.set_volume(level),
+        r => audio.set_role_volume(r, level),
+    }
+}
+
+fn set_role_mute(audio: &dyn AudioBackend, role: OutputRole, mute: bool) -> anyhow::Result<()> {
+    match role {
+        OutputRole::Console => audio.set_mute(mute),
+        r => audio.set_role_mute(r, mute),
+    }
+}
+
+// volume/muted of the requested role (console for omitted and "all"); "all" also gets "roles"
+fn role_state(audio: &dyn AudioBackend, role: Option<OutputRole>) -> anyhow::Result<serde_json::Value> {
+    let roles = render_roles(role);
+    let (vol, muted) = role_volume_and_mute(audio, roles[0])?;
+    let mut v = json!({"volume": vol, "muted": muted});
+    if let Some(r) = role {
+        v["role"] = json!(r);
+    }
+    if role == Some(OutputRole::All) {
+        let (comms_vol, comms_muted) = role_volume_and_mute(audio, OutputRole::Communications)?;
+        v["roles"] = json!({
+            "console": {"volume": vol, "muted": muted},
+            "communications": {"volume": comms_vol, "muted": comms_muted},
+        });
+    }
+    Ok(v)
+}
+
+fn merge(target: &mut serde_json::Value, fields: serde_json::Value) {
+    if let (Some(target), serde_json::Value::Object(fields)) = (target.as_object_mut(), fields) {
+        target.extend(fields);
+    }
+}
+
+// Steps each role from its starting level to `to` on its own thread, giving up as soon as
+// another volume change bumps the fade generation
+fn start_fade(ctx: &CommandCtx, from: Vec<(OutputRole, f32)>, to: f32, duration: Duration) {
+    let audio = ctx.audio.clone();
+    let generation = ctx.fade_generation.clone();
+    let own = generation.load(Ordering::SeqCst);
+    std::thread::spawn(move || {
+        for step in 1..=FADE_STEPS {
+            std::thread::sleep(duration / FADE_STEPS);
+            if generation.load(Ordering::SeqCst) != own {
+                return;
+            }
+            let t = step as f32 / FADE_STEPS as f32;
+            for &(role, start) in &from {
+                if let Err(e) = set_role_volume(audio.as_ref(), role, start + (to - start) * t) {
+                    warn!("volume fade stopped: {e}");
+                    return;
+                }
+            }
+        }
+    });
 }
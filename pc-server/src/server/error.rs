@@ -0,0 +1,67 @@
+// src/server/error.rs
+#![cfg(windows)]
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::system::InputError;
+
+// Stable codes sent to clients in the "code" field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NotAuthenticated,
+    Forbidden,
+    BadRequest,
+    TooManyBadMessages,
+    NotFound,
+    RateLimited,
+    ServerFull,
+    CommandFailed,
+    Timeout,
+    NoInteractiveSession,
+    InvalidToken,
+    InvalidCode,
+    NoRemoteIp,
+    InputRateLimited,
+    Disabled,
+    BlockedByUipi,
+    NoMatchingSession,
+    DeviceUnavailable,
+    PanicMuteActive,
+    DeviceNotFound,
+    // the device or media session can't do what was asked (e.g. balance on mono, seek)
+    NotSupported,
+    SessionNotFound,
+    // reading the screen failed for a reason other than the secure desktop
+    CaptureFailed,
+    // take_screenshot asked for a monitor index list_monitors doesn't have
+    MonitorNotFound,
+}
+
+// {"type": ty, "code": code} plus an optional "message"
+pub fn error_reply(ty: &str, code: ErrorCode, message: Option<String>) -> Value {
+    let mut v = json!({"type": ty, "code": code});
+    if let Some(m) = message {
+        v["message"] = json!(m);
+    }
+    v
+}
+
+// Plain "type":"error" reply
+pub fn error(code: ErrorCode, message: Option<String>) -> Value {
+    error_reply("error", code, message)
+}
+
+// Injected input that didn't reach the desktop; all share "reason":"input_blocked"
+pub fn input_error_reply(err: InputError) -> Value {
+    let code = match err {
+        InputError::BlockedByUipi => ErrorCode::BlockedByUipi,
+        InputError::SecureDesktopActive => ErrorCode::NoInteractiveSession,
+        InputError::Unknown => ErrorCode::CommandFailed,
+    };
+    let mut v = error(code, Some(err.to_string()));
+    v["reason"] = json!("input_blocked");
+    v["cause"] = json!(err.kind());
+    v
+}
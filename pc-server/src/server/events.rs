@@ -0,0 +1,30 @@
+// src/server/events.rs
+#![cfg(windows)]
+
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+// Topics a client can pass to `subscribe`. "heartbeat" is not broadcast: each connection
+// pushes it itself on its keepalive tick (see hello's keepalive_secs).
+pub const TOPICS: &[&str] = &["sessions", "heartbeat", "volume", "devices", "media"];
+
+// Subscribed on every connection until the client unsubscribes
+pub const DEFAULT_TOPICS: &[&str] = &["volume", "devices", "media"];
+
+// Server-initiated push; only forwarded to clients subscribed to `topic`
+#[derive(Debug, Clone)]
+pub struct ServerEvent {
+    pub topic: &'static str,
+    pub payload: Value,
+}
+
+pub type EventSender = broadcast::Sender<ServerEvent>;
+
+pub fn event_channel() -> EventSender {
+    broadcast::channel(64).0
+}
+
+// Fire-and-forget: having no subscribers is not an error
+pub fn publish(events: &EventSender, topic: &'static str, payload: Value) {
+    let _ = events.send(ServerEvent { topic, payload });
+}
@@ -0,0 +1,72 @@
+// src/server/groups.rs
+#![cfg(windows)]
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::data_file;
+
+// Named sets of output endpoint ids moved together by SetGroupVolume, persisted as {"name": [ids]}
+pub struct GroupStore {
+    path: PathBuf,
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+pub fn groups_path() -> PathBuf {
+    data_file("groups.json")
+}
+
+impl GroupStore {
+    pub fn load(path: PathBuf) -> Self {
+        let groups = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_default(),
+            Err(_) => BTreeMap::new(),
+        };
+        Self { path, groups }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.groups.get(name).map(Vec::as_slice)
+    }
+
+    // [{"name":..,"members":[..]}] sorted by name
+    pub fn to_json(&self) -> Value {
+        let list: Vec<_> = self
+            .groups
+            .iter()
+            .map(|(name, members)| json!({"name": name, "members": members}))
+            .collect();
+        json!(list)
+    }
+
+    pub fn upsert(&mut self, name: String, mut members: Vec<String>) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("group name must not be empty");
+        }
+        let mut seen = HashSet::new();
+        members.retain(|m| seen.insert(m.clone()));
+        if members.is_empty() {
+            bail!("a group needs at least one member");
+        }
+        self.groups.insert(name, members);
+        self.save()
+    }
+
+    // false if no group had that name
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        if self.groups.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<()> {
+        let s = serde_json::to_string_pretty(&self.groups)?;
+        fs::write(&self.path, s)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+// src/server/identity.rs
+#![cfg(windows)]
+
+use anyhow::Result;
+use ed25519_dalek::{Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::data_file;
+
+// Per-install ed25519 key. The secret never leaves the PC; clients pin the public key on
+// first auth (TOFU) and check auth challenges and discovery replies against it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServerIdentity {
+    // ed25519 secret (hex). Empty in identity files written before it existed; filled in on load.
+    #[serde(default)]
+    pub signing_key: String,
+}
+
+// Domain separation, so neither signature can stand in for the other
+const CHALLENGE_CONTEXT: &str = "FOSSDECK_AUTH_V1:";
+const DISCOVERY_CONTEXT: &str = "FOSSDECK_DISCOVERY_V1:";
+
+impl ServerIdentity {
+    fn keypair(&self) -> Option<SigningKey> {
+        let bytes: [u8; 32] = hex::decode(&self.signing_key).ok()?.try_into().ok()?;
+        Some(SigningKey::from_bytes(&bytes))
+    }
+
+    // ed25519 public key, hex
+    pub fn identity_key(&self) -> Option<String> {
+        self.keypair().map(|k| hex::encode(k.verifying_key().as_bytes()))
+    }
+
+    // What clients pin (TOFU): sha256 of the raw public key bytes
+    pub fn identity_fingerprint(&self) -> Option<String> {
+        self.keypair().map(|k| {
+            use sha2::Digest;
+            hex::encode(Sha256::digest(k.verifying_key().as_bytes()))
+        })
+    }
+
+    // Signature over a client-chosen challenge, hex
+    pub fn sign_challenge(&self, challenge: &str) -> Option<String> {
+        let key = self.keypair()?;
+        let sig = key.sign(format!("{CHALLENGE_CONTEXT}{challenge}").as_bytes());
+        Some(hex::encode(sig.to_bytes()))
+    }
+
+    // Signature over a discovery reply's signed fields, hex
+    pub fn sign_discovery(&self, payload: &str) -> Option<String> {
+        let key = self.keypair()?;
+        let sig = key.sign(format!("{DISCOVERY_CONTEXT}{payload}").as_bytes());
+        Some(hex::encode(sig.to_bytes()))
+    }
+}
+
+pub fn identity_path() -> PathBuf {
+    data_file("identity.json")
+}
+
+pub fn load_or_create_identity(path: &Path) -> ServerIdentity {
+    let loaded = fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<ServerIdentity>(&s).ok());
+    if let Some(id) = loaded.as_ref().filter(|id| id.keypair().is_some()) {
+        return id.clone();
+    }
+
+    let id = ServerIdentity { signing_key: hex::encode(SigningKey::generate(&mut rand::rngs::OsRng).to_bytes()) };
+    let _ = save_identity(path, &id);
+    id
+}
+
+fn save_identity(path: &Path, id: &ServerIdentity) -> Result<()> {
+    let s = serde_json::to_string_pretty(id)?;
+    fs::write(path, s)?;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+// src/server/launchers.rs
+#![cfg(windows)]
+
+use log::warn;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::data_file;
+
+// One button on the phone that starts a program on the PC
+#[derive(Debug, Clone, Deserialize)]
+pub struct Launcher {
+    pub id: String,
+    pub label: String,
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // also what a relative `program` resolves against
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+impl Launcher {
+    // A relative program is looked up in working_dir first, then left to the PATH search
+    pub fn resolved_program(&self) -> PathBuf {
+        let program = Path::new(&self.program);
+        match &self.working_dir {
+            Some(dir) if program.is_relative() => {
+                let joined = Path::new(dir).join(program);
+                if joined.exists() { joined } else { program.to_path_buf() }
+            }
+            _ => program.to_path_buf(),
+        }
+    }
+}
+
+// Launchers the owner listed in launchers.json as [{"id","label","program",...}]; the file is
+// re-read whenever its modification time changes, so edits apply without a restart
+pub struct LauncherStore {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    launchers: Vec<Launcher>,
+}
+
+pub fn launchers_path() -> PathBuf {
+    data_file("launchers.json")
+}
+
+impl LauncherStore {
+    pub fn load(path: PathBuf) -> Self {
+        let mut store = Self { path, modified: None, launchers: Vec::new() };
+        store.refresh();
+        store
+    }
+
+    // Re-reads the file if it changed since the last look
+    pub fn refresh(&mut self) {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        let Ok(s) = fs::read_to_string(&self.path) else {
+            self.launchers.clear();
+            return;
+        };
+        // a half-written file keeps the previous list
+        let entries: Vec<Value> = match serde_json::from_str(&s) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Ignoring {}: {e}", self.path.display());
+                return;
+            }
+        };
+        let mut launchers: Vec<Launcher> = Vec::new();
+        for entry in entries {
+            match serde_json::from_value::<Launcher>(entry) {
+                Ok(l) if launchers.iter().any(|other| other.id == l.id) => warn!("Duplicate launcher id {}", l.id),
+                Ok(l) => launchers.push(l),
+                Err(e) => warn!("Skipping launcher entry: {e}"),
+            }
+        }
+        self.launchers = launchers;
+    }
+
+    pub fn get(&self, id: &str) -> Option<Launcher> {
+        self.launchers.iter().find(|l| l.id == id).cloned()
+    }
+
+    // [{"id","label","icon"}] in file order; program and args stay on the PC
+    pub fn to_json(&self) -> Value {
+        let list: Vec<_> = self
+            .launchers
+            .iter()
+            .map(|l| json!({"id": l.id, "label": l.label, "icon": l.icon}))
+            .collect();
+        json!(list)
+    }
+}
@@ -0,0 +1,95 @@
+// src/server/metrics.rs
+#![cfg(windows)]
+
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::server::commands::capabilities;
+
+#[derive(Default)]
+struct CommandCounters {
+    calls: AtomicU64,
+    errors: AtomicU64,
+}
+
+// Process-lifetime counters reported by `health?verbose=1`
+pub struct Metrics {
+    // fixed key set (every device command), so recording never needs a lock
+    commands: HashMap<&'static str, CommandCounters>,
+    connections_total: AtomicU64,
+    connections_rejected: AtomicU64,
+    pairing_attempts: AtomicU64,
+    started: Instant,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            commands: capabilities(true, &[])
+                .into_iter()
+                .chain(["ping"])
+                .map(|c| (c, CommandCounters::default()))
+                .collect(),
+            connections_total: AtomicU64::new(0),
+            connections_rejected: AtomicU64::new(0),
+            pairing_attempts: AtomicU64::new(0),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record_command(&self, name: &str, failed: bool) {
+        if let Some(c) = self.commands.get(name) {
+            c.calls.fetch_add(1, Ordering::Relaxed);
+            if failed {
+                c.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn connection_opened(&self) {
+        self.connections_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // turned away because every client slot was taken
+    pub fn connection_rejected(&self) {
+        self.connections_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn pairing_attempt(&self) {
+        self.pairing_attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    // Only commands that were used at least once are listed
+    pub fn to_json(&self) -> Value {
+        let mut commands = Map::new();
+        let mut errors = 0;
+        for (name, c) in &self.commands {
+            let calls = c.calls.load(Ordering::Relaxed);
+            if calls == 0 {
+                continue;
+            }
+            let failed = c.errors.load(Ordering::Relaxed);
+            errors += failed;
+            commands.insert(name.to_string(), json!({"calls": calls, "errors": failed}));
+        }
+
+        json!({
+            "commands": commands,
+            "command_errors": errors,
+            "connections_total": self.connections_total.load(Ordering::Relaxed),
+            "connections_rejected": self.connections_rejected.load(Ordering::Relaxed),
+            "pairing_attempts": self.pairing_attempts.load(Ordering::Relaxed),
+            "uptime_secs": self.uptime_secs(),
+            // threads still holding a COM apartment reference; should track the live thread count
+            "com_threads": crate::audio::com_thread_count(),
+        })
+    }
+}
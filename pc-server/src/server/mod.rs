@@ -1,11 +1,23 @@
 // src/server/mod.rs
 #![cfg(windows)]
 
+pub mod audit;
 pub mod auth_store;
 pub mod commands;
+pub mod error;
+pub mod events;
+pub mod groups;
+pub mod identity;
+pub mod launchers;
+pub mod metrics;
 pub mod pairing;
+pub mod power;
+pub mod presets;
 pub mod rate_limit;
 pub mod ws;
 
-pub use pairing::{generate_pairing_code, PairingState};
+#[cfg(test)]
+mod tests;
+
+pub use pairing::{generate_pairing_code, is_valid_static_code, CodeCharset, PairingState, CODE_LENGTHS};
 pub use ws::run_ws_server;
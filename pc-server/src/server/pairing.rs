@@ -4,54 +4,133 @@
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
 
-use crate::server::auth_store::{auth_store_path, load_store, now_unix, save_store, AuthorizedStore};
+use crate::server::audit::AuditLog;
+use crate::server::auth_store::{
+    auth_store_path, default_scopes, load_store, now_unix, save_store, AuthorizedStore,
+};
 use crate::server::rate_limit::RateLimitEntry;
 
 const PAIRING_TTL: Duration = Duration::from_secs(300);
 const PAIRING_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_RECONNECT_GRACE: Duration = Duration::from_secs(20);
+
+// A valid code from an unknown device, waiting for the PC owner to approve it in the GUI
+#[derive(Debug, Clone)]
+pub struct PendingApproval {
+    pub id: u64,
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub ip: IpAddr,
+}
 
 pub struct PairingState {
     pub code: String,
     pub created_at: Instant,
 
-    // Active session (runtime)
+    // Admin-set code that never rotates (kiosk setups)
+    static_code: Option<String>,
+    code_format: CodeFormat,
+
+    // Active session (runtime); identity is the device id alone, since the token proves it
     pub active_device_id: Option<String>,
+    // last address of the active device, for display/logging only (NAT/DHCP can change it)
     pub active_client_ip: Option<IpAddr>,
     pub last_seen: Option<Instant>,
+    // set when the active device's socket drops without a close frame; the session is
+    // kept for `reconnect_grace` so a flaky Wi-Fi reconnect resumes it
+    disconnected_at: Option<Instant>,
+    reconnect_grace: Duration,
 
     // Persistent allowlist
     store_path: PathBuf,
     store: AuthorizedStore,
 
+    // Security events, written next to the allowlist
+    audit: AuditLog,
+
     // Rate-limit map (in-memory)
     rate_limit: HashMap<IpAddr, RateLimitEntry>,
+
+    // First pairings wait for an Approve/Deny from the GUI when set
+    require_approval: bool,
+    approvals: Vec<(PendingApproval, oneshot::Sender<bool>)>,
+    next_approval_id: u64,
 }
 
 impl PairingState {
     pub fn new(code: String) -> Self {
-        let store_path = auth_store_path();
+        Self::with_store_path(code, auth_store_path())
+    }
+
+    // Allowlist kept at `store_path` instead of %APPDATA%
+    pub fn with_store_path(code: String, store_path: PathBuf) -> Self {
         let store = load_store(&store_path);
+        let audit = AuditLog::new(store_path.with_file_name("audit.jsonl"));
 
         Self {
             code,
             created_at: Instant::now(),
+            static_code: None,
+            code_format: CodeFormat::default(),
             active_device_id: None,
             active_client_ip: None,
             last_seen: None,
+            disconnected_at: None,
+            reconnect_grace: DEFAULT_RECONNECT_GRACE,
             store_path,
             store,
+            audit,
             rate_limit: HashMap::new(),
+            require_approval: false,
+            approvals: Vec::new(),
+            next_approval_id: 0,
         }
     }
 
     pub fn is_expired(&self) -> bool {
+        if self.static_code.is_some() {
+            return false;
+        }
         self.created_at.elapsed() > PAIRING_TTL
     }
 
+    // Time left on the current code; None for a static code, which never expires
+    pub fn ttl_remaining(&self) -> Option<Duration> {
+        if self.static_code.is_some() {
+            return None;
+        }
+        Some(PAIRING_TTL.saturating_sub(self.created_at.elapsed()))
+    }
+
+    // Whether a Pair attempt could succeed now: an expired code is only replaced while no device is active
+    pub fn pairing_enabled(&self) -> bool {
+        !self.is_expired() || self.active_device_id.is_none()
+    }
+
+    pub fn rotate_code(&mut self) {
+        self.code = self.static_code.clone().unwrap_or_else(|| generate_pairing_code(self.code_format));
+        self.created_at = Instant::now();
+    }
+
+    // Takes effect immediately: the current rotating code is replaced
+    pub fn set_code_format(&mut self, format: CodeFormat) {
+        self.code_format = format;
+        self.rotate_code();
+    }
+
+    // Caller is expected to validate with `is_valid_static_code` first
+    pub fn set_static_code(&mut self, code: Option<String>) {
+        self.static_code = code;
+        self.rotate_code();
+    }
+
     pub fn mark_seen(&mut self) {
         self.last_seen = Some(Instant::now());
+        self.disconnected_at = None;
 
         // persist last_seen for active device
         if let Some(id) = self.active_device_id.clone() {
@@ -62,17 +141,49 @@ impl PairingState {
         }
     }
 
+    // Liveness from `device_id`'s own connection (heartbeat pushes): refreshes the session clock only
+    // if that device holds the session, and its last_seen only in memory; the next save writes it
+    pub fn touch_device(&mut self, device_id: &str) {
+        if self.active_device_id.as_deref() == Some(device_id) {
+            self.last_seen = Some(Instant::now());
+        }
+        if let Some(dev) = self.store.devices.get_mut(device_id) {
+            dev.last_seen = now_unix();
+        }
+    }
+
+    pub fn set_reconnect_grace(&mut self, grace: Duration) {
+        self.reconnect_grace = grace;
+    }
+
+    pub fn mark_disconnected(&mut self, device_id: &str) {
+        if self.active_device_id.as_deref() == Some(device_id) {
+            self.disconnected_at = Some(Instant::now());
+        }
+    }
+
+    // A disconnected session lives out its grace window; a connected one the idle timeout
     pub fn is_idle_too_long(&self) -> bool {
-        match (&self.active_device_id, self.last_seen) {
-            (Some(_), Some(last)) => last.elapsed() > PAIRING_IDLE_TIMEOUT,
-            _ => false,
+        if self.active_device_id.is_none() {
+            return false;
+        }
+        match (self.disconnected_at, self.last_seen) {
+            (Some(gone), _) => gone.elapsed() > self.reconnect_grace,
+            (None, Some(last)) => last.elapsed() > PAIRING_IDLE_TIMEOUT,
+            (None, None) => false,
         }
     }
 
+    // How often the watchdog should poll is_idle_too_long: a quarter of the timeout, 1-5s
+    pub fn watchdog_interval() -> Duration {
+        (PAIRING_IDLE_TIMEOUT / 4).clamp(Duration::from_secs(1), Duration::from_secs(5))
+    }
+
     pub fn clear_active(&mut self) {
         self.active_device_id = None;
         self.active_client_ip = None;
         self.last_seen = None;
+        self.disconnected_at = None;
     }
 
     pub fn authorized_count(&self) -> usize {
@@ -86,7 +197,16 @@ impl PairingState {
         v
     }
 
+    pub fn authorized_device(&self, device_id: &str) -> Option<&crate::server::auth_store::AuthorizedDevice> {
+        self.store.devices.get(device_id)
+    }
+
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
     pub fn revoke_device(&mut self, device_id: &str) {
+        self.audit.record("device_revoked", None, Some(device_id));
         self.store.devices.remove(device_id);
         let _ = save_store(&self.store_path, &self.store);
 
@@ -108,6 +228,13 @@ impl PairingState {
 
     pub fn upsert_authorized(&mut self, device_id: String, token_hash: String, device_name: Option<String>) {
         let now = now_unix();
+        // re-pairing keeps whatever scopes the admin already granted
+        let scopes = self
+            .store
+            .devices
+            .get(&device_id)
+            .map(|d| d.scopes.clone())
+            .unwrap_or_else(default_scopes);
         self.store.devices.insert(
             device_id,
             crate::server::auth_store::AuthorizedDevice {
@@ -115,11 +242,36 @@ impl PairingState {
                 token_hash,
                 added_at: now,
                 last_seen: now,
+                scopes,
             },
         );
         let _ = save_store(&self.store_path, &self.store);
     }
 
+    // None if the device isn't paired; the name must already be normalized
+    pub fn rename_device(&mut self, device_id: &str, name: String) -> Option<&crate::server::auth_store::AuthorizedDevice> {
+        let dev = self.store.devices.get_mut(device_id)?;
+        dev.name = Some(name);
+        let _ = save_store(&self.store_path, &self.store);
+        self.audit.record("device_renamed", None, Some(device_id));
+        self.store.devices.get(device_id)
+    }
+
+    pub fn has_scope(&self, device_id: &str, scope: &str) -> bool {
+        self.store
+            .devices
+            .get(device_id)
+            .map(|d| d.scopes.iter().any(|s| s == scope))
+            .unwrap_or(false)
+    }
+
+    pub fn set_scopes(&mut self, device_id: &str, scopes: Vec<String>) {
+        if let Some(dev) = self.store.devices.get_mut(device_id) {
+            dev.scopes = scopes;
+            let _ = save_store(&self.store_path, &self.store);
+        }
+    }
+
     pub fn rl_is_locked(&mut self, ip: IpAddr) -> Option<u64> {
         let entry = self.rate_limit.entry(ip).or_insert_with(RateLimitEntry::new);
         if entry.is_locked() {
@@ -129,39 +281,109 @@ impl PairingState {
         }
     }
 
+    pub fn rl_attempts_remaining(&mut self, ip: IpAddr) -> u32 {
+        let entry = self.rate_limit.entry(ip).or_insert_with(RateLimitEntry::new);
+        entry.attempts_remaining()
+    }
+
     pub fn rl_register_success(&mut self, ip: IpAddr) {
         let entry = self.rate_limit.entry(ip).or_insert_with(RateLimitEntry::new);
         entry.register_success();
     }
 
+    pub fn set_require_approval(&mut self, on: bool) {
+        self.require_approval = on;
+    }
+
+    // Known devices re-pairing (e.g. after reinstalling the app) skip the prompt
+    pub fn needs_approval(&self, device_id: &str) -> bool {
+        self.require_approval && !self.store.devices.contains_key(device_id)
+    }
+
+    pub fn request_approval(
+        &mut self,
+        device_id: String,
+        device_name: Option<String>,
+        ip: IpAddr,
+    ) -> (u64, oneshot::Receiver<bool>) {
+        self.next_approval_id += 1;
+        let id = self.next_approval_id;
+        let (tx, rx) = oneshot::channel();
+        self.audit.record("pair_pending", Some(ip), Some(&device_id));
+        self.approvals.push((PendingApproval { id, device_id, device_name, ip }, tx));
+        (id, rx)
+    }
+
+    pub fn pending_approvals(&self) -> Vec<PendingApproval> {
+        self.approvals.iter().map(|(p, _)| p.clone()).collect()
+    }
+
+    pub fn resolve_approval(&mut self, id: u64, approved: bool) {
+        let Some(pos) = self.approvals.iter().position(|(p, _)| p.id == id) else {
+            return;
+        };
+        let (p, tx) = self.approvals.remove(pos);
+        let event = if approved { "pair_approved" } else { "pair_denied" };
+        self.audit.record(event, Some(p.ip), Some(&p.device_id));
+        let _ = tx.send(approved);
+    }
+
+    // Drops a request nobody answered (timeout or shutdown)
+    pub fn cancel_approval(&mut self, id: u64) {
+        self.approvals.retain(|(p, _)| p.id != id);
+    }
+
     pub fn rl_register_failure(&mut self, ip: IpAddr) {
         let entry = self.rate_limit.entry(ip).or_insert_with(RateLimitEntry::new);
         entry.register_failure();
+        if entry.is_locked() {
+            self.audit.record("lockout", Some(ip), None);
+        }
     }
 }
 
-// Original pairing code generator, preserved (used by GUI + server)
-pub fn generate_pairing_code() -> String {
-    // Example behavior: 6-digit code derived from current time.
-    // (kept functionally identical to your original approach)
-    use std::time::{SystemTime, UNIX_EPOCH};
+pub fn is_valid_static_code(code: &str) -> bool {
+    CODE_LENGTHS.contains(&code.len()) && code.chars().all(|c| c.is_ascii_digit())
+}
 
-    let secs = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeCharset {
+    #[default]
+    Numeric,
+    // A-Z and 0-9
+    Alphanumeric,
+}
 
-    let mut n = (secs % 1_000_000) as u32;
-    let mut digits = Vec::with_capacity(6);
+// Shape of rotating pairing codes; 6 digits unless the owner asks for stronger ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CodeFormat {
+    pub length: usize,
+    pub charset: CodeCharset,
+}
 
-    for _ in 0..6 {
-        digits.push((n % 10) as u8);
-        n /= 10;
+pub const CODE_LENGTHS: std::ops::RangeInclusive<usize> = 6..=10;
+
+impl Default for CodeFormat {
+    fn default() -> Self {
+        Self { length: 6, charset: CodeCharset::Numeric }
     }
+}
+
+pub fn generate_pairing_code(format: CodeFormat) -> String {
+    use rand::Rng;
+
+    const DIGITS: &[u8] = b"0123456789";
+    const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
 
-    digits
-        .into_iter()
-        .rev()
-        .map(|d| char::from(b'0' + d))
+    let alphabet = match format.charset {
+        CodeCharset::Numeric => DIGITS,
+        CodeCharset::Alphanumeric => ALPHANUMERIC,
+    };
+    let length = format.length.clamp(*CODE_LENGTHS.start(), *CODE_LENGTHS.end());
+    let mut rng = rand::rngs::OsRng;
+    (0..length)
+        .map(|_| char::from(alphabet[rng.gen_range(0..alphabet.len())]))
         .collect()
 }
@@ -0,0 +1,96 @@
+// src/server/power.rs
+#![cfg(windows)]
+
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::system::{self, PowerAction};
+
+pub const MAX_POWER_DELAY_SECS: u64 = 24 * 60 * 60;
+
+struct Pending {
+    action: PowerAction,
+    force: bool,
+    due: Instant,
+    id: u64,
+}
+
+#[derive(Default)]
+struct Scheduled {
+    pending: Option<Pending>,
+    next_id: u64,
+}
+
+// At most one delayed shutdown/restart/sleep, shared by every connection and the GUI (which owns
+// it, like panic mute); a timer thread runs it when due unless it was cancelled or replaced first
+#[derive(Clone, Default)]
+pub struct PowerSchedule(Arc<(Mutex<Scheduled>, Condvar)>);
+
+impl PowerSchedule {
+    // Replaces whatever was pending
+    pub fn schedule(&self, action: PowerAction, force: bool, delay: Duration) {
+        let (lock, wake) = &*self.0;
+        let mut scheduled = lock.lock().unwrap();
+        scheduled.next_id += 1;
+        let id = scheduled.next_id;
+        scheduled.pending = Some(Pending { action, force, due: Instant::now() + delay, id });
+        wake.notify_all();
+        drop(scheduled);
+
+        let schedule = self.clone();
+        std::thread::spawn(move || schedule.run_when_due(id));
+    }
+
+    fn run_when_due(&self, id: u64) {
+        let (lock, wake) = &*self.0;
+        let mut scheduled = lock.lock().unwrap();
+        loop {
+            // cancelled or replaced
+            let Some(pending) = scheduled.pending.as_ref().filter(|p| p.id == id) else {
+                return;
+            };
+            let now = Instant::now();
+            if now >= pending.due {
+                break;
+            }
+            let wait = pending.due - now;
+            scheduled = wake.wait_timeout(scheduled, wait).unwrap().0;
+        }
+        let Some(pending) = scheduled.pending.take() else {
+            return;
+        };
+        drop(scheduled);
+
+        info!("Running scheduled {}", pending.action.as_str());
+        if let Err(e) = system::power_action(pending.action, pending.force) {
+            warn!("Scheduled {} failed: {e:#}", pending.action.as_str());
+        }
+    }
+
+    // The action that was pending, if any
+    pub fn cancel(&self) -> Option<PowerAction> {
+        let (lock, wake) = &*self.0;
+        let cancelled = lock.lock().unwrap().pending.take().map(|p| p.action);
+        wake.notify_all();
+        cancelled
+    }
+
+    // (action, whole seconds left, rounded up)
+    pub fn pending(&self) -> Option<(PowerAction, u64)> {
+        let scheduled = self.0.0.lock().unwrap();
+        scheduled.pending.as_ref().map(|p| {
+            let left = p.due.saturating_duration_since(Instant::now());
+            (p.action, left.as_millis().div_ceil(1000) as u64)
+        })
+    }
+
+    // {"action","seconds_remaining"}, or null with nothing pending
+    pub fn to_json(&self) -> Value {
+        match self.pending() {
+            Some((action, secs)) => json!({"action": action.as_str(), "seconds_remaining": secs}),
+            None => Value::Null,
+        }
+    }
+}
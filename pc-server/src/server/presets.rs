@@ -0,0 +1,70 @@
+// src/server/presets.rs
+#![cfg(windows)]
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::data_file;
+
+// Named master-volume levels, persisted as {"name": level}
+pub struct PresetStore {
+    path: PathBuf,
+    presets: BTreeMap<String, f32>,
+}
+
+pub fn presets_path() -> PathBuf {
+    data_file("presets.json")
+}
+
+impl PresetStore {
+    pub fn load(path: PathBuf) -> Self {
+        let presets = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_default(),
+            Err(_) => BTreeMap::new(),
+        };
+        Self { path, presets }
+    }
+
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.presets.get(name).copied()
+    }
+
+    // [{"name":..,"level":..}] sorted by name
+    pub fn to_json(&self) -> Value {
+        let list: Vec<_> = self
+            .presets
+            .iter()
+            .map(|(name, level)| json!({"name": name, "level": level}))
+            .collect();
+        json!(list)
+    }
+
+    pub fn upsert(&mut self, name: String, level: f32) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("preset name must not be empty");
+        }
+        if !(0.0..=1.0).contains(&level) {
+            bail!("level must be between 0.0 and 1.0");
+        }
+        self.presets.insert(name, level);
+        self.save()
+    }
+
+    // false if no preset had that name
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        if self.presets.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> Result<()> {
+        let s = serde_json::to_string_pretty(&self.presets)?;
+        fs::write(&self.path, s)?;
+        Ok(())
+    }
+}
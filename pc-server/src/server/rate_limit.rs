@@ -42,6 +42,17 @@ impl RateLimitEntry {
         }
     }
 
+    // failures left before lockout in the current window
+    pub(crate) fn attempts_remaining(&self) -> u32 {
+        if self.is_locked() {
+            0
+        } else if self.window_start.elapsed() > RL_WINDOW {
+            RL_MAX_ATTEMPTS
+        } else {
+            RL_MAX_ATTEMPTS.saturating_sub(self.attempts)
+        }
+    }
+
     pub(crate) fn register_failure(&mut self) {
         // reset window if expired
         if self.window_start.elapsed() > RL_WINDOW {
@@ -62,3 +73,52 @@ impl RateLimitEntry {
         self.lockout_until = None;
     }
 }
+
+// Caps injected input events (keys/mouse) across all clients; refills at `rate` per second
+// with bursts of up to `burst` events, or one second's worth if that is more. Separate from the
+// auth limiter above.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(per_sec: u32, burst: usize) -> Self {
+        let rate = per_sec.max(1) as f64;
+        let burst = burst as f64;
+        Self {
+            rate,
+            burst,
+            tokens: rate.max(burst),
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn capacity(&self) -> f64 {
+        self.rate.max(self.burst)
+    }
+
+    // Applies a changed limit without resetting the bucket
+    pub fn set_rate(&mut self, per_sec: u32) {
+        self.rate = per_sec.max(1) as f64;
+        self.tokens = self.tokens.min(self.capacity());
+    }
+
+    // Takes `n` tokens (one per event) or none; false means the events should be dropped
+    pub fn try_take(&mut self, n: usize) -> bool {
+        let now = Instant::now();
+        let refill = (now - self.last_refill).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.capacity());
+        self.last_refill = now;
+
+        if self.tokens >= n as f64 {
+            self.tokens -= n as f64;
+            true
+        } else {
+            false
+        }
+    }
+}
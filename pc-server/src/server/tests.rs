@@ -0,0 +1,2161 @@
+// src/server/tests.rs
+#![cfg(windows)]
+
+// End-to-end: real run_ws_server on an ephemeral port, driven by a tungstenite client.
+
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{client_async, connect_async, MaybeTlsStream, WebSocketStream};
+
+use crate::audio::{AppSession, AudioBackend, AudioDevice, CaptureDevice, DeviceState, EndpointState, OutputDevice, OutputRole, PresetEntry, PresetOutcome, SessionInfo, SessionState};
+use crate::config::{Config, SharedConfig};
+use crate::server::identity::ServerIdentity;
+use crate::server::groups::GroupStore;
+use crate::server::launchers::{Launcher, LauncherStore};
+use crate::server::presets::PresetStore;
+use crate::server::{run_ws_server, PairingState};
+
+const CODE: &str = "123456";
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+struct MockAudio {
+    state: Mutex<(f32, bool, bool)>,
+    // (console default, communications default) among "spk-1"/"spk-2"
+    outputs: Mutex<(String, String)>,
+    mic_volume: Mutex<f32>,
+    // (volume, muted) of the communications device; multimedia shares the console state
+    comms: Mutex<(f32, bool)>,
+    // None = no microphone at all
+    default_capture: Mutex<Option<String>>,
+    // None = mono output
+    balance: Mutex<Option<f32>>,
+    sessions: Mutex<Vec<AppSession>>,
+    // None = no system sound has played yet
+    system_sounds: Mutex<Option<(f32, bool)>>,
+    // (volume, muted) by endpoint id, separate from the default device's state
+    endpoints: Mutex<HashMap<String, (f32, bool)>>,
+}
+
+impl MockAudio {
+    fn new(volume: f32, muted: bool, mic_muted: bool) -> Self {
+        Self {
+            state: Mutex::new((volume, muted, mic_muted)),
+            outputs: Mutex::new(("spk-1".into(), "spk-1".into())),
+            mic_volume: Mutex::new(0.8),
+            comms: Mutex::new((0.3, false)),
+            default_capture: Mutex::new(Some("mic-1".into())),
+            balance: Mutex::new(Some(0.0)),
+            sessions: Mutex::new(vec![
+                AppSession { session_id: "s-discord".into(), pid: 100, name: "Discord".into(), volume: 1.0, muted: false },
+                AppSession { session_id: "s-spotify".into(), pid: 200, name: "Spotify".into(), volume: 1.0, muted: false },
+            ]),
+            system_sounds: Mutex::new(Some((1.0, false))),
+            endpoints: Mutex::new(HashMap::from([("spk-1".into(), (0.5, false)), ("spk-2".into(), (0.5, false))])),
+        }
+    }
+
+    fn mute_sessions(&self, matches: impl Fn(&AppSession) -> bool, mute: bool) -> Vec<AppSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut affected = Vec::new();
+        for s in sessions.iter_mut().filter(|s| matches(s)) {
+            s.muted = mute;
+            affected.push(s.clone());
+        }
+        affected
+    }
+
+    fn require_mic(&self) -> Result<()> {
+        if self.default_capture.lock().unwrap().is_none() {
+            anyhow::bail!("no default microphone");
+        }
+        Ok(())
+    }
+}
+
+impl AudioBackend for MockAudio {
+    fn get_volume_and_mute(&self) -> Result<(f32, bool)> {
+        let st = self.state.lock().unwrap();
+        Ok((st.0, st.1))
+    }
+
+    fn set_volume(&self, level: f32) -> Result<()> {
+        self.state.lock().unwrap().0 = level.clamp(0.0, 1.0);
+        Ok(())
+    }
+
+    fn set_mute(&self, mute: bool) -> Result<()> {
+        self.state.lock().unwrap().1 = mute;
+        Ok(())
+    }
+
+    fn get_role_volume_and_mute(&self, role: OutputRole) -> Result<(f32, bool)> {
+        match role {
+            OutputRole::Communications => Ok(*self.comms.lock().unwrap()),
+            _ => self.get_volume_and_mute(),
+        }
+    }
+
+    fn set_role_volume(&self, role: OutputRole, level: f32) -> Result<()> {
+        match role {
+            OutputRole::Communications => {
+                self.comms.lock().unwrap().0 = level.clamp(0.0, 1.0);
+                Ok(())
+            }
+            _ => self.set_volume(level),
+        }
+    }
+
+    fn set_role_mute(&self, role: OutputRole, mute: bool) -> Result<()> {
+        match role {
+            OutputRole::Communications => {
+                self.comms.lock().unwrap().1 = mute;
+                Ok(())
+            }
+            _ => self.set_mute(mute),
+        }
+    }
+
+    // a single mic regardless of the requested device, failing like the real one once it's gone
+    fn get_mic_mute(&self, _device_id: Option<&str>) -> Result<bool> {
+        self.require_mic()?;
+        Ok(self.state.lock().unwrap().2)
+    }
+
+    fn set_mic_mute(&self, _device_id: Option<&str>, mute: bool) -> Result<()> {
+        self.require_mic()?;
+        self.state.lock().unwrap().2 = mute;
+        Ok(())
+    }
+
+    fn get_mic_volume(&self) -> Result<f32> {
+        Ok(*self.mic_volume.lock().unwrap())
+    }
+
+    fn set_mic_volume(&self, level: f32) -> Result<()> {
+        *self.mic_volume.lock().unwrap() = level;
+        Ok(())
+    }
+
+    fn get_peak_level(&self) -> Result<f32> {
+        Ok(0.42)
+    }
+
+    fn list_input_devices(&self) -> Result<Vec<AudioDevice>> {
+        Ok(vec![AudioDevice { id: "mic-1".into(), name: "Test mic".into(), is_default: true }])
+    }
+
+    fn default_output_device(&self) -> Result<AudioDevice> {
+        Err(anyhow::anyhow!("no output device"))
+    }
+
+    fn default_input_device(&self) -> Result<Option<AudioDevice>> {
+        let default = self.default_capture.lock().unwrap().clone();
+        Ok(default.map(|id| AudioDevice { name: format!("Mic {id}"), id, is_default: true }))
+    }
+
+    fn list_output_devices(&self) -> Result<Vec<OutputDevice>> {
+        let (console, comms) = self.outputs.lock().unwrap().clone();
+        Ok(["spk-1", "spk-2"]
+            .into_iter()
+            .map(|id| OutputDevice {
+                id: id.into(),
+                name: format!("Speaker {id}"),
+                is_default: console == id,
+                is_default_comms: comms == id,
+            })
+            .collect())
+    }
+
+    fn set_default_output(&self, device_id: &str, role: OutputRole) -> Result<()> {
+        let mut outputs = self.outputs.lock().unwrap();
+        if !matches!(role, OutputRole::Communications) {
+            outputs.0 = device_id.into();
+        }
+        if matches!(role, OutputRole::Communications | OutputRole::All) {
+            outputs.1 = device_id.into();
+        }
+        Ok(())
+    }
+
+    // mic-1/mic-2 work; mic-3 is disabled in Sound settings
+    fn list_capture_devices(&self) -> Result<Vec<CaptureDevice>> {
+        let default = self.default_capture.lock().unwrap().clone().unwrap_or_default();
+        Ok([("mic-1", EndpointState::Active), ("mic-2", EndpointState::Active), ("mic-3", EndpointState::Disabled)]
+            .into_iter()
+            .map(|(id, state)| CaptureDevice { id: id.into(), name: format!("Mic {id}"), is_default: default == id, state })
+            .collect())
+    }
+
+    fn set_default_capture(&self, device_id: &str) -> Result<()> {
+        *self.default_capture.lock().unwrap() = Some(device_id.into());
+        Ok(())
+    }
+
+    // spk-2 plays the part of a device some app holds in exclusive mode
+    fn device_state(&self, device_id: &str) -> Result<DeviceState> {
+        Ok(DeviceState {
+            id: device_id.into(),
+            name: format!("Speaker {device_id}"),
+            sample_rate: 48_000,
+            bits_per_sample: 24,
+            channels: 2,
+            exclusive_in_use: device_id == "spk-2",
+        })
+    }
+
+    // spk-1/spk-2 are plugged in; any other id counts as unplugged
+    fn set_endpoint_volume(&self, device_id: &str, level: f32) -> Result<bool> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let Some(state) = endpoints.get_mut(device_id) else {
+            return Ok(false);
+        };
+        state.0 = level;
+        Ok(true)
+    }
+
+    fn set_endpoint_mute(&self, device_id: &str, mute: bool) -> Result<bool> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let Some(state) = endpoints.get_mut(device_id) else {
+            return Ok(false);
+        };
+        state.1 = mute;
+        Ok(true)
+    }
+
+    fn get_endpoint_volume_and_mute(&self, device_id: &str) -> Result<Option<(f32, bool)>> {
+        Ok(self.endpoints.lock().unwrap().get(device_id).copied())
+    }
+
+    // scalar stands in for dB linearly over a -60..0 range
+    fn get_volume_db(&self) -> Result<f32> {
+        Ok(self.state.lock().unwrap().0 * 60.0 - 60.0)
+    }
+
+    fn set_volume_db(&self, db: f32) -> Result<()> {
+        self.set_volume((db + 60.0) / 60.0)
+    }
+
+    fn get_volume_range_db(&self) -> Result<(f32, f32)> {
+        Ok((-60.0, 0.0))
+    }
+
+    // one Discord session (pid 100) and one Spotify session (pid 200)
+    fn list_app_sessions(&self) -> Result<Vec<AppSession>> {
+        Ok(self.sessions.lock().unwrap().clone())
+    }
+
+    fn list_audio_sessions(&self) -> Result<Vec<SessionInfo>> {
+        Ok(self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| SessionInfo {
+                session_id: s.session_id.clone(),
+                pid: s.pid,
+                process_name: Some(s.name.clone()),
+                display_name: String::new(),
+                volume: s.volume,
+                muted: s.muted,
+                state: if s.pid == 100 { SessionState::Active } else { SessionState::Inactive },
+            })
+            .collect())
+    }
+
+    fn set_process_volume(&self, pid: u32, level: f32) -> Result<bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut found = false;
+        for s in sessions.iter_mut().filter(|s| s.pid == pid) {
+            s.volume = level;
+            found = true;
+        }
+        Ok(found)
+    }
+
+    fn set_app_volume(&self, app_id: &str, level: f32) -> Result<bool> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut found = false;
+        for s in sessions.iter_mut().filter(|s| s.name.eq_ignore_ascii_case(app_id)) {
+            s.volume = level;
+            found = true;
+        }
+        Ok(found)
+    }
+
+    fn mute_session(&self, session_id: &str, mute: bool) -> Result<Vec<AppSession>> {
+        Ok(self.mute_sessions(|s| s.session_id == session_id, mute))
+    }
+
+    fn mute_process_sessions(&self, process_name: &str, mute: bool) -> Result<Vec<AppSession>> {
+        let wanted = process_name.strip_suffix(".exe").unwrap_or(process_name);
+        Ok(self.mute_sessions(|s| s.name.eq_ignore_ascii_case(wanted), mute))
+    }
+
+    // the mock's session names stand in for process names
+    fn apply_session_preset(&self, entries: &[PresetEntry]) -> Result<Vec<PresetOutcome>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        Ok(entries
+            .iter()
+            .map(|entry| {
+                let mut applied = Vec::new();
+                for s in sessions.iter_mut().filter(|s| entry.matches(Some(&s.session_id), Some(&s.name))) {
+                    s.volume = entry.level.unwrap_or(s.volume);
+                    s.muted = entry.mute.unwrap_or(s.muted);
+                    applied.push(s.clone());
+                }
+                if applied.is_empty() {
+                    PresetOutcome::NotFound
+                } else {
+                    PresetOutcome::Applied { sessions: applied }
+                }
+            })
+            .collect())
+    }
+
+    fn get_system_sounds(&self) -> Result<Option<(f32, bool)>> {
+        Ok(*self.system_sounds.lock().unwrap())
+    }
+
+    fn set_system_sounds(&self, level: Option<f32>, mute: Option<bool>) -> Result<Option<(f32, bool)>> {
+        let mut state = self.system_sounds.lock().unwrap();
+        if let Some((volume, muted)) = state.as_mut() {
+            *volume = level.unwrap_or(*volume);
+            *muted = mute.unwrap_or(*muted);
+        }
+        Ok(*state)
+    }
+
+    fn get_balance(&self) -> Result<Option<f32>> {
+        Ok(*self.balance.lock().unwrap())
+    }
+
+    fn set_balance(&self, value: f32) -> Result<bool> {
+        let mut balance = self.balance.lock().unwrap();
+        let Some(current) = balance.as_mut() else {
+            return Ok(false);
+        };
+        *current = value;
+        Ok(true)
+    }
+}
+
+struct TestServer {
+    port: u16,
+    store_dir: PathBuf,
+    pairing: Arc<Mutex<PairingState>>,
+    config: SharedConfig,
+    // the server's own backend, for changes made "outside" the server
+    audio: Arc<MockAudio>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.store_dir);
+    }
+}
+
+fn test_identity() -> ServerIdentity {
+    ServerIdentity { signing_key: hex::encode([7u8; 32]) }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn start_server(audio: MockAudio) -> TestServer {
+    start_server_with(audio, Config::default())
+}
+
+fn start_server_with(audio: MockAudio, config: Config) -> TestServer {
+    let port = free_port();
+    let store_dir = std::env::temp_dir().join(format!("fossdeck-test-{}-{port}", std::process::id()));
+    std::fs::create_dir_all(&store_dir).unwrap();
+
+    let pairing = Arc::new(Mutex::new(PairingState::with_store_path(
+        CODE.to_string(),
+        store_dir.join("authorized.json"),
+    )));
+    let identity = test_identity();
+    let config = Arc::new(RwLock::new(config));
+    let audio = Arc::new(audio);
+    let (shutdown, rx) = oneshot::channel();
+
+    tokio::spawn(run_ws_server(
+        port,
+        rx,
+        pairing.clone(),
+        identity,
+        config.clone(),
+        audio.clone(),
+        PresetStore::load(store_dir.join("presets.json")),
+        GroupStore::load(store_dir.join("groups.json")),
+        LauncherStore::load(store_dir.join("launchers.json")),
+        Default::default(),
+        Default::default(),
+    ));
+
+    TestServer { port, store_dir, pairing, config, audio, _shutdown: shutdown }
+}
+
+// Connects (retrying while the server binds) and consumes the hello message
+async fn connect(port: u16) -> (Client, Value) {
+    let url = format!("ws://127.0.0.1:{port}/ws");
+    for _ in 0..50 {
+        if let Ok((mut ws, _)) = connect_async(url.as_str()).await {
+            let hello = recv(&mut ws).await;
+            assert_eq!(hello["type"], "hello");
+            return (ws, hello);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("server on port {port} never came up");
+}
+
+// Like connect(), but from a specific loopback source address
+async fn connect_from(port: u16, source: &str) -> (Client, Value) {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(format!("{source}:0").parse().unwrap()).unwrap();
+    let stream = socket.connect(([127, 0, 0, 1], port).into()).await.unwrap();
+    let (mut ws, _) = client_async(format!("ws://127.0.0.1:{port}/ws"), MaybeTlsStream::Plain(stream))
+        .await
+        .unwrap();
+    let hello = recv(&mut ws).await;
+    assert_eq!(hello["type"], "hello");
+    (ws, hello)
+}
+
+async fn recv(ws: &mut Client) -> Value {
+    loop {
+        let msg = tokio::time::timeout(Duration::from_secs(5), ws.next())
+            .await
+            .expect("timed out waiting for reply")
+            .expect("connection closed")
+            .expect("ws error");
+        if let Message::Text(t) = msg {
+            return serde_json::from_str(&t).unwrap();
+        }
+    }
+}
+
+async fn request(ws: &mut Client, v: Value) -> Value {
+    ws.send(Message::Text(v.to_string())).await.unwrap();
+    recv(ws).await
+}
+
+// Fresh connection that has paired as `device_id`
+async fn paired_client(port: u16, device_id: &str) -> Client {
+    let (mut ws, _) = connect(port).await;
+    let reply = request(&mut ws, json!({"cmd":"pair","code":CODE,"device_id":device_id})).await;
+    assert_eq!(reply["type"], "pairing_ok");
+    ws
+}
+
+#[tokio::test]
+async fn pair_then_auth_then_get_status() {
+    let server = start_server(MockAudio::new(0.25, true, false));
+
+    // whoever can open a socket must not learn the code from it
+    let (mut ws, hello) = connect(server.port).await;
+    assert!(hello.get("pairing_code").is_none());
+    assert_eq!(hello["pairing_code_expired"], false);
+
+    let reply = request(
+        &mut ws,
+        json!({"cmd":"pair","code":CODE,"device_id":"phone-1","device_name":"Test phone"}),
+    )
+    .await;
+    assert_eq!(reply["type"], "pairing_ok");
+    let token = reply["token"].as_str().unwrap().to_string();
+    drop(ws);
+
+    let (mut ws, _) = connect(server.port).await;
+    let reply = request(&mut ws, json!({"cmd":"auth","device_id":"phone-1","token":token})).await;
+    assert_eq!(reply["type"], "auth_ok");
+
+    let reply = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(reply["type"], "status");
+    assert_eq!(reply["volume"], json!(0.25));
+    assert_eq!(reply["muted"], true);
+    assert_eq!(reply["mic_muted"], false);
+    // the mock has no default output device
+    assert!(reply["default_output_id"].is_null());
+}
+
+#[tokio::test]
+async fn auth_challenge_is_signed_by_the_identity_key() {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let (mut ws, hello) = connect(server.port).await;
+    let reply = request(
+        &mut ws,
+        json!({"cmd":"pair","code":CODE,"device_id":"phone-1","challenge":"nonce-1"}),
+    )
+    .await;
+    assert_eq!(reply["type"], "pairing_ok");
+    assert_eq!(reply["identity_key"], hello["identity_key"]);
+
+    let key: [u8; 32] = hex::decode(reply["identity_key"].as_str().unwrap()).unwrap().try_into().unwrap();
+    let sig: [u8; 64] = hex::decode(reply["challenge_sig"].as_str().unwrap()).unwrap().try_into().unwrap();
+    let key = VerifyingKey::from_bytes(&key).unwrap();
+    let sig = Signature::from_bytes(&sig);
+    assert!(key.verify(b"FOSSDECK_AUTH_V1:nonce-1", &sig).is_ok());
+    assert!(key.verify(b"FOSSDECK_AUTH_V1:nonce-2", &sig).is_err());
+
+    // no challenge, no signature; oversized challenges are not signed either
+    let token = reply["token"].as_str().unwrap().to_string();
+    drop(ws);
+    let (mut ws, _) = connect(server.port).await;
+    let reply = request(
+        &mut ws,
+        json!({"cmd":"auth","device_id":"phone-1","token":token,"challenge":"x".repeat(200)}),
+    )
+    .await;
+    assert_eq!(reply["type"], "auth_ok");
+    assert!(reply["challenge_sig"].is_null());
+}
+
+#[test]
+fn discovery_replies_are_signed_by_the_identity_key() {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let identity = test_identity();
+    let payload = format!("pc|3030|/ws|1.0|nonce-1|{}", identity.identity_fingerprint().unwrap());
+    let key: [u8; 32] = hex::decode(identity.identity_key().unwrap()).unwrap().try_into().unwrap();
+    let sig: [u8; 64] = hex::decode(identity.sign_discovery(&payload).unwrap()).unwrap().try_into().unwrap();
+    let key = VerifyingKey::from_bytes(&key).unwrap();
+    let sig = Signature::from_bytes(&sig);
+    assert!(key.verify(format!("FOSSDECK_DISCOVERY_V1:{payload}").as_bytes(), &sig).is_ok());
+    // an auth challenge signature can't be passed off as a discovery reply
+    assert!(key.verify(format!("FOSSDECK_AUTH_V1:{payload}").as_bytes(), &sig).is_err());
+}
+
+#[tokio::test]
+async fn identity_is_proven_before_any_secret_is_sent() {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let (mut ws, hello) = connect(server.port).await;
+    let reply = request(&mut ws, json!({"cmd":"identify","challenge":"nonce-1"})).await;
+    assert_eq!(reply["type"], "identity");
+    assert_eq!(reply["identity_key"], hello["identity_key"]);
+
+    let key: [u8; 32] = hex::decode(reply["identity_key"].as_str().unwrap()).unwrap().try_into().unwrap();
+    let sig: [u8; 64] = hex::decode(reply["challenge_sig"].as_str().unwrap()).unwrap().try_into().unwrap();
+    let key = VerifyingKey::from_bytes(&key).unwrap();
+    assert!(key.verify(b"FOSSDECK_AUTH_V1:nonce-1", &Signature::from_bytes(&sig)).is_ok());
+
+    // identifying grants nothing
+    let reply = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(reply["code"], "not_authenticated");
+}
+
+#[tokio::test]
+async fn command_without_auth_is_rejected() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let (mut ws, _) = connect(server.port).await;
+
+    let reply = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], "not_authenticated");
+
+    let reply = request(&mut ws, json!({"cmd":"whoami"})).await;
+    assert_eq!(reply["code"], "not_authenticated");
+}
+
+#[tokio::test]
+async fn whoami_reports_the_caller() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let (mut ws, _) = connect(server.port).await;
+    let reply = request(
+        &mut ws,
+        json!({"cmd":"pair","code":CODE,"device_id":"phone-6","device_name":"Pixel"}),
+    )
+    .await;
+    assert_eq!(reply["type"], "pairing_ok");
+
+    let reply = request(&mut ws, json!({"cmd":"whoami"})).await;
+    assert_eq!(reply["type"], "whoami");
+    assert_eq!(reply["device_id"], "phone-6");
+    assert_eq!(reply["name"], "Pixel");
+    assert_eq!(reply["active"], true);
+    assert_eq!(reply["scopes"], json!(["volume", "media", "system"]));
+}
+
+#[tokio::test]
+async fn wrong_code_is_rate_limited() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let (mut ws, _) = connect(server.port).await;
+    let pair = json!({"cmd":"pair","code":"000000","device_id":"phone-2"});
+
+    for remaining in (0..5).rev() {
+        let reply = request(&mut ws, pair.clone()).await;
+        assert_eq!(reply["type"], "pairing_error");
+        assert_eq!(reply["code"], "invalid_code");
+        assert_eq!(reply["attempts_remaining"], remaining);
+    }
+
+    let reply = request(&mut ws, pair).await;
+    assert_eq!(reply["type"], "rate_limited");
+    assert_eq!(reply["code"], "rate_limited");
+    assert_eq!(reply["reason"], "pair");
+}
+
+#[tokio::test]
+async fn close_frame_frees_active_session() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-3").await;
+
+    ws.close(None).await.unwrap();
+    // the server acknowledges the close, after which the stream ends
+    let drained = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(msg)) = ws.next().await {
+            if msg.is_close() {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(drained.is_ok(), "server never acknowledged the close");
+
+    let (_ws, hello) = connect(server.port).await;
+    assert_eq!(hello["paired"], false);
+    assert_eq!(hello["active_device_id"], Value::Null);
+}
+
+#[tokio::test]
+async fn volume_presets_round_trip() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-4").await;
+
+    let reply = request(&mut ws, json!({"cmd":"save_preset","name":"loud","level":1.5})).await;
+    assert_eq!(reply["code"], "bad_request");
+
+    let reply = request(&mut ws, json!({"cmd":"save_preset","name":"quiet","level":0.125})).await;
+    assert_eq!(reply["type"], "ok");
+    assert_eq!(reply["presets"], json!([{"name":"quiet","level":0.125}]));
+
+    let reply = request(&mut ws, json!({"cmd":"apply_preset","name":"quiet"})).await;
+    assert_eq!(reply["type"], "status");
+    assert_eq!(reply["volume"], json!(0.125));
+
+    let reply = request(&mut ws, json!({"cmd":"delete_preset","name":"quiet"})).await;
+    assert_eq!(reply["presets"], json!([]));
+
+    let reply = request(&mut ws, json!({"cmd":"apply_preset","name":"quiet"})).await;
+    assert_eq!(reply["code"], "not_found");
+}
+
+#[tokio::test]
+async fn malformed_message_flood_closes_connection() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let (mut ws, _) = connect(server.port).await;
+
+    // a good message in between resets the count
+    for _ in 0..5 {
+        let reply = request(&mut ws, json!("garbage")).await;
+        assert_eq!(reply["code"], "bad_request");
+    }
+    let reply = request(&mut ws, json!({"cmd":"ping"})).await;
+    assert_eq!(reply["code"], "not_authenticated");
+
+    for _ in 0..9 {
+        let reply = request(&mut ws, json!("garbage")).await;
+        assert_eq!(reply["code"], "bad_request");
+    }
+    let reply = request(&mut ws, json!("garbage")).await;
+    assert_eq!(reply["code"], "too_many_bad_messages");
+
+    let next = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap();
+    assert!(matches!(next, Some(Ok(Message::Close(_))) | None));
+}
+
+#[tokio::test]
+async fn session_survives_reconnect_from_new_address() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+
+    let (mut ws, _) = connect_from(server.port, "127.0.0.1").await;
+    let reply = request(&mut ws, json!({"cmd":"pair","code":CODE,"device_id":"phone-5"})).await;
+    let token = reply["token"].as_str().unwrap().to_string();
+    drop(ws);
+
+    let (mut ws, hello) = connect_from(server.port, "127.0.0.2").await;
+    assert_eq!(hello["active_device_id"], "phone-5");
+
+    let reply = request(&mut ws, json!({"cmd":"auth","device_id":"phone-5","token":token})).await;
+    assert_eq!(reply["type"], "auth_ok");
+
+    let reply = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(reply["type"], "status");
+}
+
+#[tokio::test]
+async fn config_changes_apply_without_restart() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-1").await;
+
+    let reply = request(&mut ws, json!({"cmd":"volume_up"})).await;
+    assert!((reply["volume"].as_f64().unwrap() - 0.55).abs() < 1e-4);
+
+    {
+        let mut config = server.config.write().unwrap();
+        config.volume_step = 0.2;
+        config.disabled_categories = vec!["media".into()];
+    }
+    let reply = request(&mut ws, json!({"cmd":"volume_up"})).await;
+    assert!((reply["volume"].as_f64().unwrap() - 0.75).abs() < 1e-4);
+    let reply = request(&mut ws, json!({"cmd":"next_track"})).await;
+    assert_eq!(reply["code"], "disabled");
+}
+
+#[tokio::test]
+async fn mic_volume_is_clamped_and_reported_in_status() {
+    let server = start_server(MockAudio::new(0.5, false, true));
+    let mut ws = paired_client(server.port, "phone-1").await;
+
+    let reply = request(&mut ws, json!({"cmd":"get_mic_volume"})).await;
+    assert_eq!(reply["type"], "mic_volume");
+    assert!((reply["level"].as_f64().unwrap() - 0.8).abs() < 1e-6);
+    assert_eq!(reply["muted"], true);
+
+    let reply = request(&mut ws, json!({"cmd":"set_mic_volume","level":1.7})).await;
+    assert_eq!(reply["mic_volume"], json!(1.0));
+    let reply = request(&mut ws, json!({"cmd":"set_mic_volume","level":-0.2})).await;
+    assert_eq!(reply["mic_volume"], json!(0.0));
+
+    let reply = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(reply["mic_volume"], json!(0.0));
+}
+
+#[tokio::test]
+async fn peaks_are_pushed_until_unsubscribed() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-1").await;
+
+    let reply = request(&mut ws, json!({"cmd":"subscribe_peaks","interval_ms":1})).await;
+    assert_eq!(reply["interval_ms"], 50);
+    for _ in 0..3 {
+        let peak = recv(&mut ws).await;
+        assert_eq!(peak["type"], "peak");
+        assert!((peak["level"].as_f64().unwrap() - 0.42).abs() < 1e-6);
+    }
+
+    let mut reply = request(&mut ws, json!({"cmd":"unsubscribe_peaks"})).await;
+    // frames already in flight may precede the ack
+    while reply["type"] == "peak" {
+        reply = recv(&mut ws).await;
+    }
+    assert_eq!(reply["action"], "unsubscribe_peaks");
+    let quiet = tokio::time::timeout(Duration::from_millis(300), ws.next()).await;
+    assert!(quiet.is_err(), "peak pushed after unsubscribe: {quiet:?}");
+}
+
+#[tokio::test]
+async fn adjust_volume_percent_steps_and_clamps() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-1").await;
+
+    let reply = request(&mut ws, json!({"cmd":"adjust_volume_percent","delta":5})).await;
+    assert_eq!(reply["percent"], 55);
+    assert!((reply["volume"].as_f64().unwrap() - 0.55).abs() < 1e-4);
+
+    let reply = request(&mut ws, json!({"cmd":"adjust_volume_percent","delta":-200})).await;
+    assert_eq!(reply["percent"], 0);
+    let reply = request(&mut ws, json!({"cmd":"adjust_volume_percent","delta":i32::MAX})).await;
+    assert_eq!(reply["percent"], 100);
+}
+
+#[tokio::test]
+async fn undo_restores_previous_volume_and_mute() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-7").await;
+
+    let reply = request(&mut ws, json!({"cmd":"undo_audio"})).await;
+    assert_eq!(reply, json!({"type":"ok","action":"undo","changed":false}));
+
+    request(&mut ws, json!({"cmd":"set_volume","level":0.75})).await;
+    // a refused change leaves nothing to undo
+    let reply = request(&mut ws, json!({"cmd":"apply_preset","name":"missing"})).await;
+    assert_eq!(reply["type"], "error");
+    request(&mut ws, json!({"cmd":"mute"})).await;
+
+    let reply = request(&mut ws, json!({"cmd":"undo_audio"})).await;
+    assert_eq!(reply["changed"], true);
+    assert_eq!(reply["volume"], json!(0.75));
+    assert_eq!(reply["muted"], false);
+
+    let reply = request(&mut ws, json!({"cmd":"undo_audio"})).await;
+    assert_eq!(reply["volume"], json!(0.5));
+
+    let reply = request(&mut ws, json!({"cmd":"undo_audio"})).await;
+    assert_eq!(reply["changed"], false);
+}
+
+#[tokio::test]
+async fn status_interval_pushes_until_stopped() {
+    let server = start_server(MockAudio::new(0.3, false, true));
+    let mut ws = paired_client(server.port, "phone-8").await;
+
+    let reply = request(&mut ws, json!({"cmd":"set_status_interval","ms":10})).await;
+    assert_eq!(reply["ms"], 500);
+
+    let pushed = recv(&mut ws).await;
+    assert_eq!(pushed["type"], "status");
+    assert_eq!(pushed["mic_muted"], true);
+
+    ws.send(Message::Text(json!({"cmd":"set_status_interval","ms":null}).to_string()))
+        .await
+        .unwrap();
+    // a push may already be in flight ahead of the reply
+    let mut reply = recv(&mut ws).await;
+    while reply["type"] == "status" {
+        reply = recv(&mut ws).await;
+    }
+    assert_eq!(reply["ms"], Value::Null);
+
+    let quiet = tokio::time::timeout(Duration::from_millis(1200), ws.next()).await;
+    assert!(quiet.is_err(), "status kept arriving after the timer was stopped");
+}
+
+#[tokio::test]
+async fn foreign_origin_is_refused_before_upgrade() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::Error;
+
+    let server = start_server(MockAudio::new(0.5, false, false));
+    // wait for the listener
+    connect(server.port).await;
+
+    let url = format!("ws://127.0.0.1:{}/ws", server.port);
+    let mut req = url.as_str().into_client_request().unwrap();
+    req.headers_mut().insert("origin", "https://evil.example".parse().unwrap());
+    match connect_async(req).await {
+        Err(Error::Http(resp)) => assert_eq!(resp.status(), 403),
+        other => panic!("expected 403, got {other:?}"),
+    }
+
+    let mut req = url.as_str().into_client_request().unwrap();
+    req.headers_mut().insert("origin", "http://tauri.localhost".parse().unwrap());
+    assert!(connect_async(req).await.is_ok());
+}
+
+#[tokio::test]
+async fn full_state_nulls_unavailable_pieces() {
+    let server = start_server(MockAudio::new(0.4, true, false));
+    let mut ws = paired_client(server.port, "phone-9").await;
+
+    let reply = request(&mut ws, json!({"cmd":"get_full_state"})).await;
+    assert_eq!(reply["type"], "full_state");
+    assert_eq!(reply["volume"], json!(0.4));
+    assert_eq!(reply["muted"], true);
+    assert_eq!(reply["mic_muted"], false);
+    assert_eq!(reply["output_device"], Value::Null);
+    assert!(reply["capabilities"].as_array().is_some_and(|c| c.contains(&json!("get_status"))));
+}
+
+#[tokio::test]
+async fn first_pairing_waits_for_owner_approval() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    server.pairing.lock().unwrap().set_require_approval(true);
+
+    // Answers the single pending request once it shows up in the GUI list
+    let resolve = |approved: bool| {
+        let pairing = server.pairing.clone();
+        async move {
+            loop {
+                let pending = pairing.lock().unwrap().pending_approvals();
+                if let Some(p) = pending.first() {
+                    pairing.lock().unwrap().resolve_approval(p.id, approved);
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+    };
+
+    let (mut ws, _) = connect(server.port).await;
+    let reply = request(&mut ws, json!({"cmd":"pair","code":CODE,"device_id":"phone-1"})).await;
+    assert_eq!(reply["type"], "pairing_pending");
+    resolve(false).await;
+    let reply = recv(&mut ws).await;
+    assert_eq!(reply["type"], "pairing_denied");
+    assert_eq!(reply["reason"], "denied");
+    assert_eq!(server.pairing.lock().unwrap().authorized_count(), 0);
+
+    let reply = request(&mut ws, json!({"cmd":"pair","code":CODE,"device_id":"phone-1"})).await;
+    assert_eq!(reply["type"], "pairing_pending");
+    resolve(true).await;
+    let reply = recv(&mut ws).await;
+    assert_eq!(reply["type"], "pairing_ok");
+    assert!(reply["token"].is_string());
+
+    // Already-known devices re-pair without another prompt
+    drop(ws);
+    let (mut ws, _) = connect(server.port).await;
+    let reply = request(&mut ws, json!({"cmd":"pair","code":CODE,"device_id":"phone-1"})).await;
+    assert_eq!(reply["type"], "pairing_ok");
+}
+
+#[tokio::test]
+async fn slow_approval_outlasts_the_read_timeout() {
+    let config = Config { read_timeout_secs: 1, ..Config::default() };
+    let server = start_server_with(MockAudio::new(0.5, false, false), config);
+    server.pairing.lock().unwrap().set_require_approval(true);
+
+    let (mut ws, _) = connect(server.port).await;
+    let reply = request(&mut ws, json!({"cmd":"pair","code":CODE,"device_id":"phone-40"})).await;
+    assert_eq!(reply["type"], "pairing_pending");
+
+    // the owner answers well after the 1s read timeout; recv keeps answering pings meanwhile
+    let pairing = server.pairing.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        let id = pairing.lock().unwrap().pending_approvals()[0].id;
+        pairing.lock().unwrap().resolve_approval(id, true);
+    });
+    let reply = recv(&mut ws).await;
+    assert_eq!(reply["type"], "pairing_ok");
+
+    // the deadline restarted at the decision, so the paired session carries on
+    let reply = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(reply["type"], "status");
+}
+
+#[tokio::test]
+async fn dropped_session_resumes_only_within_grace() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    server.pairing.lock().unwrap().set_reconnect_grace(Duration::from_millis(300));
+
+    let (mut ws, _) = connect(server.port).await;
+    let reply = request(&mut ws, json!({"cmd":"pair","code":CODE,"device_id":"phone-8"})).await;
+    let token = reply["token"].as_str().unwrap().to_string();
+    let auth = json!({"cmd":"auth","device_id":"phone-8","token":token});
+
+    // reconnect inside the window: the session is still held for this device
+    drop(ws);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let (mut ws, hello) = connect(server.port).await;
+    assert_eq!(hello["active_device_id"], "phone-8");
+    let reply = request(&mut ws, auth.clone()).await;
+    assert_eq!(reply["type"], "auth_ok");
+    assert_eq!(reply["resumed"], true);
+
+    // reconnect after it: the token still works, but as a fresh session
+    drop(ws);
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let (mut ws, _) = connect(server.port).await;
+    let reply = request(&mut ws, auth).await;
+    assert_eq!(reply["type"], "auth_ok");
+    assert_eq!(reply["resumed"], false);
+}
+
+// Plain HTTP/1.1 GET; returns the raw response (head and body)
+async fn http_get(port: u16, path: &'static str) -> String {
+    tokio::task::spawn_blocking(move || {
+        use std::io::{Read, Write};
+        let mut s = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(s, "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        s.read_to_string(&mut resp).unwrap();
+        resp
+    })
+    .await
+    .unwrap()
+}
+
+async fn health(port: u16) -> Value {
+    let resp = http_get(port, "/health?verbose=1").await;
+    serde_json::from_str(resp.split_once("\r\n\r\n").unwrap().1).unwrap()
+}
+
+#[tokio::test]
+async fn health_reports_command_metrics() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-9").await;
+
+    request(&mut ws, json!({"cmd":"get_status"})).await;
+    request(&mut ws, json!({"cmd":"get_status"})).await;
+    request(&mut ws, json!({"cmd":"apply_preset","name":"missing"})).await;
+
+    let metrics = health(server.port).await["metrics"].clone();
+    assert_eq!(metrics["commands"]["get_status"], json!({"calls":2,"errors":0}));
+    assert_eq!(metrics["commands"]["apply_preset"], json!({"calls":1,"errors":1}));
+    assert_eq!(metrics["command_errors"], 1);
+    assert_eq!(metrics["connections_total"], 1);
+    assert_eq!(metrics["pairing_attempts"], 1);
+}
+
+#[tokio::test]
+async fn silent_connection_is_dropped_but_pong_keeps_it_alive() {
+    let config = Config { read_timeout_secs: 1, ..Config::default() };
+    let server = start_server_with(MockAudio::new(0.5, false, false), config);
+
+    // never polled, so the server's pings go unanswered
+    let (mut silent, _) = connect(server.port).await;
+    // polled, so tungstenite answers each ping with a pong
+    let (mut alive, _) = connect(server.port).await;
+
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(2500);
+    while let Ok(frame) = tokio::time::timeout_at(deadline, alive.next()).await {
+        assert!(matches!(frame, Some(Ok(Message::Ping(_)))), "unexpected {frame:?}");
+    }
+    let reply = request(&mut alive, json!({"cmd":"pair","code":CODE,"device_id":"phone-10"})).await;
+    assert_eq!(reply["type"], "pairing_ok");
+
+    let ended = tokio::time::timeout(Duration::from_secs(1), async {
+        while let Some(Ok(Message::Ping(_))) = silent.next().await {}
+    })
+    .await;
+    assert!(ended.is_ok(), "silent connection was never dropped");
+}
+
+#[tokio::test]
+async fn pong_reports_stats_and_heartbeat_follows_keepalive() {
+    let config = Config { read_timeout_secs: 3, ..Config::default() };
+    let server = start_server_with(MockAudio::new(0.5, false, false), config);
+    let (_other, _) = connect(server.port).await;
+    let (mut ws, hello) = connect(server.port).await;
+    assert_eq!(hello["keepalive_secs"], json!(1.0));
+    let reply = request(&mut ws, json!({"cmd":"pair","code":CODE,"device_id":"phone-1"})).await;
+    assert_eq!(reply["type"], "pairing_ok");
+
+    let reply = request(&mut ws, json!({"cmd":"ping","nonce":"n1"})).await;
+    assert_eq!(reply["type"], "pong");
+    assert_eq!(reply["active_clients"], 2);
+    assert!(reply["server_uptime_secs"].is_u64());
+
+    let reply = request(&mut ws, json!({"cmd":"subscribe","topics":["heartbeat"]})).await;
+    assert_eq!(reply["topics"], json!(["devices", "heartbeat", "media", "volume"]));
+    let beat = recv(&mut ws).await;
+    assert_eq!(beat["type"], "heartbeat");
+    assert!(beat["ts"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn heartbeat_only_touches_its_own_device_in_memory() {
+    let config = Config { read_timeout_secs: 3, ..Config::default() };
+    let server = start_server_with(MockAudio::new(0.5, false, false), config);
+    let mut ws = paired_client(server.port, "phone-41").await;
+    request(&mut ws, json!({"cmd":"subscribe","topics":["heartbeat"]})).await;
+
+    let store = server.store_dir.join("authorized.json");
+    let saved = std::fs::read(&store).unwrap();
+    let paired_at = server.pairing.lock().unwrap().authorized_device("phone-41").unwrap().last_seen;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(recv(&mut ws).await["type"], "heartbeat");
+    assert_eq!(recv(&mut ws).await["type"], "heartbeat");
+    assert!(server.pairing.lock().unwrap().authorized_device("phone-41").unwrap().last_seen > paired_at);
+    assert_eq!(std::fs::read(&store).unwrap(), saved, "heartbeats must not rewrite authorized.json");
+
+    // another device holding the session keeps its own clock
+    let held_since = {
+        let mut st = server.pairing.lock().unwrap();
+        st.active_device_id = Some("phone-other".into());
+        st.last_seen
+    };
+    assert_eq!(recv(&mut ws).await["type"], "heartbeat");
+    let st = server.pairing.lock().unwrap();
+    assert_eq!(st.active_device_id.as_deref(), Some("phone-other"));
+    assert_eq!(st.last_seen, held_since);
+}
+
+#[tokio::test]
+async fn request_ack_sends_ack_before_reply() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-11").await;
+
+    let ack = request(&mut ws, json!({"cmd":"set_volume","level":0.25,"request_ack":true})).await;
+    assert_eq!(ack, json!({"type":"ack","action":"set_volume","ok":true}));
+    let reply = recv(&mut ws).await;
+    assert_eq!(reply["action"], "set_volume");
+    assert_eq!(reply["volume"], json!(0.25));
+
+    // off by default, and never for read-only commands
+    let reply = request(&mut ws, json!({"cmd":"set_volume","level":0.4})).await;
+    assert_eq!(reply["action"], "set_volume");
+    let reply = request(&mut ws, json!({"cmd":"get_status","request_ack":true})).await;
+    assert_eq!(reply["type"], "status");
+}
+
+#[tokio::test]
+async fn communications_default_switches_separately() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-12").await;
+
+    let reply = request(&mut ws, json!({"cmd":"set_output_device","id":"spk-9"})).await;
+    assert_eq!(reply["code"], "not_found");
+
+    let reply = request(&mut ws, json!({"cmd":"set_output_device","id":"spk-2","role":"communications"})).await;
+    assert_eq!(reply["type"], "ok");
+
+    let reply = request(&mut ws, json!({"cmd":"list_output_devices"})).await;
+    let devices = reply["devices"].as_array().unwrap();
+    assert_eq!(devices[0]["is_default"], true);
+    assert_eq!(devices[0]["is_default_comms"], false);
+    assert_eq!(devices[1]["is_default"], false);
+    assert_eq!(devices[1]["is_default_comms"], true);
+}
+
+#[tokio::test]
+async fn device_state_reports_format_and_exclusive_use() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-14").await;
+
+    let reply = request(&mut ws, json!({"cmd":"get_device_state","id":"spk-2"})).await;
+    assert_eq!(reply["type"], "device_state");
+    assert_eq!(reply["device"]["sample_rate"], 48_000);
+    assert_eq!(reply["device"]["bits_per_sample"], 24);
+    assert_eq!(reply["device"]["exclusive_in_use"], true);
+
+    let reply = request(&mut ws, json!({"cmd":"get_device_state","id":"mic-1"})).await;
+    assert_eq!(reply["device"]["exclusive_in_use"], false);
+
+    let reply = request(&mut ws, json!({"cmd":"get_device_state","id":"spk-9"})).await;
+    assert_eq!(reply["code"], "not_found");
+}
+
+#[tokio::test]
+async fn client_meta_is_echoed_in_status_replies() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let (mut ws, _) = connect(server.port).await;
+
+    let meta = json!({"layout": "deck-4x3", "rev": 7});
+    let pair = json!({"cmd":"pair","code":CODE,"device_id":"phone-15","client_meta":meta});
+    assert_eq!(request(&mut ws, pair).await["type"], "pairing_ok");
+
+    let reply = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(reply["client_meta"], meta);
+    let reply = request(&mut ws, json!({"cmd":"get_full_state"})).await;
+    assert_eq!(reply["client_meta"], meta);
+
+    let huge = json!({"blob": "x".repeat(2048)});
+    let (mut ws, _) = connect(server.port).await;
+    let reply = request(&mut ws, json!({"cmd":"auth","device_id":"phone-15","token":"t","client_meta":huge})).await;
+    assert_eq!(reply["type"], "auth_error");
+    assert_eq!(reply["code"], "bad_request");
+}
+
+#[tokio::test]
+async fn disabled_categories_are_refused_and_not_advertised() {
+    let config = Config { disabled_categories: vec!["system".into()], ..Config::default() };
+    let server = start_server_with(MockAudio::new(0.5, false, false), config);
+
+    let (mut ws, hello) = connect(server.port).await;
+    let caps = hello["capabilities"].as_array().unwrap();
+    assert!(caps.iter().all(|c| c != "take_screenshot" && c != "open_path"));
+
+    let reply = request(&mut ws, json!({"cmd":"pair","code":CODE,"device_id":"phone-16"})).await;
+    assert_eq!(reply["type"], "pairing_ok");
+    let reply = request(&mut ws, json!({"cmd":"take_screenshot"})).await;
+    assert_eq!(reply["code"], "disabled");
+    let reply = request(&mut ws, json!({"cmd":"set_volume","level":0.3})).await;
+    assert_eq!(reply["type"], "ok");
+}
+
+#[tokio::test]
+async fn device_can_rename_itself() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-17").await;
+
+    let reply = request(&mut ws, json!({"cmd":"rename_device","name":"  Kitchen   tablet "})).await;
+    assert_eq!(reply["type"], "device_renamed");
+    assert_eq!(reply["device_id"], "phone-17");
+    assert_eq!(reply["name"], "Kitchen tablet");
+    let stored = server.pairing.lock().unwrap().authorized_device("phone-17").unwrap().name.clone();
+    assert_eq!(stored.as_deref(), Some("Kitchen tablet"));
+
+    let reply = request(&mut ws, json!({"cmd":"rename_device","name":"   "})).await;
+    assert_eq!(reply["code"], "bad_request");
+    let reply = request(&mut ws, json!({"cmd":"rename_device","name":"x".repeat(65)})).await;
+    assert_eq!(reply["code"], "bad_request");
+}
+
+#[tokio::test]
+async fn group_volume_skips_unplugged_members() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-18").await;
+
+    let group = json!({"cmd":"create_group","name":"downstairs","members":["spk-1","usb-dac","spk-2"]});
+    let reply = request(&mut ws, group).await;
+    assert_eq!(reply["groups"], json!([{"name":"downstairs","members":["spk-1","usb-dac","spk-2"]}]));
+
+    let reply = request(&mut ws, json!({"cmd":"set_group_volume","group":"downstairs","level":0.4})).await;
+    assert_eq!(reply["adjusted"], json!(["spk-1","spk-2"]));
+    assert_eq!(reply["skipped"], json!(["usb-dac"]));
+
+    let reply = request(&mut ws, json!({"cmd":"set_group_volume","group":"upstairs","level":0.4})).await;
+    assert_eq!(reply["code"], "not_found");
+
+    request(&mut ws, json!({"cmd":"delete_group","name":"downstairs"})).await;
+    let reply = request(&mut ws, json!({"cmd":"list_groups"})).await;
+    assert_eq!(reply["groups"], json!([]));
+}
+
+#[tokio::test]
+async fn pairing_status_reveals_the_code_only_to_admins() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-19").await;
+
+    let reply = request(&mut ws, json!({"cmd":"get_pairing_status"})).await;
+    assert_eq!(reply["type"], "pairing_status");
+    assert_eq!(reply["code_active"], true);
+    assert_eq!(reply["authorized_count"], 1);
+    assert!(reply["ttl_remaining_secs"].as_u64().unwrap() <= 300);
+    assert!(reply.get("code").is_none());
+
+    let mut scopes = crate::server::auth_store::default_scopes();
+    scopes.push("admin".into());
+    server.pairing.lock().unwrap().set_scopes("phone-19", scopes);
+    let reply = request(&mut ws, json!({"cmd":"get_pairing_status"})).await;
+    assert_eq!(reply["code"], CODE);
+}
+
+#[tokio::test]
+async fn control_page_is_served_and_may_connect_back() {
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let server = start_server(MockAudio::new(0.5, false, false));
+    connect(server.port).await;
+
+    let page = http_get(server.port, "/control").await;
+    assert!(page.starts_with("HTTP/1.1 200"));
+    assert!(page.contains("/ws`"));
+
+    // a LAN browser loads the page from the PC's address, so its Origin matches Host
+    if let Some((_, ip)) = crate::discovery::list_interfaces().first() {
+        let origin = format!("{ip}:{}", server.port);
+        let mut req = format!("ws://127.0.0.1:{}/ws", server.port).into_client_request().unwrap();
+        req.headers_mut().insert("host", origin.parse().unwrap());
+        req.headers_mut().insert("origin", format!("http://{origin}").parse().unwrap());
+        assert!(connect_async(req).await.is_ok());
+    }
+
+    // a DNS-rebinding page: Host and Origin agree, but name someone else
+    let foreign = format!("evil.example:{}", server.port);
+    let mut req = format!("ws://127.0.0.1:{}/ws", server.port).into_client_request().unwrap();
+    req.headers_mut().insert("host", foreign.parse().unwrap());
+    req.headers_mut().insert("origin", format!("http://{foreign}").parse().unwrap());
+    match connect_async(req).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(resp)) => assert_eq!(resp.status(), 403),
+        other => panic!("expected 403, got {other:?}"),
+    }
+}
+
+#[test]
+fn same_origin_upgrades_must_name_this_server() {
+    use crate::server::ws::origin_allowed;
+    let own = ["192.168.1.5".parse().unwrap(), "127.0.0.1".parse().unwrap()];
+    let allowed = |origin: &str, host: &str| origin_allowed(Some(origin), Some(host), &[], &own, 3030);
+    assert!(allowed("http://192.168.1.5:3030", "192.168.1.5:3030"));
+    assert!(!allowed("http://evil.example:3030", "evil.example:3030"));
+    assert!(!allowed("http://10.0.0.9:3030", "10.0.0.9:3030"));
+    assert!(!allowed("http://192.168.1.5:8080", "192.168.1.5:8080"));
+    assert!(!allowed("http://192.168.1.5", "192.168.1.5"));
+    // a configured LAN name goes through allowed_origins
+    let names = ["http://deck.lan:3030".to_string()];
+    assert!(origin_allowed(Some("http://deck.lan:3030"), Some("deck.lan:3030"), &names, &own, 3030));
+}
+
+#[tokio::test]
+async fn minimal_verbosity_skips_status_in_replies() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-13").await;
+
+    let reply = request(&mut ws, json!({"cmd":"set_verbosity","full_status":false})).await;
+    assert_eq!(reply["full_status"], false);
+    let reply = request(&mut ws, json!({"cmd":"set_volume","level":0.75})).await;
+    assert_eq!(reply, json!({"type":"ok","action":"set_volume"}));
+
+    // the change still happened, and queries are unaffected
+    let reply = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(reply["volume"], json!(0.75));
+
+    request(&mut ws, json!({"cmd":"set_verbosity","full_status":true})).await;
+    let reply = request(&mut ws, json!({"cmd":"mute"})).await;
+    assert_eq!(reply["muted"], true);
+}
+
+
+#[tokio::test]
+async fn taken_port_fails_or_falls_back_to_a_free_one() {
+    let taken = TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let store_dir = std::env::temp_dir().join(format!("fossdeck-test-{}-bind-{port}", std::process::id()));
+    let start = |config: Config| {
+        let (shutdown, rx) = oneshot::channel();
+        let pairing = Arc::new(Mutex::new(PairingState::with_store_path(
+            CODE.to_string(),
+            store_dir.join("authorized.json"),
+        )));
+        let identity = test_identity();
+        let presets = PresetStore::load(store_dir.join("presets.json"));
+        let groups = GroupStore::load(store_dir.join("groups.json"));
+        let launchers = LauncherStore::load(store_dir.join("launchers.json"));
+        let audio = Arc::new(MockAudio::new(0.5, false, false));
+        let config = Arc::new(RwLock::new(config));
+        let server = run_ws_server(
+            port,
+            rx,
+            pairing,
+            identity,
+            config,
+            audio,
+            presets,
+            groups,
+            launchers,
+            Default::default(),
+            Default::default(),
+        );
+        (shutdown, server)
+    };
+
+    let (_shutdown, bound) = start(Config::default());
+    assert!(bound.await.is_err());
+
+    let config = Config { fallback_to_free_port: true, ..Config::default() };
+    let (_shutdown, bound) = start(config);
+    let addr = bound.await.unwrap();
+    assert_ne!(addr.port(), port);
+    connect(addr.port()).await;
+
+    let _ = std::fs::remove_dir_all(&store_dir);
+}
+
+#[test]
+fn com_is_released_when_audio_threads_exit() {
+    let before = crate::audio::com_thread_count();
+
+    let threads: Vec<_> = (0..500)
+        .map(|_| {
+            std::thread::spawn(|| {
+                for _ in 0..4 {
+                    // may fail on a machine without audio; COM is initialized either way
+                    let _ = crate::audio::default_output_device();
+                }
+                assert!(crate::audio::com_thread_count() > 0);
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert!(crate::audio::com_thread_count() <= before);
+}
+
+#[test]
+fn volume_calls_reuse_the_thread_enumerator() {
+    std::thread::spawn(|| {
+        for i in 0..50 {
+            // may fail on a machine without audio; the enumerator is created either way
+            if let Ok((level, _)) = crate::audio::get_volume_and_mute() {
+                let _ = crate::audio::set_volume(level);
+            }
+            assert_eq!(crate::audio::enumerators_created_on_this_thread(), 1, "call {i}");
+        }
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn pairing_codes_follow_the_configured_format() {
+    use crate::server::generate_pairing_code;
+    use crate::server::pairing::{CodeCharset, CodeFormat};
+
+    let default = generate_pairing_code(CodeFormat::default());
+    assert_eq!(default.len(), 6);
+    assert!(default.chars().all(|c| c.is_ascii_digit()));
+
+    for length in 6..=10 {
+        let numeric = generate_pairing_code(CodeFormat { length, charset: CodeCharset::Numeric });
+        assert_eq!(numeric.len(), length);
+        assert!(numeric.chars().all(|c| c.is_ascii_digit()));
+
+        let alnum = generate_pairing_code(CodeFormat { length, charset: CodeCharset::Alphanumeric });
+        assert_eq!(alnum.len(), length);
+        assert!(alnum.chars().all(|c| c.is_ascii_digit() || c.is_ascii_uppercase()));
+    }
+
+    // out-of-range lengths are clamped rather than producing weak or unwieldy codes
+    assert_eq!(generate_pairing_code(CodeFormat { length: 2, charset: CodeCharset::Numeric }).len(), 6);
+    assert_eq!(generate_pairing_code(CodeFormat { length: 40, charset: CodeCharset::Numeric }).len(), 10);
+}
+
+#[tokio::test]
+async fn alphanumeric_codes_pair_case_insensitively() {
+    use crate::server::pairing::{CodeCharset, CodeFormat};
+
+    let server = start_server(MockAudio::new(0.5, false, false));
+    server
+        .pairing
+        .lock()
+        .unwrap()
+        .set_code_format(CodeFormat { length: 8, charset: CodeCharset::Alphanumeric });
+
+    let (mut ws, _) = connect(server.port).await;
+    let code = server.pairing.lock().unwrap().code.clone();
+    assert_eq!(code.len(), 8);
+
+    let pair = json!({"cmd":"pair","code":code.to_ascii_lowercase(),"device_id":"phone-20"});
+    assert_eq!(request(&mut ws, pair).await["type"], "pairing_ok");
+}
+
+#[tokio::test]
+async fn render_role_selects_console_or_communications() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-21").await;
+
+    let resp = request(&mut ws, json!({"cmd":"mute","role":"communications"})).await;
+    assert_eq!(resp["role"], "communications");
+    assert_eq!(resp["muted"], true);
+
+    // omitting the role keeps acting on the console device only
+    let resp = request(&mut ws, json!({"cmd":"set_volume","level":0.7})).await;
+    assert_eq!(resp["muted"], false);
+    assert!(resp.get("role").is_none());
+
+    let status = request(&mut ws, json!({"cmd":"get_status","role":"all"})).await;
+    assert_eq!(status["roles"]["console"], json!({"volume":0.7f32,"muted":false}));
+    assert_eq!(status["roles"]["communications"], json!({"volume":0.3f32,"muted":true}));
+
+    // toggling both follows the console state, leaving them in sync
+    let resp = request(&mut ws, json!({"cmd":"toggle_mute","role":"all"})).await;
+    assert_eq!(resp["roles"]["console"]["muted"], true);
+    assert_eq!(resp["roles"]["communications"]["muted"], true);
+
+    let resp = request(&mut ws, json!({"cmd":"set_volume","level":0.2,"role":"all"})).await;
+    assert_eq!(resp["roles"]["console"]["volume"], 0.2f32);
+    assert_eq!(resp["roles"]["communications"]["volume"], 0.2f32);
+}
+
+#[tokio::test]
+async fn volume_fade_reports_the_target_and_is_cancelled_by_a_new_set_volume() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-22").await;
+
+    let resp = request(&mut ws, json!({"cmd":"set_volume","level":0.0,"fade_ms":100})).await;
+    assert_eq!(resp["volume"], 0.0);
+    assert_eq!(resp["fade_ms"], 100);
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(status["volume"], 0.0);
+
+    // a long fade down, overtaken by an immediate set
+    request(&mut ws, json!({"cmd":"set_volume","level":1.0,"fade_ms":2000})).await;
+    let resp = request(&mut ws, json!({"cmd":"set_volume","level":0.4})).await;
+    assert_eq!(resp["volume"], 0.4f32);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(status["volume"], 0.4f32);
+}
+
+#[tokio::test]
+async fn a_second_communications_fade_cancels_the_first() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-42").await;
+
+    request(&mut ws, json!({"cmd":"set_volume","level":1.0,"role":"communications","fade_ms":2000})).await;
+    request(&mut ws, json!({"cmd":"set_volume","level":0.2,"role":"communications","fade_ms":100})).await;
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let status = request(&mut ws, json!({"cmd":"get_status","role":"all"})).await;
+    assert_eq!(status["roles"]["communications"]["volume"], 0.2f32);
+}
+
+#[tokio::test]
+async fn mute_app_needs_exactly_one_target() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-23").await;
+
+    for cmd in [
+        json!({"cmd":"mute_app","mute":true}),
+        json!({"cmd":"mute_app","session_id":"s","process_name":"discord","mute":true}),
+    ] {
+        let resp = request(&mut ws, cmd).await;
+        assert_eq!(resp["type"], "error");
+        assert_eq!(resp["code"], "bad_request");
+    }
+}
+
+#[tokio::test]
+async fn default_capture_device_switches_only_to_active_mics() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-24").await;
+
+    let list = request(&mut ws, json!({"cmd":"list_capture_devices"})).await;
+    assert_eq!(list["type"], "capture_devices");
+    assert_eq!(list["devices"][0]["is_default"], true);
+    assert_eq!(list["devices"][2]["state"], "disabled");
+
+    let resp = request(&mut ws, json!({"cmd":"set_default_capture_device","device_id":"mic-2"})).await;
+    assert_eq!(resp["type"], "ok");
+    let list = request(&mut ws, json!({"cmd":"list_capture_devices"})).await;
+    assert_eq!(list["devices"][1]["is_default"], true);
+
+    let resp = request(&mut ws, json!({"cmd":"set_default_capture_device","device_id":"mic-3"})).await;
+    assert_eq!(resp["code"], "device_unavailable");
+    assert_eq!(resp["state"], "disabled");
+
+    let resp = request(&mut ws, json!({"cmd":"set_default_capture_device","device_id":"mic-9"})).await;
+    assert_eq!(resp["code"], "not_found");
+}
+
+#[tokio::test]
+async fn status_names_the_devices_and_tolerates_no_microphone() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-25").await;
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(status["input_device"], "Mic mic-1");
+    assert_eq!(status["mic_muted"], false);
+    assert_eq!(status["mic_volume"], 0.8);
+    assert_eq!(status["has_microphone"], true);
+    assert!(status["output_device"].is_null());
+
+    let audio = MockAudio::new(0.5, false, false);
+    *audio.default_capture.lock().unwrap() = None;
+    let server = start_server(audio);
+    let mut ws = paired_client(server.port, "phone-25").await;
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(status["type"], "status");
+    assert!(status["input_device"].is_null());
+    assert!(status["mic_muted"].is_null());
+    assert!(status["mic_volume"].is_null());
+    assert_eq!(status["has_microphone"], false);
+    let state = request(&mut ws, json!({"cmd":"get_full_state"})).await;
+    assert_eq!(state["has_microphone"], false);
+}
+
+#[test]
+fn toggle_mic_mute_inverts_and_reports_the_new_state() {
+    use crate::server::commands::toggle_mic_mute;
+
+    let audio = MockAudio::new(0.5, false, false);
+    assert!(toggle_mic_mute(&audio, None).unwrap());
+    assert!(audio.state.lock().unwrap().2);
+    assert!(!toggle_mic_mute(&audio, Some("mic-1")).unwrap());
+
+    // unplugged mid-session: an error, and the stored state is left alone
+    *audio.default_capture.lock().unwrap() = None;
+    let err = toggle_mic_mute(&audio, None).unwrap_err();
+    assert!(err.to_string().contains("microphone"));
+    assert!(!audio.state.lock().unwrap().2);
+}
+
+#[tokio::test]
+async fn balance_survives_volume_changes_and_mono_is_unsupported() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-26").await;
+
+    let resp = request(&mut ws, json!({"cmd":"set_balance","value":-3.0})).await;
+    assert_eq!(resp["value"], -1.0);
+    let resp = request(&mut ws, json!({"cmd":"set_balance","value":0.25})).await;
+    assert_eq!(resp["type"], "ok");
+    request(&mut ws, json!({"cmd":"set_volume","level":0.9})).await;
+    let resp = request(&mut ws, json!({"cmd":"get_balance"})).await;
+    assert_eq!(resp, json!({"type":"balance","supported":true,"value":0.25}));
+
+    let audio = MockAudio::new(0.5, false, false);
+    *audio.balance.lock().unwrap() = None;
+    let server = start_server(audio);
+    let mut ws = paired_client(server.port, "phone-26").await;
+    let resp = request(&mut ws, json!({"cmd":"get_balance"})).await;
+    assert_eq!(resp["supported"], false);
+    let resp = request(&mut ws, json!({"cmd":"set_balance","value":0.5})).await;
+    assert_eq!(resp["code"], "not_supported");
+}
+
+#[test]
+fn balance_is_read_back_from_channel_levels() {
+    use crate::audio::balance_from_levels;
+
+    assert_eq!(balance_from_levels(1.0, 1.0), 0.0);
+    assert_eq!(balance_from_levels(0.5, 1.0), 0.5);
+    assert_eq!(balance_from_levels(0.5, 0.125), -0.75);
+    assert_eq!(balance_from_levels(0.0, 0.8), 1.0);
+    assert_eq!(balance_from_levels(0.0, 0.0), 0.0);
+}
+
+#[tokio::test]
+async fn set_volume_db_clamps_and_reports_both_scales() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-27").await;
+
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(status["volume_db"], -30.0);
+    assert_eq!(status["volume_range_db"], json!([-60.0, 0.0]));
+
+    let resp = request(&mut ws, json!({"cmd":"set_volume_db","db":-15.0})).await;
+    assert_eq!(resp["volume_db"], -15.0);
+    assert_eq!(resp["volume"], 0.75);
+
+    let resp = request(&mut ws, json!({"cmd":"set_volume_db","db":12.0})).await;
+    assert_eq!(resp["volume_db"], 0.0);
+    assert_eq!(resp["volume"], 1.0);
+
+    request(&mut ws, json!({"cmd":"set_verbosity","full_status":false})).await;
+    let resp = request(&mut ws, json!({"cmd":"set_volume_db","db":-90.0})).await;
+    assert_eq!(resp["volume_db"], -60.0);
+    assert_eq!(resp["volume"], 0.0);
+}
+
+#[tokio::test]
+async fn volume_steps_multiply_the_configured_step() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    server.config.write().unwrap().volume_step = 0.125;
+    let mut ws = paired_client(server.port, "phone-28").await;
+
+    let resp = request(&mut ws, json!({"cmd":"volume_up","steps":2})).await;
+    assert_eq!(resp["volume"], 0.75);
+    let resp = request(&mut ws, json!({"cmd":"volume_down","delta":0.25,"steps":3})).await;
+    assert_eq!(resp["volume"], 0.0);
+    let resp = request(&mut ws, json!({"cmd":"volume_up","steps":1000})).await;
+    assert_eq!(resp["volume"], 1.0);
+}
+
+#[tokio::test]
+async fn mute_app_matches_process_names_case_insensitively() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-29").await;
+
+    let resp = request(&mut ws, json!({"cmd":"mute_app","process_name":"discord.EXE","mute":true})).await;
+    assert_eq!(resp["type"], "ok");
+    assert_eq!(resp["sessions"][0]["session_id"], "s-discord");
+    assert_eq!(resp["sessions"].as_array().unwrap().len(), 1);
+
+    let resp = request(&mut ws, json!({"cmd":"mute_app","process_name":"zoom","mute":true})).await;
+    assert_eq!(resp["code"], "no_matching_session");
+}
+
+#[test]
+fn audio_calls_share_one_thread_that_survives_a_panicking_request() {
+    use crate::audio::on_audio_thread;
+
+    let name = || on_audio_thread(|| Ok(std::thread::current().id()));
+    let first = name().unwrap();
+    let second = std::thread::spawn(name).join().unwrap().unwrap();
+    assert_eq!(first, second);
+    assert_ne!(first, std::thread::current().id());
+
+    // nested calls from the audio thread run inline instead of deadlocking
+    assert_eq!(on_audio_thread(name).unwrap(), first);
+
+    let err = on_audio_thread::<()>(|| panic!("boom")).unwrap_err();
+    assert!(err.to_string().contains("audio thread stopped"));
+    assert!(name().is_ok());
+}
+
+#[tokio::test]
+async fn system_sounds_are_set_now_or_left_pending() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-30").await;
+
+    let resp = request(&mut ws, json!({"cmd":"set_system_sounds","level":1.5})).await;
+    assert_eq!(resp["pending"], false);
+    assert_eq!(resp["volume"], 1.0);
+    let resp = request(&mut ws, json!({"cmd":"set_system_sounds","mute":true})).await;
+    assert_eq!(resp["muted"], true);
+    assert_eq!(resp["volume"], 1.0);
+    let resp = request(&mut ws, json!({"cmd":"set_system_sounds"})).await;
+    assert_eq!(resp["code"], "bad_request");
+
+    let audio = MockAudio::new(0.5, false, false);
+    *audio.system_sounds.lock().unwrap() = None;
+    let server = start_server(audio);
+    let mut ws = paired_client(server.port, "phone-30").await;
+    let resp = request(&mut ws, json!({"cmd":"get_system_sounds"})).await;
+    assert_eq!(resp["available"], false);
+    let resp = request(&mut ws, json!({"cmd":"set_system_sounds","level":0.25,"mute":false})).await;
+    assert_eq!(resp["type"], "ok");
+    assert_eq!(resp["pending"], true);
+    assert_eq!(resp["volume"], 0.25);
+}
+
+#[tokio::test]
+async fn list_audio_sessions_reports_state_per_session() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-31").await;
+
+    request(&mut ws, json!({"cmd":"mute_app","session_id":"s-spotify","mute":true})).await;
+    let resp = request(&mut ws, json!({"cmd":"list_audio_sessions"})).await;
+    assert_eq!(resp["type"], "audio_sessions");
+    let sessions = resp["sessions"].as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0]["process_name"], "Discord");
+    assert_eq!(sessions[0]["state"], "active");
+    assert_eq!(sessions[1]["state"], "inactive");
+    assert_eq!(sessions[1]["muted"], true);
+}
+
+#[tokio::test]
+async fn panic_mute_holds_the_mic_muted_across_disconnects() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-32").await;
+
+    let resp = request(&mut ws, json!({"cmd":"panic_mute","enabled":true})).await;
+    assert_eq!(resp["mic_muted"], true);
+    let resp = request(&mut ws, json!({"cmd":"toggle_mic_mute"})).await;
+    assert_eq!(resp["code"], "panic_mute_active");
+    drop(ws);
+
+    // something else (Teams, Windows) unmutes the mic
+    server.audio.state.lock().unwrap().2 = false;
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(server.audio.state.lock().unwrap().2);
+
+    let mut ws = paired_client(server.port, "phone-32").await;
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(status["panic_mute"], true);
+
+    request(&mut ws, json!({"cmd":"panic_mute","enabled":false})).await;
+    let resp = request(&mut ws, json!({"cmd":"toggle_mic_mute"})).await;
+    assert_eq!(resp["mic_muted"], false);
+}
+
+#[tokio::test]
+async fn panic_mute_stays_off_when_the_mic_cannot_be_muted() {
+    let audio = MockAudio::new(0.5, false, false);
+    *audio.default_capture.lock().unwrap() = None;
+    let server = start_server(audio);
+    let mut ws = paired_client(server.port, "phone-43").await;
+
+    let resp = request(&mut ws, json!({"cmd":"panic_mute","enabled":true})).await;
+    assert_eq!(resp["type"], "error");
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(status["panic_mute"], false);
+}
+
+#[tokio::test]
+async fn volume_commands_can_target_an_endpoint_by_id() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-33").await;
+
+    let list = request(&mut ws, json!({"cmd":"list_audio_devices"})).await;
+    let ids: Vec<_> = list["devices"].as_array().unwrap().iter().map(|d| d["id"].clone()).collect();
+    assert_eq!(ids, [json!("spk-1"), json!("spk-2"), json!("mic-1")]);
+    assert_eq!(list["devices"][2]["flow"], "capture");
+
+    let resp = request(&mut ws, json!({"cmd":"set_volume","level":0.25,"device_id":"spk-2"})).await;
+    assert_eq!(resp["device_id"], "spk-2");
+    assert_eq!(resp["volume"], 0.25);
+    let resp = request(&mut ws, json!({"cmd":"toggle_mute","device_id":"spk-2"})).await;
+    assert_eq!(resp["muted"], true);
+
+    // the default device is untouched
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(status["volume"], 0.5);
+    assert_eq!(status["muted"], false);
+    let status = request(&mut ws, json!({"cmd":"get_status","device_id":"spk-2"})).await;
+    assert_eq!(status["volume"], 0.25);
+    assert_eq!(status["muted"], true);
+
+    let resp = request(&mut ws, json!({"cmd":"mute","device_id":"spk-9"})).await;
+    assert_eq!(resp["code"], "device_not_found");
+    let resp = request(&mut ws, json!({"cmd":"set_volume","level":0.5,"device_id":"spk-1","role":"all"})).await;
+    assert_eq!(resp["code"], "bad_request");
+}
+
+#[tokio::test]
+async fn audio_preset_applies_every_entry_and_reports_each() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-34").await;
+
+    let resp = request(
+        &mut ws,
+        json!({"cmd":"apply_audio_preset","master":0.75,"entries":[
+            {"process_name":"discord.exe","level":0.5},
+            {"process_name":"Game","level":1.0},
+            {"session_id":"s-spotify","mute":true},
+        ]}),
+    )
+    .await;
+    assert_eq!(resp["action"], "apply_audio_preset");
+    assert_eq!(resp["volume"], 0.75);
+    let results = resp["results"].as_array().unwrap();
+    assert_eq!(results[0]["result"], "applied");
+    assert_eq!(results[0]["sessions"][0]["volume"], 0.5);
+    // a missing app doesn't stop the entries after it
+    assert_eq!(results[1]["result"], "not_found");
+    assert_eq!(results[1]["process_name"], "Game");
+    assert_eq!(results[2]["result"], "applied");
+    assert_eq!(results[2]["sessions"][0]["muted"], true);
+
+    // an entry with no target fails on its own; the rest still apply
+    let resp = request(
+        &mut ws,
+        json!({"cmd":"apply_audio_preset","entries":[
+            {"level":0.5},
+            {"session_id":"s-spotify","process_name":"Spotify","level":0.5},
+            {"process_name":"Discord","level":0.4},
+        ]}),
+    )
+    .await;
+    assert_eq!(resp["type"], "ok");
+    let results = resp["results"].as_array().unwrap();
+    assert_eq!(results[0]["result"], "failed");
+    assert_eq!(results[0]["code"], "bad_request");
+    assert_eq!(results[1]["result"], "failed");
+    assert_eq!(results[1]["code"], "bad_request");
+    assert_eq!(results[2]["result"], "applied");
+    assert_eq!(results[2]["sessions"][0]["volume"], 0.4);
+}
+
+#[test]
+fn seek_positions_clamp_to_the_track() {
+    use crate::media::clamp_position_ms;
+    assert_eq!(clamp_position_ms(90_000, 60_000), 60_000);
+    assert_eq!(clamp_position_ms(-5, 60_000), 0);
+    assert_eq!(clamp_position_ms(30_000, 60_000), 30_000);
+    // no known length
+    assert_eq!(clamp_position_ms(90_000, 0), 90_000);
+}
+
+#[test]
+fn connection_commands_parse_apart_from_device_commands() {
+    use crate::server::commands::{ConnectionCommand, WsCommand};
+    let cmd = WsCommand::parse(r#"{"cmd":"whoami"}"#).unwrap();
+    assert!(matches!(cmd, WsCommand::Connection(ConnectionCommand::WhoAmI)));
+    let cmd = WsCommand::parse(r#"{"cmd":"subscribe","topics":["volume"]}"#).unwrap();
+    assert!(matches!(cmd, WsCommand::Connection(ConnectionCommand::Subscribe { .. })));
+    assert!(matches!(WsCommand::parse(r#"{"cmd":"get_status"}"#).unwrap(), WsCommand::Control(_)));
+    // field errors are reported against the connection command, not as an unknown device command
+    let e = WsCommand::parse(r#"{"cmd":"subscribe_peaks"}"#).unwrap_err();
+    assert!(e.to_string().contains("interval_ms"), "{e}");
+}
+
+#[test]
+fn media_commands_take_an_optional_session_target() {
+    use crate::server::commands::ControlCommand;
+    // the bare form keeps meaning the global media key
+    let cmd: ControlCommand = serde_json::from_value(json!({"cmd":"next_track"})).unwrap();
+    assert!(matches!(cmd, ControlCommand::NextTrack { session_id: None, app_id: None }));
+    let cmd: ControlCommand = serde_json::from_value(json!({"cmd":"stop","app_id":"Spotify.exe"})).unwrap();
+    assert!(matches!(cmd, ControlCommand::Stop { app_id: Some(ref a), .. } if a == "Spotify.exe"));
+}
+
+#[test]
+fn repeat_cycles_off_list_track() {
+    use crate::media::RepeatMode;
+    assert_eq!(RepeatMode::Off.next(), RepeatMode::List);
+    assert_eq!(RepeatMode::List.next(), RepeatMode::Track);
+    assert_eq!(RepeatMode::Track.next(), RepeatMode::Off);
+}
+
+#[test]
+fn input_failures_map_to_actionable_replies() {
+    use crate::server::error::input_error_reply;
+    use crate::system::InputError;
+    use windows::Win32::Foundation::{ERROR_ACCESS_DENIED, ERROR_SUCCESS};
+
+    assert_eq!(InputError::classify(ERROR_SUCCESS, false, true), InputError::SecureDesktopActive);
+    assert_eq!(InputError::classify(ERROR_SUCCESS, true, true), InputError::BlockedByUipi);
+    assert_eq!(InputError::classify(ERROR_ACCESS_DENIED, true, false), InputError::BlockedByUipi);
+    assert_eq!(InputError::classify(ERROR_SUCCESS, true, false), InputError::Unknown);
+
+    let reply = input_error_reply(InputError::BlockedByUipi);
+    assert_eq!(reply["code"], "blocked_by_uipi");
+    assert_eq!(reply["reason"], "input_blocked");
+    assert!(reply["message"].as_str().unwrap().contains("administrator"));
+    let reply = input_error_reply(InputError::SecureDesktopActive);
+    assert_eq!(reply["code"], "no_interactive_session");
+    assert_eq!(reply["cause"], "secure_desktop_active");
+
+    // the kind survives the context SendInput callers add
+    let err = anyhow::Error::new(InputError::Unknown).context("SendInput inserted 0/2 events").context("vk 0xb3");
+    assert_eq!(err.downcast_ref::<InputError>(), Some(&InputError::Unknown));
+}
+
+#[tokio::test]
+async fn timeline_subscription_has_a_floor() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-35").await;
+
+    let reply = request(&mut ws, json!({"cmd":"subscribe_timeline","interval_ms":100})).await;
+    assert_eq!(reply["action"], "subscribe_timeline");
+    assert_eq!(reply["interval_ms"], 500);
+    // the first tick is immediate, so a PC that is playing something may push one frame first
+    let mut reply = request(&mut ws, json!({"cmd":"unsubscribe_timeline"})).await;
+    while reply["type"] == "timeline" {
+        reply = recv(&mut ws).await;
+    }
+    assert_eq!(reply["action"], "unsubscribe_timeline");
+}
+
+#[test]
+fn media_app_ids_get_friendly_names() {
+    use crate::media::friendly_app_name;
+    assert_eq!(friendly_app_name("Spotify.exe").as_deref(), Some("Spotify"));
+    assert_eq!(friendly_app_name("C:\\Program Files\\VideoLAN\\VLC\\vlc.EXE").as_deref(), Some("vlc"));
+    assert_eq!(
+        friendly_app_name("Microsoft.ZuneMusic_8wekyb3d8bbwe!Microsoft.ZuneMusic").as_deref(),
+        Some("ZuneMusic")
+    );
+    assert_eq!(friendly_app_name("Chrome").as_deref(), Some("Chrome"));
+    assert_eq!(friendly_app_name(""), None);
+}
+
+#[test]
+fn media_backend_setting_defaults_to_keys() {
+    use crate::media::MediaBackend;
+    let config: Config = serde_json::from_value(json!({"max_clients": 4})).unwrap();
+    assert_eq!(config.media_backend, MediaBackend::Keys);
+    let config: Config = serde_json::from_value(json!({"media_backend": "auto"})).unwrap();
+    assert_eq!(config.media_backend, MediaBackend::Auto);
+}
+
+#[tokio::test]
+async fn launchers_are_listed_from_the_data_file() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-36").await;
+
+    let reply = request(&mut ws, json!({"cmd":"list_launchers"})).await;
+    assert_eq!(reply["launchers"], json!([]));
+
+    // picked up without a restart; program and args never leave the PC
+    let file = json!([
+        {"id":"obs","label":"OBS","program":"obs64.exe","args":["--minimize-to-tray"],"icon":"obs.png"},
+        {"id":"backup","label":"Backup","program":"backup.cmd","working_dir":"C:\\scripts"},
+        {"label":"missing id","program":"x.exe"}
+    ]);
+    std::fs::write(server.store_dir.join("launchers.json"), file.to_string()).unwrap();
+    let reply = request(&mut ws, json!({"cmd":"list_launchers"})).await;
+    assert_eq!(
+        reply["launchers"],
+        json!([{"id":"obs","label":"OBS","icon":"obs.png"},{"id":"backup","label":"Backup","icon":null}])
+    );
+
+    let reply = request(&mut ws, json!({"cmd":"launch","id":"daw"})).await;
+    assert_eq!(reply["code"], "not_found");
+}
+
+#[test]
+fn launcher_programs_resolve_against_the_working_dir() {
+    let dir = std::env::temp_dir().join(format!("fossdeck-test-{}-launcher", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("run.cmd"), "").unwrap();
+    let launcher = |program: &str| Launcher {
+        id: "x".into(),
+        label: "x".into(),
+        program: program.into(),
+        args: Vec::new(),
+        working_dir: Some(dir.to_string_lossy().into_owned()),
+        icon: None,
+    };
+    assert_eq!(launcher("run.cmd").resolved_program(), dir.join("run.cmd"));
+    // not in the working dir: left for the PATH search
+    assert_eq!(launcher("notepad.exe").resolved_program(), std::path::PathBuf::from("notepad.exe"));
+}
+
+#[test]
+fn open_url_accepts_only_web_and_mail_links() {
+    use crate::system::{parse_web_url, WebUrl};
+    let ok = |url: &str| parse_web_url(url).unwrap();
+    assert_eq!(ok("https://dashboard.example.com/live?x=1"), WebUrl { scheme: "https", host: "dashboard.example.com".into() });
+    assert_eq!(ok("HTTP://user:pw@Twitch.tv:8080/chat"), WebUrl { scheme: "http", host: "twitch.tv".into() });
+    assert_eq!(ok("http://[::1]:3000/"), WebUrl { scheme: "http", host: "[::1]".into() });
+    assert_eq!(ok("mailto:me@example.org?subject=hi"), WebUrl { scheme: "mailto", host: "example.org".into() });
+
+    for bad in [
+        "",
+        "file:///C:/Windows/System32/calc.exe",
+        "javascript:alert(1)",
+        "\\\\server\\share\\run.exe",
+        "http:\\\\server\\share",
+        "https:example.com",
+        "https://",
+        "https://exa mple.com",
+        "https://example.com/\" --new-window",
+        "https://example.com/\npath",
+        "https://ex%41mple.com",
+        "ms-settings:display",
+        "C:\\Windows\\notepad.exe",
+        "mailto:nobody",
+    ] {
+        assert!(parse_web_url(bad).is_err(), "{bad:?} was accepted");
+    }
+    assert!(parse_web_url(&format!("https://example.com/{}", "a".repeat(2048))).is_err());
+}
+
+#[tokio::test]
+async fn open_url_rejects_other_schemes_before_the_shell() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-37").await;
+
+    let expected = if crate::system::has_interactive_session() { "bad_request" } else { "no_interactive_session" };
+    let reply = request(&mut ws, json!({"cmd":"open_url","url":"file:///C:/Windows/System32/calc.exe"})).await;
+    assert_eq!(reply["code"], expected);
+    let reply = request(&mut ws, json!({"cmd":"open_url","url":"javascript:alert(1)"})).await;
+    assert_eq!(reply["code"], expected);
+}
+
+#[tokio::test]
+async fn power_commands_are_off_until_enabled_and_can_be_cancelled() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-38").await;
+    let mut scopes = crate::server::auth_store::default_scopes();
+    scopes.push("power".into());
+    server.pairing.lock().unwrap().set_scopes("phone-38", scopes);
+
+    let shutdown = json!({"cmd":"power","action":"shutdown","delay_secs":3600});
+    let reply = request(&mut ws, shutdown.clone()).await;
+    assert_eq!(reply["code"], "disabled");
+
+    server.config.write().unwrap().power_commands_enabled = true;
+    let reply = request(&mut ws, json!({"cmd":"power","action":"sleep","delay_secs":86_401})).await;
+    assert_eq!(reply["code"], "bad_request");
+    let reply = request(&mut ws, shutdown).await;
+    assert_eq!(reply["power_action"], "shutdown");
+    assert_eq!(reply["pending_power"]["action"], "shutdown");
+
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    let remaining = status["pending_power"]["seconds_remaining"].as_u64().unwrap();
+    assert!((3590..=3600).contains(&remaining));
+
+    let reply = request(&mut ws, json!({"cmd":"cancel_power"})).await;
+    assert_eq!(reply["cancelled"], "shutdown");
+    let status = request(&mut ws, json!({"cmd":"get_status"})).await;
+    assert_eq!(status["pending_power"], Value::Null);
+    let reply = request(&mut ws, json!({"cmd":"cancel_power"})).await;
+    assert_eq!(reply["cancelled"], Value::Null);
+}
+
+#[test]
+fn a_new_power_action_replaces_the_pending_one() {
+    use crate::server::power::PowerSchedule;
+    use crate::system::PowerAction;
+    let schedule = PowerSchedule::default();
+    schedule.schedule(PowerAction::Sleep, false, Duration::from_secs(3600));
+    schedule.schedule(PowerAction::Restart, true, Duration::from_secs(7200));
+    let (action, secs) = schedule.pending().unwrap();
+    assert_eq!(action, PowerAction::Restart);
+    assert!(secs > 3600);
+    assert_eq!(schedule.cancel(), Some(PowerAction::Restart));
+    assert_eq!(schedule.pending(), None);
+}
+
+#[test]
+fn screenshots_are_scaled_to_the_max_dimension() {
+    use crate::screen::{downscale_to_rgb, encode_png, Frame};
+    // 4x2 BGRA: left half blue, right half red
+    let mut bgra = Vec::new();
+    for _ in 0..2 {
+        for x in 0..4 {
+            bgra.extend(if x < 2 { [255, 0, 0, 255] } else { [0, 0, 255, 255] });
+        }
+    }
+    let frame = Frame { width: 4, height: 2, bgra };
+
+    let (w, h, rgb) = downscale_to_rgb(&frame, 2);
+    assert_eq!((w, h), (2, 1));
+    assert_eq!(rgb, vec![0, 0, 255, 255, 0, 0]);
+
+    // small enough already: untouched, only reordered to RGB
+    let (w, h, rgb) = downscale_to_rgb(&frame, 1920);
+    assert_eq!((w, h), (4, 2));
+    assert_eq!(&rgb[..6], &[0, 0, 255, 0, 0, 255]);
+
+    let png = encode_png(w, h, &rgb).unwrap();
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+}
+
+#[test]
+fn take_screenshot_returns_the_image_unless_saving_to_disk() {
+    use crate::screen::ScreenshotTarget;
+    use crate::server::commands::ControlCommand;
+    let cmd: ControlCommand = serde_json::from_value(json!({"cmd":"take_screenshot"})).unwrap();
+    assert!(matches!(cmd, ControlCommand::TakeScreenshot { save_to_disk: false, target: ScreenshotTarget::Primary }));
+    let cmd: ControlCommand = serde_json::from_value(json!({"cmd":"take_screenshot","save_to_disk":true})).unwrap();
+    assert!(matches!(cmd, ControlCommand::TakeScreenshot { save_to_disk: true, .. }));
+}
+
+#[test]
+fn screenshot_targets_parse_from_strings() {
+    use crate::screen::ScreenshotTarget;
+    use crate::server::commands::ControlCommand;
+    let target = |s: &str| serde_json::from_value::<ScreenshotTarget>(json!(s));
+    assert_eq!(target("primary").unwrap(), ScreenshotTarget::Primary);
+    assert_eq!(target("monitor:2").unwrap(), ScreenshotTarget::Monitor(2));
+    assert_eq!(target("active_window").unwrap(), ScreenshotTarget::ActiveWindow);
+    for bad in ["monitor:", "monitor:-1", "monitor:two", "secondary", ""] {
+        assert!(target(bad).is_err(), "{bad:?} was accepted");
+    }
+
+    let cmd: ControlCommand = serde_json::from_value(json!({"cmd":"take_screenshot","target":"monitor:1"})).unwrap();
+    assert!(matches!(cmd, ControlCommand::TakeScreenshot { save_to_disk: false, target: ScreenshotTarget::Monitor(1) }));
+}
+
+#[test]
+fn typed_text_turns_line_breaks_into_enter() {
+    use crate::system::{typed_chars, TypedChar};
+    assert_eq!(
+        typed_chars("a\r\nb\n\rж😀"),
+        vec![
+            TypedChar::Units(vec![0x61]),
+            TypedChar::Enter,
+            TypedChar::Units(vec![0x62]),
+            TypedChar::Enter,
+            TypedChar::Enter,
+            TypedChar::Units(vec![0x436]),
+            TypedChar::Units(vec![0xD83D, 0xDE00]),
+        ]
+    );
+    assert!(typed_chars("").is_empty());
+}
+
+#[tokio::test]
+async fn type_text_is_capped_and_needs_the_input_scope() {
+    let server = start_server(MockAudio::new(0.5, false, false));
+    let mut ws = paired_client(server.port, "phone-39").await;
+
+    let reply = request(&mut ws, json!({"cmd":"type_text","text":"hello"})).await;
+    assert_eq!(reply["code"], "forbidden");
+
+    let mut scopes = crate::server::auth_store::default_scopes();
+    scopes.push("input".into());
+    server.pairing.lock().unwrap().set_scopes("phone-39", scopes);
+    // typing needs a desktop; a run from a service session stops at that check instead
+    let expected = if crate::system::has_interactive_session() { "bad_request" } else { "no_interactive_session" };
+    let reply = request(&mut ws, json!({"cmd":"type_text","text":"x".repeat(1001)})).await;
+    assert_eq!(reply["code"], expected);
+}
+
+#[test]
+fn type_text_is_charged_per_character() {
+    use crate::server::commands::ControlCommand;
+    use crate::server::rate_limit::TokenBucket;
+    let cmd: ControlCommand = serde_json::from_value(json!({"cmd":"type_text","text":"hi\r\nthere"})).unwrap();
+    assert_eq!(cmd.input_events(), 8);
+    let cmd: ControlCommand = serde_json::from_value(json!({"cmd":"send_key_code","vk":13,"up":false})).unwrap();
+    assert_eq!(cmd.input_events(), 1);
+
+    // all or nothing: a text the bucket can't cover takes nothing from it
+    let mut bucket = TokenBucket::new(50, 0);
+    assert!(!bucket.try_take(51));
+    assert!(bucket.try_take(50));
+    assert!(!bucket.try_take(1));
+}
+
+#[test]
+fn long_text_fits_the_default_input_limit() {
+    use crate::server::commands::ControlCommand;
+    use crate::server::rate_limit::TokenBucket;
+    use crate::system::MAX_TYPE_TEXT_CHARS;
+    // the same bucket the server builds at startup
+    let mut bucket = TokenBucket::new(Config::default().input_events_per_sec, MAX_TYPE_TEXT_CHARS);
+    let cmd: ControlCommand = serde_json::from_value(json!({"cmd":"type_text","text":"x".repeat(200)})).unwrap();
+    assert!(bucket.try_take(cmd.input_events()));
+    // the cap itself is reachable from a full bucket, just not twice in a row
+    let mut bucket = TokenBucket::new(Config::default().input_events_per_sec, MAX_TYPE_TEXT_CHARS);
+    assert!(bucket.try_take(MAX_TYPE_TEXT_CHARS));
+    assert!(!bucket.try_take(MAX_TYPE_TEXT_CHARS));
+}
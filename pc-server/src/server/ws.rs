@@ -1,107 +1,384 @@
 // src/server/ws.rs
 #![cfg(windows)]
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use futures::{SinkExt, StreamExt};
-use log::{error, info};
+use log::{debug, error, info};
+use serde::Deserialize;
 use serde_json::json;
-use std::net::{SocketAddr};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::{select, sync::oneshot};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, oneshot};
+use tokio::select;
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 use warp::http::StatusCode;
 use warp::ws::{Message, WebSocket};
-use warp::Filter;
+use warp::{Filter, Rejection, Reply};
 
-use crate::server::auth_store::{generate_token, sha256_hex};
-use crate::server::commands::{handle_command, WsCommand};
-use crate::server::pairing::{generate_pairing_code, PairingState};
+use crate::audio::{AudioBackend, VolumeWatcher};
+use crate::config::SharedConfig;
+use crate::discovery::interface_ip;
+use crate::media::{self, MediaWatcher};
+use crate::sessions::SessionWatcher;
+use crate::system::{has_interactive_session, InputError, MAX_TYPE_TEXT_CHARS};
 
+use crate::server::auth_store::{generate_token, normalize_device_name, sha256_hex, MAX_DEVICE_NAME_CHARS};
+use crate::server::commands::{
+    capabilities, handle_command, AuthCommand, CommandCtx, ConnectionCommand, ControlCommand, WsCommand, MAX_CLIENT_META_BYTES,
+};
+use crate::server::error::{error, error_reply, input_error_reply, ErrorCode};
+use crate::server::events::{event_channel, EventSender, DEFAULT_TOPICS, TOPICS};
+use crate::server::identity::ServerIdentity;
+use crate::server::metrics::Metrics;
+use crate::server::pairing::PairingState;
+use crate::server::power::PowerSchedule;
+use crate::server::groups::GroupStore;
+use crate::server::launchers::LauncherStore;
+use crate::server::presets::PresetStore;
+use crate::server::rate_limit::TokenBucket;
+
+const CONTROL_PAGE: &str = include_str!("control.html");
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+
+// consecutive unparseable messages before the connection is dropped
+const MAX_BAD_MESSAGES: u32 = 10;
+
+// how long a first pairing waits for the GUI owner before it is denied
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+// floor for SetStatusInterval pushes
+const MIN_STATUS_INTERVAL: Duration = Duration::from_millis(500);
+
+// SubscribePeaks bounds; each tick is a COM call on a blocking thread
+const MIN_PEAK_INTERVAL_MS: u64 = 50;
+const MAX_PEAK_INTERVAL_MS: u64 = 1000;
+
+// floor for SubscribeTimeline pushes; each tick is a WinRT round trip
+const MIN_TIMELINE_INTERVAL_MS: u64 = 500;
+
+// how quickly panic mute undoes an unmute by Teams, Windows or another client
+const PANIC_MUTE_REASSERT: Duration = Duration::from_millis(250);
+
+// Binds (see Config::fallback_to_free_port), spawns the server and returns the address it got
+#[allow(clippy::too_many_arguments)]
 pub async fn run_ws_server(
     port: u16,
     shutdown_rx: oneshot::Receiver<()>,
     pairing_state: Arc<Mutex<PairingState>>,
-) -> Result<()> {
+    identity: ServerIdentity,
+    config: SharedConfig,
+    audio: Arc<dyn AudioBackend>,
+    presets: PresetStore,
+    groups: GroupStore,
+    launchers: LauncherStore,
+    // owned by the caller so it outlives this server (GUI restarts keep it)
+    panic_mute: Arc<AtomicBool>,
+    // likewise, so a pending shutdown survives a server restart and stays cancelable
+    power: PowerSchedule,
+) -> Result<SocketAddr> {
     let cancel = CancellationToken::new();
     let cancel_filter = warp::any().map({
         let cancel = cancel.clone();
         move || cancel.clone()
     });
 
-    let pairing_filter = {
-        let shared = pairing_state.clone();
-        warp::any().map(move || shared.clone())
+    // server-push events; the watcher unregisters its COM callbacks when dropped at shutdown
+    let events = event_channel();
+    let metrics = Arc::new(Metrics::default());
+    let session_watcher = SessionWatcher::start(events.clone());
+    let volume_watcher = VolumeWatcher::start(events.clone());
+    let media_watcher = MediaWatcher::start(events.clone());
+
+    // bind-time settings; changing these needs a restart
+    let startup = config.read().unwrap().clone();
+    let clients = ClientSlots::new(startup.max_clients);
+    let clients_filter = {
+        let clients = clients.clone();
+        warp::any().map(move || clients.clone())
     };
 
-    let health = warp::path!("health").map(|| warp::reply::with_status("ok", StatusCode::OK));
+    let ctx_filter = {
+        let ctx = ServerCtx {
+            pairing: pairing_state.clone(),
+            identity: Arc::new(identity),
+            events,
+            audio: audio.clone(),
+            presets: Arc::new(Mutex::new(presets)),
+            groups: Arc::new(Mutex::new(groups)),
+            launchers: Arc::new(Mutex::new(launchers)),
+            metrics: metrics.clone(),
+            input_limiter: Arc::new(Mutex::new(TokenBucket::new(startup.input_events_per_sec, MAX_TYPE_TEXT_CHARS))),
+            fade_generation: Default::default(),
+            config,
+            active_clients: clients.count.clone(),
+            panic_mute: panic_mute.clone(),
+            power,
+        };
+        warp::any().map(move || ctx.clone())
+    };
+
+    let health = warp::path!("health")
+        .and(warp::query::<HashMap<String, String>>())
+        .and(clients_filter.clone())
+        .map(move |q: HashMap<String, String>, clients: ClientSlots| {
+            if q.get("verbose").map(String::as_str) == Some("1") {
+                warp::reply::json(&json!({
+                    "status": "ok",
+                    "clients": clients.count(),
+                    "max_clients": clients.max,
+                    "metrics": metrics.to_json(),
+                }))
+                .into_response()
+            } else {
+                warp::reply::with_status("ok", StatusCode::OK).into_response()
+            }
+        });
+
+    // tiny browser client for machines without the app; pairs/auths over /ws like any other
+    let control_page = warp::path::end()
+        .or(warp::path!("control"))
+        .unify()
+        .and(warp::get())
+        .map(|| warp::reply::html(CONTROL_PAGE));
+
+    // known once bound (see fallback_to_free_port); no upgrade can arrive before that
+    let own_port = Arc::new(AtomicU16::new(0));
+    let origin_filter = {
+        let allowed = Arc::new(startup.allowed_origins.clone());
+        let own_port = own_port.clone();
+        warp::header::optional::<String>("origin")
+            .and(warp::header::optional::<String>("host"))
+            .and_then(move |origin: Option<String>, host: Option<String>| {
+                let allowed = allowed.clone();
+                let port = own_port.load(Ordering::SeqCst);
+                async move {
+                    if origin_allowed(origin.as_deref(), host.as_deref(), &allowed, &local_ips(), port) {
+                        Ok(())
+                    } else {
+                        info!("Rejected WS upgrade from origin {origin:?}");
+                        Err(warp::reject::custom(OriginRejected))
+                    }
+                }
+            })
+            .untuple_one()
+    };
 
     let ws_route = warp::path!("ws")
+        .and(origin_filter)
         .and(warp::ws())
         .and(warp::addr::remote())
         .and(cancel_filter.clone())
-        .and(pairing_filter.clone())
-        .map(|ws: warp::ws::Ws, remote: Option<SocketAddr>, cancel: CancellationToken, pairing| {
+        .and(clients_filter)
+        .and(ctx_filter)
+        .map(|ws: warp::ws::Ws, remote: Option<SocketAddr>, cancel: CancellationToken, clients: ClientSlots, ctx: ServerCtx| {
             ws.on_upgrade(move |socket| async move {
-                handle_ws(socket, cancel, remote, pairing).await;
+                let Some(_slot) = clients.acquire() else {
+                    ctx.metrics.connection_rejected();
+                    reject_full(socket).await;
+                    return;
+                };
+                ctx.metrics.connection_opened();
+                handle_ws(socket, cancel, remote, ctx).await;
             })
         });
 
-    let routes = health.or(ws_route);
+    let routes = health.or(control_page).or(ws_route).recover(reject_origin);
 
-    let addr = ([0, 0, 0, 0], port);
+    let bind_ip = match (&startup.bind_interface, startup.bind_ws_to_interface) {
+        (Some(name), true) => interface_ip(name).ok_or_else(|| anyhow!("network interface {name} not found"))?,
+        _ => Ipv4Addr::UNSPECIFIED,
+    };
+    let addr = (bind_ip, port);
+
+    // shutdown fans out through `cancel`, so each bind attempt below can take its own signal
+    let signal = |cancel: CancellationToken| async move { cancel.cancelled().await };
+    let bound = match warp::serve(routes.clone()).try_bind_with_graceful_shutdown(addr, signal(cancel.clone())) {
+        Err(e) if startup.fallback_to_free_port && is_addr_in_use(&e) => {
+            info!("Port {port} is in use; falling back to a free port");
+            warp::serve(routes).try_bind_with_graceful_shutdown((bind_ip, 0), signal(cancel.clone()))
+        }
+        other => other,
+    };
+    let (local_addr, server) = bound.map_err(|e| anyhow!("could not listen on {bind_ip}:{port}: {e}"))?;
+    own_port.store(local_addr.port(), Ordering::SeqCst);
+
+    let cancel_for_shutdown = cancel.clone();
+    tokio::spawn(async move {
+        let _ = shutdown_rx.await;
+        info!("Graceful shutdown signal received — closing all clients...");
+        cancel_for_shutdown.cancel();
+    });
 
     // idle watchdog: if no heartbeat, clear active session
     let pairing_for_watchdog = pairing_state.clone();
     let cancel_for_watchdog = cancel.clone();
     tokio::spawn(async move {
-        use tokio::time::{sleep, Duration as TokioDuration};
+        let tick = PairingState::watchdog_interval();
         loop {
-            if cancel_for_watchdog.is_cancelled() {
-                break;
-            }
             {
                 let mut st = pairing_for_watchdog.lock().unwrap();
                 if st.is_idle_too_long() {
                     st.clear_active();
                 }
             }
-            sleep(TokioDuration::from_secs(5)).await;
+            select! {
+                _ = cancel_for_watchdog.cancelled() => break,
+                _ = tokio::time::sleep(tick) => {}
+            }
         }
     });
 
-    let cancel_for_shutdown = cancel.clone();
-    warp::serve(routes)
-        .bind_with_graceful_shutdown(addr, async move {
-            let _ = shutdown_rx.await;
-            info!("Graceful shutdown signal received — closing all clients...");
-            cancel_for_shutdown.cancel();
-        })
-        .1
-        .await;
-
-    Ok(())
+    // panic mute: put the mic back to muted whenever something else unmutes it
+    let audio_for_panic = audio;
+    let cancel_for_panic = cancel.clone();
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(PANIC_MUTE_REASSERT);
+        // a missing mic fails every tick; only the first failure in a row is logged
+        let mut failing = false;
+        loop {
+            select! {
+                _ = cancel_for_panic.cancelled() => break,
+                _ = tick.tick() => {}
+            }
+            if !panic_mute.load(Ordering::SeqCst) {
+                continue;
+            }
+            let audio = audio_for_panic.clone();
+            let reasserted = tokio::task::spawn_blocking(move || -> anyhow::Result<bool> {
+                if audio.get_mic_mute(None)? {
+                    return Ok(false);
+                }
+                audio.set_mic_mute(None, true)?;
+                Ok(true)
+            })
+            .await;
+            let was_failing = std::mem::replace(&mut failing, matches!(reasserted, Ok(Err(_))));
+            match reasserted {
+                Ok(Ok(true)) => info!("Panic mute: microphone was unmuted, muted it again"),
+                Ok(Ok(false)) => {}
+                Ok(Err(e)) if !was_failing => error!("Panic mute check failed: {e:?}"),
+                Ok(Err(e)) => debug!("Panic mute check still failing: {e:?}"),
+                Err(e) => error!("Panic mute task failed: {e}"),
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        server.await;
+        drop(session_watcher);
+        drop(volume_watcher);
+        drop(media_watcher);
+    });
+
+    Ok(local_addr)
+}
+
+// Challenges are client-chosen; cap them so we never sign arbitrary large blobs
+const MAX_CHALLENGE_LEN: usize = 128;
+
+fn sign_challenge(identity: &ServerIdentity, challenge: &str) -> Option<String> {
+    (challenge.len() <= MAX_CHALLENGE_LEN).then(|| identity.sign_challenge(challenge)).flatten()
+}
+
+// warp only exposes bind failures as an opaque error; look for the io::Error underneath
+fn is_addr_in_use(e: &warp::Error) -> bool {
+    let mut source = std::error::Error::source(e);
+    while let Some(err) = source {
+        if let Some(io) = err.downcast_ref::<std::io::Error>() {
+            return io.kind() == std::io::ErrorKind::AddrInUse;
+        }
+        source = err.source();
+    }
+    false
 }
 
-async fn handle_ws(
-    ws: WebSocket,
-    cancel: CancellationToken,
-    remote: Option<SocketAddr>,
+// Shared server state handed to every connection
+#[derive(Clone)]
+struct ServerCtx {
     pairing: Arc<Mutex<PairingState>>,
-) {
+    identity: Arc<ServerIdentity>,
+    events: EventSender,
+    audio: Arc<dyn AudioBackend>,
+    presets: Arc<Mutex<PresetStore>>,
+    groups: Arc<Mutex<GroupStore>>,
+    launchers: Arc<Mutex<LauncherStore>>,
+    metrics: Arc<Metrics>,
+    input_limiter: Arc<Mutex<TokenBucket>>,
+    fade_generation: Arc<AtomicUsize>,
+    config: SharedConfig,
+    active_clients: Arc<AtomicUsize>,
+    panic_mute: Arc<AtomicBool>,
+    power: PowerSchedule,
+}
+
+async fn handle_ws(ws: WebSocket, cancel: CancellationToken, remote: Option<SocketAddr>, ctx: ServerCtx) {
+    let ServerCtx {
+        pairing,
+        identity,
+        events,
+        audio,
+        presets,
+        groups,
+        launchers,
+        metrics,
+        input_limiter,
+        fade_generation,
+        config,
+        active_clients,
+        panic_mute,
+        power,
+    } = ctx;
+    // silence (no frames, not even pongs) after which a connection counts as dead; a changed
+    // value applies to connections opened afterwards
+    let read_timeout = Duration::from_secs(config.read().unwrap().read_timeout_secs.max(1));
+    // undo history lives as long as this connection
+    let mut cmd_ctx = CommandCtx {
+        audio,
+        presets,
+        groups,
+        launchers,
+        history: Default::default(),
+        metrics: metrics.clone(),
+        input_limiter,
+        fade_generation,
+        full_status: true,
+        client_meta: None,
+        config,
+        active_clients,
+        panic_mute,
+        power,
+    };
     let (mut tx, mut rx) = ws.split();
     let remote_ip = remote.map(|a| a.ip());
 
     let mut authenticated = false;
     let mut authed_device_id: Option<String> = None;
 
+    let mut events_rx = events.subscribe();
+    let mut topics: HashSet<&'static str> = DEFAULT_TOPICS.iter().copied().collect();
+    let mut bad_messages: u32 = 0;
+    let mut status_timer: Option<tokio::time::Interval> = None;
+    let mut peak_timer: Option<tokio::time::Interval> = None;
+    let mut timeline_timer: Option<tokio::time::Interval> = None;
+
+    // pings keep idle-but-alive clients talking (their pongs reset the deadline)
+    let ping_period = read_timeout / 3;
+    let mut ping_timer = tokio::time::interval_at(Instant::now() + ping_period, ping_period);
+    let mut last_read = Instant::now();
+
     // hello
-    let (is_active_paired, active_id, authorized_count, code, code_expired) = {
+    // the code itself is only shown on the PC, or to admins via get_pairing_status
+    let (is_active_paired, active_id, authorized_count, code_expired) = {
         let st = pairing.lock().unwrap();
         (
             st.active_device_id.is_some(),
             st.active_device_id.clone(),
             st.authorized_count(),
-            st.code.clone(),
             st.is_expired(),
         )
     };
@@ -111,8 +388,12 @@ async fn handle_ws(
         "paired": is_active_paired,
         "active_device_id": active_id,
         "authorized_count": authorized_count,
-        "pairing_code": code,
         "pairing_code_expired": code_expired,
+        "identity_key": identity.identity_key(),
+        "identity_fingerprint": identity.identity_fingerprint(),
+        // period of server pings and of "heartbeat" pushes
+        "keepalive_secs": ping_period.as_secs_f32(),
+        "capabilities": capabilities(has_interactive_session(), &cmd_ctx.config.read().unwrap().refused_categories()),
     });
 
     if tx.send(Message::text(hello.to_string())).await.is_err() {
@@ -126,9 +407,89 @@ async fn handle_ws(
                 break;
             }
 
-            msg = rx.next() => {
+            ev = events_rx.recv() => {
+                match ev {
+                    Ok(ev) if authenticated && topics.contains(ev.topic) => {
+                        if tx.send(Message::text(ev.payload.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            _ = next_tick(&mut status_timer), if authenticated => {
+                let status = run_command(ControlCommand::GetStatus { role: None, device_id: None }, cmd_ctx.clone()).await;
+                // errors are left for explicit requests rather than repeated every tick
+                if status["type"] == "status" && tx.send(Message::text(status.to_string())).await.is_err() {
+                    break;
+                }
+            }
+
+            _ = next_tick(&mut peak_timer), if authenticated => {
+                let audio = cmd_ctx.audio.clone();
+                let peak = tokio::time::timeout(COMMAND_TIMEOUT, tokio::task::spawn_blocking(move || audio.get_peak_level())).await;
+                // a missing device or slow tick just skips a frame
+                if let Ok(Ok(Ok(level))) = peak
+                    && tx.send(Message::text(json!({"type":"peak","level":level}).to_string())).await.is_err()
+                {
+                    break;
+                }
+            }
+
+            _ = next_tick(&mut timeline_timer), if authenticated => {
+                let position = tokio::time::timeout(COMMAND_TIMEOUT, tokio::task::spawn_blocking(media::playback_position)).await;
+                // nothing is pushed while paused or without a session
+                if let Ok(Ok(Ok(Some(p)))) = position
+                    && p.playing
+                    && tx
+                        .send(Message::text(
+                            json!({"type":"timeline","position_ms":p.position_ms,"duration_ms":p.duration_ms}).to_string(),
+                        ))
+                        .await
+                        .is_err()
+                {
+                    break;
+                }
+            }
+
+            _ = ping_timer.tick() => {
+                if tx.send(Message::ping(Vec::new())).await.is_err() {
+                    break;
+                }
+                // unlike the ping frame this reaches the client's JS, which can time the round trip
+                if let Some(me) = authed_device_id.as_deref().filter(|_| topics.contains("heartbeat")) {
+                    let ts = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+                    if tx.send(Message::text(json!({"type":"heartbeat","ts":ts}).to_string())).await.is_err() {
+                        break;
+                    }
+                    // a delivered push counts as activity for this connection's device
+                    pairing.lock().unwrap().touch_device(me);
+                }
+            }
+
+            msg = tokio::time::timeout_at(last_read + read_timeout, rx.next()) => {
+                let Ok(msg) = msg else {
+                    info!("No frames from {remote:?} in {read_timeout:?}; dropping dead connection");
+                    break;
+                };
+                last_read = Instant::now();
                 let Some(Ok(msg)) = msg else { break; };
 
+                if msg.is_close() {
+                    // clean client close: free the session now instead of waiting for the idle watchdog
+                    if let Some(me) = &authed_device_id {
+                        let mut st = pairing.lock().unwrap();
+                        if st.active_device_id.as_ref() == Some(me) {
+                            st.clear_active();
+                        }
+                    }
+                    // flushes the close reply queued by the protocol layer
+                    let _ = tx.close().await;
+                    break;
+                }
+
                 if !msg.is_text() {
                     continue;
                 }
@@ -138,36 +499,86 @@ async fn handle_ws(
                     Err(_) => continue,
                 };
 
-                let reply = match serde_json::from_str::<WsCommand>(text) {
+                let parsed = WsCommand::parse(text);
+                if parsed.is_ok() {
+                    bad_messages = 0;
+                } else {
+                    bad_messages += 1;
+                    if bad_messages >= MAX_BAD_MESSAGES {
+                        error!("Closing connection after {bad_messages} malformed messages");
+                        let reply = error(ErrorCode::TooManyBadMessages, None);
+                        let _ = tx.send(Message::text(reply.to_string())).await;
+                        let _ = tx.send(Message::close()).await;
+                        break;
+                    }
+                }
+
+                let reply = match parsed {
 
                     // ---------------------------
                     // AUTH
                     // ---------------------------
-                    Ok(WsCommand::Auth { device_id, token }) => {
+                    Ok(WsCommand::Auth(ref cmd)) if cmd.client_meta_len() > MAX_CLIENT_META_BYTES => {
+                        let ty = match cmd {
+                            AuthCommand::Pair { .. } => "pairing_error",
+                            AuthCommand::Auth { .. } | AuthCommand::Identify { .. } => "auth_error",
+                        };
+                        let msg = format!("client_meta exceeds {MAX_CLIENT_META_BYTES} bytes");
+                        error_reply(ty, ErrorCode::BadRequest, Some(msg))
+                    }
+
+                    Ok(WsCommand::Auth(AuthCommand::Identify { challenge })) => json!({
+                        "type": "identity",
+                        "identity_key": identity.identity_key(),
+                        "challenge_sig": sign_challenge(&identity, &challenge),
+                    }),
+
+                    Ok(WsCommand::Auth(AuthCommand::Auth { device_id, token, client_meta, challenge })) => {
                         if remote_ip.is_none() {
-                            json!({"type":"auth_error","reason":"no_remote_ip"})
+                            error_reply("auth_error", ErrorCode::NoRemoteIp, None)
                         } else {
                             let ip = remote_ip.unwrap();
                             let mut st = pairing.lock().unwrap();
 
                             if let Some(rem) = st.rl_is_locked(ip) {
-                                json!({"type":"rate_limited","reason":"auth","retry_after_secs": rem})
+                                st.audit().record("auth_rate_limited", Some(ip), Some(&device_id));
+                                rate_limited("auth", rem)
                             } else {
                                 if st.is_authorized(&device_id, &token) {
+                                    st.audit().record("auth_ok", Some(ip), Some(&device_id));
                                     st.rl_register_success(ip);
                                     authenticated = true;
                                     authed_device_id = Some(device_id.clone());
 
+                                    let resumed = st.active_device_id.as_ref() == Some(&device_id)
+                                        && !st.is_idle_too_long();
+                                    if resumed && st.active_client_ip.is_some_and(|prev| prev != ip) {
+                                        info!("Device {device_id} resumed its session from new address {ip}");
+                                    }
+
+                                    if let Some(meta) = &client_meta {
+                                        info!("Device {device_id} client_meta: {meta}");
+                                    }
+                                    cmd_ctx.client_meta = client_meta;
+
                                     st.active_device_id = Some(device_id);
                                     st.active_client_ip = Some(ip);
                                     st.mark_seen();
 
-                                    json!({"type":"auth_ok"})
+                                    json!({
+                                        "type":"auth_ok",
+                                        "resumed": resumed,
+                                        "identity_key": identity.identity_key(),
+                                        "challenge_sig": challenge.as_deref().and_then(|c| sign_challenge(&identity, c)),
+                                    })
                                 } else {
+                                    st.audit().record("auth_failed", Some(ip), Some(&device_id));
                                     st.rl_register_failure(ip);
                                     authenticated = false;
                                     authed_device_id = None;
-                                    json!({"type":"auth_error","reason":"invalid_token"})
+                                    let mut v = error_reply("auth_error", ErrorCode::InvalidToken, None);
+                                    v["attempts_remaining"] = json!(st.rl_attempts_remaining(ip));
+                                    v
                                 }
                             }
                         }
@@ -176,71 +587,303 @@ async fn handle_ws(
                     // ---------------------------
                     // PAIR
                     // ---------------------------
-                    Ok(WsCommand::Pair { code, device_id, device_name }) => {
-                        if remote_ip.is_none() {
-                            json!({"type":"pairing_error","reason":"no_remote_ip"})
-                        } else {
-                            let ip = remote_ip.unwrap();
-                            let mut st = pairing.lock().unwrap();
+                    Ok(WsCommand::Auth(AuthCommand::Pair { code, device_id, device_name, client_meta, challenge })) => {
+                        metrics.pairing_attempt();
+                        // Ok((ip, approval)) once the code checks out; Err(reply) otherwise
+                        let checked = match remote_ip {
+                            None => Err(error_reply("pairing_error", ErrorCode::NoRemoteIp, None)),
+                            Some(ip) => {
+                                let mut st = pairing.lock().unwrap();
 
-                            if let Some(rem) = st.rl_is_locked(ip) {
-                                json!({"type":"rate_limited","reason":"pair","retry_after_secs": rem})
-                            } else {
-                                if st.is_expired() && st.active_device_id.is_none() {
-                                    st.code = generate_pairing_code();
-                                    st.created_at = std::time::Instant::now();
+                                if let Some(rem) = st.rl_is_locked(ip) {
+                                    st.audit().record("pair_rate_limited", Some(ip), Some(&device_id));
+                                    Err(rate_limited("pair", rem))
+                                } else {
+                                    if st.is_expired() && st.active_device_id.is_none() {
+                                        st.rotate_code();
+                                    }
+
+                                    // alphanumeric codes are shown upper-case; accept however they were typed
+                                    if !st.code.eq_ignore_ascii_case(code.trim()) {
+                                        st.audit().record("pair_code_invalid", Some(ip), Some(&device_id));
+                                        st.rl_register_failure(ip);
+                                        let mut v = error_reply("pairing_error", ErrorCode::InvalidCode, None);
+                                        v["attempts_remaining"] = json!(st.rl_attempts_remaining(ip));
+                                        Err(v)
+                                    } else {
+                                        st.audit().record("pair_code_valid", Some(ip), Some(&device_id));
+                                        st.rl_register_success(ip);
+                                        let approval = st
+                                            .needs_approval(&device_id)
+                                            .then(|| st.request_approval(device_id.clone(), device_name.clone(), ip));
+                                        Ok((ip, approval))
+                                    }
+                                }
+                            }
+                        };
+
+                        match checked {
+                            Err(reply) => reply,
+                            Ok((ip, approval)) => {
+                                let decision = match approval {
+                                    None => Ok(()),
+                                    Some((approval_id, decision)) => {
+                                        let pending = json!({
+                                            "type": "pairing_pending",
+                                            "timeout_secs": APPROVAL_TIMEOUT.as_secs(),
+                                        });
+                                        if tx.send(Message::text(pending.to_string())).await.is_err() {
+                                            pairing.lock().unwrap().cancel_approval(approval_id);
+                                            break;
+                                        }
+
+                                        // the owner may take longer than the read timeout: keep pinging and
+                                        // reading meanwhile so the client's pongs hold the connection open
+                                        let decision = tokio::time::timeout(APPROVAL_TIMEOUT, decision);
+                                        tokio::pin!(decision);
+                                        let mut gone = false;
+                                        let answer = loop {
+                                            select! {
+                                                _ = cancel.cancelled() => break None,
+                                                r = &mut decision => break r.ok().and_then(Result::ok),
+                                                _ = ping_timer.tick() => {
+                                                    if tx.send(Message::ping(Vec::new())).await.is_err() {
+                                                        gone = true;
+                                                        break None;
+                                                    }
+                                                }
+                                                msg = tokio::time::timeout_at(last_read + read_timeout, rx.next()) => {
+                                                    let Ok(Some(Ok(msg))) = msg else {
+                                                        gone = true;
+                                                        break None;
+                                                    };
+                                                    last_read = Instant::now();
+                                                    if msg.is_close() {
+                                                        gone = true;
+                                                        break None;
+                                                    }
+                                                    if msg.is_text() {
+                                                        let reply = error(ErrorCode::NotAuthenticated, Some("waiting for owner approval".into()));
+                                                        if tx.send(Message::text(reply.to_string())).await.is_err() {
+                                                            gone = true;
+                                                            break None;
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        };
+                                        if gone {
+                                            pairing.lock().unwrap().cancel_approval(approval_id);
+                                            break;
+                                        }
+                                        last_read = Instant::now();
+                                        match answer {
+                                            Some(true) => Ok(()),
+                                            Some(false) => Err("denied"),
+                                            None => {
+                                                let mut st = pairing.lock().unwrap();
+                                                st.cancel_approval(approval_id);
+                                                st.audit().record("pair_approval_timeout", Some(ip), Some(&device_id));
+                                                Err("timeout")
+                                            }
+                                        }
+                                    }
+                                };
+
+                                match decision {
+                                    Err(reason) => json!({"type":"pairing_denied","reason":reason}),
+                                    Ok(()) => {
+                                        let mut st = pairing.lock().unwrap();
+
+                                        // generate + store token
+                                        let token = generate_token();
+                                        let token_hash = sha256_hex(&token);
+                                        st.upsert_authorized(device_id.clone(), token_hash, device_name);
+
+                                        authenticated = true;
+                                        authed_device_id = Some(device_id.clone());
+
+                                        if let Some(meta) = &client_meta {
+                                            info!("Device {device_id} client_meta: {meta}");
+                                        }
+                                        cmd_ctx.client_meta = client_meta;
+
+                                        st.active_device_id = Some(device_id);
+                                        st.active_client_ip = Some(ip);
+                                        st.mark_seen();
+
+                                        json!({
+                                            "type":"pairing_ok",
+                                            "token": token,
+                                            "identity_key": identity.identity_key(),
+                                            "challenge_sig": challenge.as_deref().and_then(|c| sign_challenge(&identity, c)),
+                                        })
+                                    }
                                 }
+                            }
+                        }
+                    }
 
-                                if st.code != code {
-                                    st.rl_register_failure(ip);
-                                    json!({"type":"pairing_error","reason":"invalid_code"})
-                                } else {
-                                    st.rl_register_success(ip);
+                    // ---------------------------
+                    // EVENT SUBSCRIPTIONS
+                    // ---------------------------
+                    Ok(WsCommand::Connection(ConnectionCommand::Subscribe { topics: requested })) if authenticated => {
+                        update_topics(&mut topics, &requested, true)
+                    }
+                    Ok(WsCommand::Connection(ConnectionCommand::Unsubscribe { topics: requested })) if authenticated => {
+                        update_topics(&mut topics, &requested, false)
+                    }
 
-                                    // generate + store token
-                                    let token = generate_token();
-                                    let token_hash = sha256_hex(&token);
-                                    st.upsert_authorized(device_id.clone(), token_hash, device_name);
+                    // ---------------------------
+                    // PERIODIC STATUS
+                    // ---------------------------
+                    Ok(WsCommand::Connection(ConnectionCommand::SetStatusInterval { ms })) if authenticated => {
+                        let period = ms.map(|ms| Duration::from_millis(ms).max(MIN_STATUS_INTERVAL));
+                        // replacing the timer keeps at most one per connection
+                        status_timer = period.map(|p| {
+                            let mut t = tokio::time::interval(p);
+                            t.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                            t
+                        });
+                        json!({
+                            "type": "ok",
+                            "action": "set_status_interval",
+                            "ms": period.map(|p| p.as_millis() as u64),
+                        })
+                    }
 
-                                    authenticated = true;
-                                    authed_device_id = Some(device_id.clone());
+                    Ok(WsCommand::Connection(ConnectionCommand::SubscribePeaks { interval_ms })) if authenticated => {
+                        let ms = interval_ms.clamp(MIN_PEAK_INTERVAL_MS, MAX_PEAK_INTERVAL_MS);
+                        let mut t = tokio::time::interval(Duration::from_millis(ms));
+                        t.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+                        peak_timer = Some(t);
+                        json!({"type":"ok","action":"subscribe_peaks","interval_ms":ms})
+                    }
 
-                                    st.active_device_id = Some(device_id);
-                                    st.active_client_ip = Some(ip);
-                                    st.mark_seen();
+                    Ok(WsCommand::Connection(ConnectionCommand::UnsubscribePeaks)) if authenticated => {
+                        peak_timer = None;
+                        json!({"type":"ok","action":"unsubscribe_peaks"})
+                    }
+
+                    Ok(WsCommand::Connection(ConnectionCommand::SubscribeTimeline { interval_ms })) if authenticated => {
+                        let ms = interval_ms.max(MIN_TIMELINE_INTERVAL_MS);
+                        let mut t = tokio::time::interval(Duration::from_millis(ms));
+                        t.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+                        timeline_timer = Some(t);
+                        json!({"type":"ok","action":"subscribe_timeline","interval_ms":ms})
+                    }
+
+                    Ok(WsCommand::Connection(ConnectionCommand::UnsubscribeTimeline)) if authenticated => {
+                        timeline_timer = None;
+                        json!({"type":"ok","action":"unsubscribe_timeline"})
+                    }
+
+                    Ok(WsCommand::Connection(ConnectionCommand::SetVerbosity { full_status })) if authenticated => {
+                        cmd_ctx.full_status = full_status;
+                        json!({"type":"ok","action":"set_verbosity","full_status":full_status})
+                    }
+
+                    // ---------------------------
+                    // WHOAMI
+                    // ---------------------------
+                    Ok(WsCommand::Connection(ConnectionCommand::WhoAmI)) if authenticated => {
+                        let st = pairing.lock().unwrap();
+                        match authed_device_id.as_deref().and_then(|id| st.authorized_device(id).map(|d| (id, d))) {
+                            Some((id, dev)) => json!({
+                                "type": "whoami",
+                                "device_id": id,
+                                "name": dev.name,
+                                "added_at": dev.added_at,
+                                "last_seen": dev.last_seen,
+                                "scopes": dev.scopes,
+                                "active": st.active_device_id.as_deref() == Some(id),
+                            }),
+                            // revoked from the GUI while connected
+                            None => error(ErrorCode::NotAuthenticated, None),
+                        }
+                    }
 
-                                    json!({"type":"pairing_ok","token": token})
+                    Ok(WsCommand::Connection(ConnectionCommand::GetPairingStatus)) if authenticated => {
+                        let st = pairing.lock().unwrap();
+                        let mut v = json!({
+                            "type": "pairing_status",
+                            "code_active": !st.is_expired(),
+                            "ttl_remaining_secs": st.ttl_remaining().map(|d| d.as_secs()),
+                            "pairing_enabled": st.pairing_enabled(),
+                            "authorized_count": st.authorized_count(),
+                        });
+                        // any paired device could otherwise onboard others
+                        if authed_device_id.as_deref().is_some_and(|id| st.has_scope(id, "admin")) {
+                            v["code"] = json!(st.code);
+                        }
+                        v
+                    }
+
+                    Ok(WsCommand::Connection(ConnectionCommand::RenameDevice { name })) if authenticated => {
+                        match normalize_device_name(&name) {
+                            None => error(
+                                ErrorCode::BadRequest,
+                                Some(format!("name must be 1-{MAX_DEVICE_NAME_CHARS} characters")),
+                            ),
+                            Some(name) => {
+                                let mut st = pairing.lock().unwrap();
+                                match authed_device_id.as_deref().and_then(|id| st.rename_device(id, name).map(|d| (id, d))) {
+                                    Some((id, dev)) => json!({
+                                        "type": "device_renamed",
+                                        "device_id": id,
+                                        "name": dev.name,
+                                        "added_at": dev.added_at,
+                                        "last_seen": dev.last_seen,
+                                        "scopes": dev.scopes,
+                                    }),
+                                    None => error(ErrorCode::NotAuthenticated, None),
                                 }
                             }
                         }
                     }
 
+                    Ok(WsCommand::Connection(_)) => error(ErrorCode::NotAuthenticated, None),
+
                     // ---------------------------
                     // DEVICE CONTROL COMMANDS
                     // ---------------------------
-                    Ok(cmd) => {
+                    Ok(WsCommand::Control(cmd)) => {
                         if !authenticated {
-                            json!({"type":"error","reason":"not_authenticated"})
+                            error(ErrorCode::NotAuthenticated, None)
+                        } else if cmd.disabled_by(&cmd_ctx.config.read().unwrap().refused_categories()) {
+                            error(ErrorCode::Disabled, None)
                         } else {
-                            // heartbeat / keepalive
-                            {
+                            // heartbeat / keepalive + scope check
+                            let allowed = {
                                 let mut st = pairing.lock().unwrap();
                                 st.mark_seen();
-                            }
+                                match (cmd.required_scope(), &authed_device_id) {
+                                    (Some(scope), Some(id)) => st.has_scope(id, scope),
+                                    _ => true,
+                                }
+                            };
 
-                            match handle_command(cmd) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    error!("Command error: {e:?}");
-                                    json!({"type":"error","reason":"command_failed"})
+                            if !allowed {
+                                error(ErrorCode::Forbidden, None)
+                            } else {
+                                let action = cmd.name();
+                                let ack = cmd.mutates() && wants_ack(text);
+                                let reply = run_command(cmd, cmd_ctx.clone()).await;
+                                // minimal LED/macro-pad clients key off this instead of the full reply
+                                if ack {
+                                    let ack = json!({"type":"ack","action":action,"ok":reply["type"] != "error"});
+                                    if tx.send(Message::text(ack.to_string())).await.is_err() {
+                                        break;
+                                    }
                                 }
+                                reply
                             }
                         }
                     }
 
                     Err(e) => {
                         error!("Bad JSON from client: {e:?}");
-                        json!({"type":"error","reason":"bad_request"})
+                        error(ErrorCode::BadRequest, Some(e.to_string()))
                     }
                 };
 
@@ -262,5 +905,196 @@ async fn handle_ws(
         }
     }
 
-    // client disconnected; watchdog clears active session if no heartbeat
+    // client dropped without a close frame; keep the session for the reconnect grace window
+    if let Some(me) = &authed_device_id {
+        pairing.lock().unwrap().mark_disconnected(me);
+    }
+}
+
+// `"request_ack": true` can ride along on any command; ControlCommand itself ignores the field
+#[derive(Deserialize)]
+struct AckRequest {
+    #[serde(default)]
+    request_ack: bool,
+}
+
+fn wants_ack(text: &str) -> bool {
+    serde_json::from_str::<AckRequest>(text).is_ok_and(|a| a.request_ack)
+}
+
+// Device calls (COM/WinRT) can block for seconds during driver hiccups; run them off the
+// async task so this connection's select! loop keeps servicing cancel/events.
+async fn run_command(cmd: ControlCommand, ctx: CommandCtx) -> serde_json::Value {
+    let task = tokio::task::spawn_blocking(move || handle_command(cmd, &ctx));
+    match tokio::time::timeout(COMMAND_TIMEOUT, task).await {
+        Ok(Ok(Ok(v))) => v,
+        Ok(Ok(Err(e))) => {
+            error!("Command error: {e:?}");
+            match e.downcast_ref::<InputError>() {
+                Some(input) => input_error_reply(*input),
+                None => error(ErrorCode::CommandFailed, Some(e.to_string())),
+            }
+        }
+        Ok(Err(e)) => {
+            error!("Command task panicked: {e:?}");
+            error(ErrorCode::CommandFailed, None)
+        }
+        Err(_) => {
+            error!("Command timed out after {COMMAND_TIMEOUT:?}");
+            error(ErrorCode::Timeout, None)
+        }
+    }
+}
+
+// Pends forever while no timer is set
+async fn next_tick(timer: &mut Option<tokio::time::Interval>) {
+    match timer {
+        Some(t) => {
+            t.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+fn update_topics(topics: &mut HashSet<&'static str>, requested: &[String], subscribe: bool) -> serde_json::Value {
+    for t in requested {
+        let Some(known) = TOPICS.iter().find(|k| **k == t.as_str()) else {
+            return error(ErrorCode::BadRequest, Some(format!("unknown topic: {t}")));
+        };
+        if subscribe {
+            topics.insert(known);
+        } else {
+            topics.remove(known);
+        }
+    }
+
+    let mut current: Vec<_> = topics.iter().copied().collect();
+    current.sort();
+    json!({
+        "type": "ok",
+        "action": if subscribe { "subscribe" } else { "unsubscribe" },
+        "topics": current,
+    })
+}
+
+// Shared count of connected clients, bounded by Config::max_clients
+#[derive(Clone)]
+struct ClientSlots {
+    count: Arc<AtomicUsize>,
+    max: usize,
+}
+
+impl ClientSlots {
+    fn new(max: usize) -> Self {
+        Self { count: Arc::new(AtomicUsize::new(0)), max }
+    }
+
+    fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    fn acquire(&self) -> Option<ClientSlot> {
+        self.count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < self.max).then_some(n + 1))
+            .ok()
+            .map(|_| ClientSlot(self.count.clone()))
+    }
+}
+
+// Frees its slot when the connection handler returns
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn reject_full(ws: WebSocket) {
+    let (mut tx, _rx) = ws.split();
+    let reply = error(ErrorCode::ServerFull, None);
+    let _ = tx.send(Message::text(reply.to_string())).await;
+    let _ = tx.send(Message::close()).await;
+}
+
+#[derive(Debug)]
+struct OriginRejected;
+
+impl warp::reject::Reject for OriginRejected {}
+
+async fn reject_origin(err: Rejection) -> Result<warp::reply::Response, Rejection> {
+    if err.find::<OriginRejected>().is_some() {
+        Ok(warp::reply::with_status("origin not allowed", StatusCode::FORBIDDEN).into_response())
+    } else {
+        Err(err)
+    }
+}
+
+// Addresses this PC answers on, loopback included
+fn local_ips() -> Vec<IpAddr> {
+    get_if_addrs::get_if_addrs().unwrap_or_default().into_iter().map(|iface| iface.ip()).collect()
+}
+
+// Native clients send no Origin; the Tauri webview and local tools use a localhost origin.
+// `own_ips`/`own_port` are where this server listens: a Host naming anything else is a
+// DNS-rebinding page, whose Host and Origin agree with each other but not with us.
+pub(crate) fn origin_allowed(
+    origin: Option<&str>,
+    host: Option<&str>,
+    allowed: &[String],
+    own_ips: &[IpAddr],
+    own_port: u16,
+) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    if allowed.iter().any(|a| a.eq_ignore_ascii_case(origin)) {
+        return true;
+    }
+
+    let rest = origin.split_once("://").map_or(origin, |(_, r)| r);
+    // same origin: the embedded control page, loaded from this PC's own address
+    if let Some(host) = host
+        && host.eq_ignore_ascii_case(rest.trim_end_matches('/'))
+        && host_is_us(host, own_ips, own_port)
+    {
+        return true;
+    }
+
+    let host = if let Some(v6) = rest.strip_prefix('[') {
+        v6.split(']').next().unwrap_or("")
+    } else {
+        rest.split([':', '/']).next().unwrap_or("")
+    };
+    matches!(
+        host.to_ascii_lowercase().as_str(),
+        "localhost" | "127.0.0.1" | "::1" | "tauri.localhost"
+    )
+}
+
+// Host header as ip[:port] naming one of our addresses and the port we listen on
+fn host_is_us(host: &str, own_ips: &[IpAddr], own_port: u16) -> bool {
+    let (ip, port) = if let Some(v6) = host.strip_prefix('[') {
+        let Some((ip, rest)) = v6.split_once(']') else {
+            return false;
+        };
+        (ip, rest.strip_prefix(':'))
+    } else {
+        match host.split_once(':') {
+            Some((ip, port)) => (ip, Some(port)),
+            None => (host, None),
+        }
+    };
+    let port = match port {
+        Some(p) => p.parse::<u16>().ok(),
+        None => Some(80),
+    };
+    ip.parse::<IpAddr>().is_ok_and(|ip| own_ips.contains(&ip)) && port == Some(own_port)
+}
+
+fn rate_limited(scope: &str, retry_after_secs: u64) -> serde_json::Value {
+    let mut v = error_reply("rate_limited", ErrorCode::RateLimited, None);
+    v["reason"] = json!(scope);
+    v["retry_after_secs"] = json!(retry_after_secs);
+    v
 }
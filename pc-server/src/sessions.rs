@@ -0,0 +1,151 @@
+// src/sessions.rs
+use anyhow::Result;
+use log::error;
+use serde_json::json;
+use std::sync::{mpsc, Mutex};
+use std::thread::{self, JoinHandle};
+use windows::core::{implement, ComObject, GUID, PCWSTR};
+use windows::Win32::Foundation::BOOL;
+use windows::Win32::Media::Audio::{
+    eConsole, eRender, AudioSessionDisconnectReason, AudioSessionState, IAudioSessionControl, IAudioSessionEvents,
+    IAudioSessionEvents_Impl, IAudioSessionManager2, IAudioSessionNotification, IAudioSessionNotification_Impl,
+    IMMDeviceEnumerator, MMDeviceEnumerator,
+};
+use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL, COINIT_MULTITHREADED};
+
+use crate::server::events::{publish, EventSender};
+
+fn notify(events: &EventSender, reason: &str) {
+    publish(events, "sessions", json!({"type":"sessions_changed","reason":reason}));
+}
+
+// Watches the default render device for sessions appearing, changing state or volume.
+// COM registrations live on a dedicated MTA thread and are released when this is dropped.
+pub struct SessionWatcher {
+    stop_tx: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SessionWatcher {
+    pub fn start(events: EventSender) -> Self {
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            if let Err(e) = watch_sessions(events, stop_rx) {
+                error!("Audio session watcher failed: {e:?}");
+            }
+        });
+        Self { stop_tx: Some(stop_tx), handle: Some(handle) }
+    }
+}
+
+impl Drop for SessionWatcher {
+    fn drop(&mut self) {
+        // dropping the sender wakes the watcher thread
+        self.stop_tx.take();
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+#[implement(IAudioSessionEvents)]
+struct SessionEvents {
+    events: EventSender,
+}
+
+impl IAudioSessionEvents_Impl for SessionEvents_Impl {
+    fn OnDisplayNameChanged(&self, _name: &PCWSTR, _ctx: *const GUID) -> windows::core::Result<()> {
+        notify(&self.events, "display_name");
+        Ok(())
+    }
+
+    fn OnIconPathChanged(&self, _path: &PCWSTR, _ctx: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnSimpleVolumeChanged(&self, _volume: f32, _mute: BOOL, _ctx: *const GUID) -> windows::core::Result<()> {
+        notify(&self.events, "volume");
+        Ok(())
+    }
+
+    fn OnChannelVolumeChanged(&self, _count: u32, _volumes: *const f32, _changed: u32, _ctx: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnGroupingParamChanged(&self, _param: *const GUID, _ctx: *const GUID) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnStateChanged(&self, _state: AudioSessionState) -> windows::core::Result<()> {
+        notify(&self.events, "state");
+        Ok(())
+    }
+
+    fn OnSessionDisconnected(&self, _reason: AudioSessionDisconnectReason) -> windows::core::Result<()> {
+        notify(&self.events, "disconnected");
+        Ok(())
+    }
+}
+
+#[implement(IAudioSessionNotification)]
+struct SessionNotifier {
+    events: EventSender,
+    session_events: IAudioSessionEvents,
+    registered: Mutex<Vec<IAudioSessionControl>>,
+}
+
+impl IAudioSessionNotification_Impl for SessionNotifier_Impl {
+    fn OnSessionCreated(&self, session: Option<&IAudioSessionControl>) -> windows::core::Result<()> {
+        if let Some(session) = session {
+            if let Err(e) = crate::audio::apply_pending_system_sounds(session) {
+                error!("Applying the pending system sounds volume failed: {e:?}");
+            }
+            unsafe { session.RegisterAudioSessionNotification(&self.session_events)? };
+            self.registered.lock().unwrap().push(session.clone());
+        }
+        notify(&self.events, "created");
+        Ok(())
+    }
+}
+
+fn watch_sessions(events: EventSender, stop_rx: mpsc::Receiver<()>) -> Result<()> {
+    unsafe { CoInitializeEx(None, COINIT_MULTITHREADED).ok()? };
+    let res = register_and_wait(events, stop_rx);
+    unsafe { CoUninitialize() };
+    res
+}
+
+fn register_and_wait(events: EventSender, stop_rx: mpsc::Receiver<()>) -> Result<()> {
+    unsafe {
+        let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+        let device = enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?;
+        let manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, None)?;
+
+        let session_events: IAudioSessionEvents = SessionEvents { events: events.clone() }.into();
+        let notifier = ComObject::new(SessionNotifier {
+            events,
+            session_events: session_events.clone(),
+            registered: Mutex::new(Vec::new()),
+        });
+
+        // enumerating first is also what arms RegisterSessionNotification
+        let list = manager.GetSessionEnumerator()?;
+        for i in 0..list.GetCount()? {
+            let session = list.GetSession(i)?;
+            session.RegisterAudioSessionNotification(&session_events)?;
+            notifier.registered.lock().unwrap().push(session);
+        }
+
+        let notifier_iface: IAudioSessionNotification = notifier.to_interface();
+        manager.RegisterSessionNotification(&notifier_iface)?;
+
+        // block until SessionWatcher is dropped
+        let _ = stop_rx.recv();
+
+        manager.UnregisterSessionNotification(&notifier_iface)?;
+        for session in notifier.registered.lock().unwrap().drain(..) {
+            let _ = session.UnregisterAudioSessionNotification(&session_events);
+        }
+    }
+    Ok(())
+}
@@ -1,5 +1,149 @@
 // src/system.rs
 use anyhow::Result;
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use windows::Win32::Foundation::{GetLastError, ERROR_ACCESS_DENIED, WIN32_ERROR};
+use windows::Win32::UI::Input::KeyboardAndMouse::{SendInput, INPUT};
+
+// Why injected input didn't reach the desktop; carried inside the anyhow error so ws.rs can
+// turn it into an actionable reply instead of command_failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputError {
+    // the focused window belongs to a process with a higher integrity level (UIPI)
+    BlockedByUipi,
+    // UAC prompt, lock screen or Ctrl+Alt+Del screen has the input
+    SecureDesktopActive,
+    Unknown,
+}
+
+impl InputError {
+    pub fn kind(self) -> &'static str {
+        match self {
+            InputError::BlockedByUipi => "blocked_by_uipi",
+            InputError::SecureDesktopActive => "secure_desktop_active",
+            InputError::Unknown => "unknown",
+        }
+    }
+
+    // Best guess from what SendInput left in GetLastError and the desktop's state right after
+    pub(crate) fn classify(last_error: WIN32_ERROR, interactive: bool, foreground_elevated: bool) -> Self {
+        if !interactive {
+            InputError::SecureDesktopActive
+        } else if foreground_elevated || last_error == ERROR_ACCESS_DENIED {
+            InputError::BlockedByUipi
+        } else {
+            InputError::Unknown
+        }
+    }
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InputError::BlockedByUipi => "the focused app is running as administrator; run FOSS-Deck as administrator to control it",
+            InputError::SecureDesktopActive => "the PC is locked or showing a UAC prompt",
+            InputError::Unknown => "Windows did not accept the input",
+        })
+    }
+}
+
+impl std::error::Error for InputError {}
+
+// SendInput that fails with an InputError when not every event was inserted
+pub fn send_inputs(inputs: &[INPUT]) -> Result<()> {
+    let sent = unsafe { SendInput(inputs, std::mem::size_of::<INPUT>() as i32) };
+    if sent == inputs.len() as u32 {
+        return Ok(());
+    }
+    Err(input_failure(sent, inputs.len()))
+}
+
+// Call right after SendInput came up short, while GetLastError still holds its reason
+fn input_failure(sent: u32, total: usize) -> anyhow::Error {
+    let last_error = unsafe { GetLastError() };
+    let err = InputError::classify(last_error, has_interactive_session(), crate::window::foreground_blocks_input());
+    anyhow::Error::new(err).context(format!("SendInput inserted {sent}/{total} events"))
+}
+
+pub const MAX_TYPE_TEXT_CHARS: usize = 1000;
+// characters per SendInput call, and the pause between calls; bigger bursts make some apps
+// (terminals, remote desktop clients) drop characters
+const TYPE_CHUNK_CHARS: usize = 16;
+const TYPE_CHUNK_DELAY: std::time::Duration = std::time::Duration::from_millis(10);
+
+// One keystroke of type_text: a character as its UTF-16 units, or Enter for a line break
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum TypedChar {
+    Units(Vec<u16>),
+    Enter,
+}
+
+// \r\n, \n and \r each become one Enter
+pub(crate) fn typed_chars(text: &str) -> Vec<TypedChar> {
+    let mut out = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                out.push(TypedChar::Enter);
+            }
+            '\n' => out.push(TypedChar::Enter),
+            _ => out.push(TypedChar::Units(c.encode_utf16(&mut [0; 2]).to_vec())),
+        }
+    }
+    out
+}
+
+// Types `text` into the focused window; returns how many typed_chars went in, which is all of
+// them unless the error says otherwise
+pub fn type_text(text: &str) -> (usize, Result<()>) {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYBD_EVENT_FLAGS, KEYEVENTF_KEYUP, KEYEVENTF_UNICODE, VIRTUAL_KEY,
+        VK_RETURN,
+    };
+
+    fn key(vk: VIRTUAL_KEY, scan: u16, flags: KEYBD_EVENT_FLAGS) -> INPUT {
+        INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 { ki: KEYBDINPUT { wVk: vk, wScan: scan, dwFlags: flags, time: 0, dwExtraInfo: 0 } },
+        }
+    }
+
+    let chars = typed_chars(text);
+    let mut typed = 0;
+    for (i, chunk) in chars.chunks(TYPE_CHUNK_CHARS).enumerate() {
+        if i > 0 {
+            std::thread::sleep(TYPE_CHUNK_DELAY);
+        }
+        let mut inputs = Vec::new();
+        // event count after each character, to tell how far a short SendInput got
+        let mut ends = Vec::with_capacity(chunk.len());
+        for c in chunk {
+            match c {
+                TypedChar::Enter => {
+                    inputs.push(key(VK_RETURN, 0, KEYBD_EVENT_FLAGS(0)));
+                    inputs.push(key(VK_RETURN, 0, KEYEVENTF_KEYUP));
+                }
+                // surrogate pairs go down as two units, then up
+                TypedChar::Units(units) => {
+                    inputs.extend(units.iter().map(|&u| key(VIRTUAL_KEY(0), u, KEYEVENTF_UNICODE)));
+                    inputs.extend(units.iter().map(|&u| key(VIRTUAL_KEY(0), u, KEYEVENTF_UNICODE | KEYEVENTF_KEYUP)));
+                }
+            }
+            ends.push(inputs.len());
+        }
+        let sent = unsafe { SendInput(&inputs, std::mem::size_of::<INPUT>() as i32) };
+        if sent as usize != inputs.len() {
+            let err = input_failure(sent, inputs.len());
+            typed += ends.iter().take_while(|&&end| end <= sent as usize).count();
+            return (typed, Err(err));
+        }
+        typed += chunk.len();
+    }
+    (typed, Ok(()))
+}
 
 pub fn open_calculator() -> Result<()> {
     // spawn and detach
@@ -7,11 +151,163 @@ pub fn open_calculator() -> Result<()> {
     Ok(())
 }
 
+// Starts a user-configured program outside our console and process group, so it outlives the server
+pub fn launch_program(program: &Path, args: &[String], working_dir: Option<&str>) -> Result<()> {
+    use std::os::windows::process::CommandExt;
+    use std::process::{Command, Stdio};
+    use windows::Win32::System::Threading::{CREATE_NEW_PROCESS_GROUP, DETACHED_PROCESS};
+
+    let mut cmd = Command::new(program);
+    cmd.args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .creation_flags((DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP).0);
+    if let Some(dir) = working_dir {
+        cmd.current_dir(dir);
+    }
+    cmd.spawn().map_err(|e| anyhow::anyhow!("failed to start {}: {e}", program.display()))?;
+    Ok(())
+}
+
+// Opens a folder, or a file's folder with the file selected, in Explorer
+pub fn open_in_explorer(path: &Path) -> Result<()> {
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::UI::Shell::ShellExecuteW;
+    use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+    let result = unsafe {
+        if path.is_dir() {
+            ShellExecuteW(
+                HWND::default(),
+                &HSTRING::from("explore"),
+                &HSTRING::from(path),
+                PCWSTR::null(),
+                PCWSTR::null(),
+                SW_SHOWNORMAL,
+            )
+        } else {
+            ShellExecuteW(
+                HWND::default(),
+                &HSTRING::from("open"),
+                &HSTRING::from("explorer.exe"),
+                &HSTRING::from(format!("/select,\"{}\"", path.display())),
+                PCWSTR::null(),
+                SW_SHOWNORMAL,
+            )
+        }
+    };
+
+    // ShellExecute reports failure as a value <= 32
+    if result.0 as isize <= 32 {
+        anyhow::bail!("ShellExecuteW failed ({})", result.0 as isize);
+    }
+    Ok(())
+}
+
+const MAX_URL_LEN: usize = 2048;
+
+// A URL that passed parse_web_url; only these ever reach the shell
+#[derive(Debug, PartialEq, Eq)]
+pub struct WebUrl {
+    pub scheme: &'static str,
+    // domain for mailto:
+    pub host: String,
+}
+
+// http(s) URLs with a plain host and mailto: links; everything else (file:, javascript:, UNC
+// paths, app protocols) is refused before ShellExecute could interpret it
+pub(crate) fn parse_web_url(url: &str) -> Result<WebUrl, &'static str> {
+    if url.is_empty() || url.len() > MAX_URL_LEN {
+        return Err("url is empty or too long");
+    }
+    // whitespace or quotes could be read as extra arguments; backslashes as a UNC path
+    if url.chars().any(|c| c.is_control() || c.is_whitespace() || c == '"' || c == '\\') {
+        return Err("url contains characters that are not allowed");
+    }
+    let Some((scheme, rest)) = url.split_once(':') else {
+        return Err("url has no scheme");
+    };
+    let scheme = match scheme.to_ascii_lowercase().as_str() {
+        "http" => "http",
+        "https" => "https",
+        "mailto" => "mailto",
+        _ => return Err("only http, https and mailto urls can be opened"),
+    };
+
+    let host = if scheme == "mailto" {
+        let address = rest.split('?').next().unwrap_or_default();
+        // first recipient's domain
+        let first = address.split(',').next().unwrap_or_default();
+        match first.rsplit_once('@') {
+            Some((user, domain)) if !user.is_empty() => domain,
+            _ => return Err("mailto url has no address"),
+        }
+    } else {
+        let Some(rest) = rest.strip_prefix("//") else {
+            return Err("url has no host");
+        };
+        let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        // drop user:pass@ and :port
+        let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+        if let Some(v6) = host_port.strip_prefix('[') {
+            match v6.split_once(']') {
+                Some((addr, _)) if !addr.is_empty() && addr.chars().all(|c| c.is_ascii_hexdigit() || c == ':') => {
+                    return Ok(WebUrl { scheme, host: format!("[{addr}]") });
+                }
+                _ => return Err("url host is not valid"),
+            }
+        }
+        host_port.split(':').next().unwrap_or_default()
+    };
+    if host.is_empty() || !host.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '.') {
+        return Err("url host is not valid");
+    }
+    Ok(WebUrl { scheme, host: host.to_lowercase() })
+}
+
+// Hands an already validated URL to the default browser / mail client
+pub fn open_url(url: &str) -> Result<()> {
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::UI::Shell::ShellExecuteW;
+    use windows::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+    let result = unsafe {
+        ShellExecuteW(
+            HWND::default(),
+            &HSTRING::from("open"),
+            &HSTRING::from(url),
+            PCWSTR::null(),
+            PCWSTR::null(),
+            SW_SHOWNORMAL,
+        )
+    };
+    if result.0 as isize <= 32 {
+        anyhow::bail!("ShellExecuteW failed ({})", result.0 as isize);
+    }
+    Ok(())
+}
+
+// Canonical `path` if it exists under one of `roots`; None otherwise, including `..` escapes
+pub fn resolve_allowed_path(path: &str, roots: &[PathBuf]) -> Option<PathBuf> {
+    let path = Path::new(path);
+    if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return None;
+    }
+    let path = path.canonicalize().ok()?;
+    roots
+        .iter()
+        .filter_map(|r| r.canonicalize().ok())
+        .any(|root| path.starts_with(root))
+        .then_some(path)
+}
+
 pub fn take_screenshot() -> Result<()> {
     // Win + PrintScreen -> saves into Pictures\Screenshots
     use windows::Win32::UI::Input::KeyboardAndMouse::{
-        SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP,
-        VK_LWIN, VK_SNAPSHOT, VIRTUAL_KEY,
+        INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, VK_LWIN, VK_SNAPSHOT, VIRTUAL_KEY,
     };
 
     fn key(vk: VIRTUAL_KEY, up: bool) -> INPUT {
@@ -29,19 +325,150 @@ pub fn take_screenshot() -> Result<()> {
         }
     }
 
-    unsafe {
-        let inputs = [
-            key(VK_LWIN, false),     // Win down
-            key(VK_SNAPSHOT, false), // PrtScn down
-            key(VK_SNAPSHOT, true),  // PrtScn up
-            key(VK_LWIN, true),      // Win up
-        ];
+    let inputs = [
+        key(VK_LWIN, false),     // Win down
+        key(VK_SNAPSHOT, false), // PrtScn down
+        key(VK_SNAPSHOT, true),  // PrtScn up
+        key(VK_LWIN, true),      // Win up
+    ];
+    send_inputs(&inputs)
+}
+
+// Single raw key event; callers must pair every down with an up themselves.
+pub fn send_key_code(vk: u16, up: bool) -> Result<()> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{
+        INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYBD_EVENT_FLAGS, KEYEVENTF_KEYUP, VIRTUAL_KEY,
+    };
+
+    let input = INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: VIRTUAL_KEY(vk),
+                wScan: 0,
+                dwFlags: if up { KEYEVENTF_KEYUP } else { KEYBD_EVENT_FLAGS(0) },
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    };
 
-        let sent = SendInput(&inputs, std::mem::size_of::<INPUT>() as i32);
-        if sent != inputs.len() as u32 {
-            anyhow::bail!("SendInput failed (sent {sent}/{})", inputs.len());
+    send_inputs(&[input]).map_err(|e| e.context(format!("vk {vk:#04x}")))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerAction {
+    Shutdown,
+    Restart,
+    Sleep,
+    Hibernate,
+}
+
+impl PowerAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PowerAction::Shutdown => "shutdown",
+            PowerAction::Restart => "restart",
+            PowerAction::Sleep => "sleep",
+            PowerAction::Hibernate => "hibernate",
         }
     }
+}
+
+// Turns on SeShutdownPrivilege for this process; ExitWindowsEx and SetSuspendState fail without it
+fn enable_shutdown_privilege() -> Result<()> {
+    use windows::Win32::Foundation::{CloseHandle, ERROR_NOT_ALL_ASSIGNED, HANDLE, LUID};
+    use windows::Win32::Security::{
+        AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES, SE_PRIVILEGE_ENABLED, SE_SHUTDOWN_NAME,
+        TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+    };
+    use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
 
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY, &mut token)?;
+        let mut luid = LUID::default();
+        let adjusted = LookupPrivilegeValueW(None, SE_SHUTDOWN_NAME, &mut luid).and_then(|()| {
+            let privileges = TOKEN_PRIVILEGES {
+                PrivilegeCount: 1,
+                Privileges: [LUID_AND_ATTRIBUTES { Luid: luid, Attributes: SE_PRIVILEGE_ENABLED }],
+            };
+            AdjustTokenPrivileges(token, false, Some(&privileges), 0, None, None)
+        });
+        // AdjustTokenPrivileges succeeds even when the token doesn't hold the privilege
+        let missing = GetLastError() == ERROR_NOT_ALL_ASSIGNED;
+        let _ = CloseHandle(token);
+        adjusted?;
+        if missing {
+            anyhow::bail!("this account may not shut down the PC");
+        }
+    }
     Ok(())
 }
+
+// Shutdown/restart close the session (forcibly with `force`, else only hung apps are killed);
+// sleep/hibernate return once the PC has woken up again
+pub fn power_action(action: PowerAction, force: bool) -> Result<()> {
+    use windows::Win32::Foundation::BOOLEAN;
+    use windows::Win32::System::Power::SetSuspendState;
+    use windows::Win32::System::Shutdown::{
+        ExitWindowsEx, EWX_FORCE, EWX_FORCEIFHUNG, EWX_POWEROFF, EWX_REBOOT, SHTDN_REASON_FLAG_PLANNED,
+        SHTDN_REASON_MAJOR_OTHER,
+    };
+
+    enable_shutdown_privilege()?;
+    let reason = SHTDN_REASON_MAJOR_OTHER | SHTDN_REASON_FLAG_PLANNED;
+    let kill = if force { EWX_FORCE } else { EWX_FORCEIFHUNG };
+    unsafe {
+        match action {
+            PowerAction::Shutdown => ExitWindowsEx(EWX_POWEROFF | kill, reason)?,
+            PowerAction::Restart => ExitWindowsEx(EWX_REBOOT | kill, reason)?,
+            PowerAction::Sleep | PowerAction::Hibernate => {
+                let hibernate = BOOLEAN((action == PowerAction::Hibernate) as u8);
+                if SetSuspendState(hibernate, BOOLEAN(force as u8), BOOLEAN(0)).0 == 0 {
+                    anyhow::bail!("SetSuspendState failed ({})", GetLastError().0);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// False in session 0 (running as a service) or while the lock/secure desktop has input,
+// i.e. whenever SendInput/launching apps can't reach the user.
+pub fn has_interactive_session() -> bool {
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::System::StationsAndDesktops::{
+        CloseDesktop, GetProcessWindowStation, GetUserObjectInformationW, OpenInputDesktop, DESKTOP_CONTROL_FLAGS,
+        DESKTOP_SWITCHDESKTOP, UOI_FLAGS, USEROBJECTFLAGS,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::WSF_VISIBLE;
+
+    unsafe {
+        let Ok(station) = GetProcessWindowStation() else {
+            return false;
+        };
+        let mut flags = USEROBJECTFLAGS::default();
+        let visible = GetUserObjectInformationW(
+            HANDLE(station.0),
+            UOI_FLAGS,
+            Some(&mut flags as *mut _ as *mut std::ffi::c_void),
+            std::mem::size_of::<USEROBJECTFLAGS>() as u32,
+            None,
+        )
+        .is_ok()
+            && flags.dwFlags & WSF_VISIBLE as u32 != 0;
+        if !visible {
+            return false;
+        }
+
+        match OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_SWITCHDESKTOP) {
+            Ok(desktop) => {
+                let _ = CloseDesktop(desktop);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
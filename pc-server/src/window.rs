@@ -0,0 +1,97 @@
+// src/window.rs
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use windows::core::PWSTR;
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
+use windows::Win32::Security::{
+    GetSidSubAuthority, GetSidSubAuthorityCount, GetTokenInformation, TokenIntegrityLevel, TOKEN_MANDATORY_LABEL, TOKEN_QUERY,
+};
+use windows::Win32::System::Threading::{
+    GetCurrentProcess, OpenProcess, OpenProcessToken, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+    PROCESS_QUERY_LIMITED_INFORMATION,
+};
+use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+
+pub struct ForegroundApp {
+    pub pid: u32,
+    // executable name without extension, e.g. "spotify"
+    pub name: String,
+}
+
+pub fn foreground_app() -> Result<ForegroundApp> {
+    unsafe {
+        let hwnd = GetForegroundWindow();
+        if hwnd.0.is_null() {
+            return Err(anyhow!("no foreground window"));
+        }
+
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+        if pid == 0 {
+            return Err(anyhow!("could not resolve foreground process"));
+        }
+
+        let name = process_name(pid).unwrap_or_else(|| format!("pid {pid}"));
+        Ok(ForegroundApp { pid, name })
+    }
+}
+
+pub fn process_name(pid: u32) -> Option<String> {
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let mut buf = [0u16; 260];
+        let mut len = buf.len() as u32;
+        let res = QueryFullProcessImageNameW(handle, PROCESS_NAME_WIN32, PWSTR(buf.as_mut_ptr()), &mut len);
+        let _ = CloseHandle(handle);
+        res.ok()?;
+
+        let path = String::from_utf16_lossy(&buf[..len as usize]);
+        Path::new(&path).file_stem().map(|s| s.to_string_lossy().into_owned())
+    }
+}
+
+// Mandatory integrity RID of a process token, e.g. 0x2000 medium or 0x3000 high (elevated)
+fn integrity_level(process: HANDLE) -> Option<u32> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(process, TOKEN_QUERY, &mut token).ok()?;
+
+        let mut len = 0u32;
+        let _ = GetTokenInformation(token, TokenIntegrityLevel, None, 0, &mut len);
+        // u64s keep the SID pointer inside the label aligned
+        let mut buf = vec![0u64; (len as usize).div_ceil(8)];
+        let res = GetTokenInformation(token, TokenIntegrityLevel, Some(buf.as_mut_ptr().cast()), len, &mut len);
+        let _ = CloseHandle(token);
+        res.ok()?;
+
+        let label = &*(buf.as_ptr() as *const TOKEN_MANDATORY_LABEL);
+        let count = *GetSidSubAuthorityCount(label.Label.Sid);
+        let last = u32::from(count).checked_sub(1)?;
+        Some(*GetSidSubAuthority(label.Label.Sid, last))
+    }
+}
+
+// Whether UIPI will silently drop our SendInput events: the foreground window belongs to a
+// process running at a higher integrity level (typically an elevated app) than this server.
+pub fn foreground_blocks_input() -> bool {
+    const HIGH_RID: u32 = 0x3000;
+
+    let Ok(fg) = foreground_app() else {
+        return false;
+    };
+    unsafe {
+        let Some(ours) = integrity_level(GetCurrentProcess()) else {
+            return false;
+        };
+        let Ok(handle) = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, fg.pid) else {
+            return false;
+        };
+        let theirs = integrity_level(handle);
+        let _ = CloseHandle(handle);
+        match theirs {
+            Some(level) => level > ours,
+            // an elevated process's token isn't readable from a non-elevated one
+            None => ours < HIGH_RID,
+        }
+    }
+}